@@ -0,0 +1,150 @@
+'use strict';
+
+// Test the Prometheus metrics endpoint of mayastor.
+
+const assert = require('chai').assert;
+const async = require('async');
+const http = require('http');
+const common = require('./test_common');
+
+const METRICS_PORT = 19502;
+const METRICS_ENDPOINT = common.getMyIp() + ':' + METRICS_PORT;
+const POOL = 'metrics-pool';
+const UUID = 'dbe4d7eb-118a-4d15-b789-a18d9af6ff21';
+
+// Fetch the metrics and return the body of the response.
+function scrape (path, done) {
+  http
+    .get(`http://${METRICS_ENDPOINT}${path}`, (res) => {
+      let body = '';
+      res.on('data', (chunk) => {
+        body += chunk;
+      });
+      res.on('end', () => done(null, res.statusCode, body));
+    })
+    .on('error', done);
+}
+
+// Return value of the sample with given name and labels or undefined.
+function sampleValue (body, name, labels) {
+  const line = body
+    .split('\n')
+    .filter((l) => !l.startsWith('#'))
+    .find((l) => {
+      if (!l.startsWith(name + '{')) return false;
+      return Object.keys(labels).every((k) =>
+        l.includes(`${k}="${labels[k]}"`)
+      );
+    });
+  if (line) {
+    return parseFloat(line.split(' ').pop());
+  }
+}
+
+describe('metrics', function () {
+  var client;
+
+  this.timeout(10000);
+
+  before((done) => {
+    client = common.createGrpcClient();
+    if (!client) {
+      return done(new Error('Failed to initialize grpc client'));
+    }
+    common.startMayastor(null, [
+      '-g', common.grpcEndpoint,
+      '--metrics-endpoint', METRICS_ENDPOINT
+    ]);
+    async.series(
+      [
+        (next) => {
+          common.waitFor((pingDone) => {
+            client.listPools({}, pingDone);
+          }, next);
+        },
+        (next) => {
+          client.createPool(
+            { name: POOL, disks: ['malloc:///malloc0?size_mb=64'] },
+            next
+          );
+        },
+        (next) => {
+          client.createReplica(
+            { uuid: UUID, pool: POOL, size: 8 * 1024 * 1024, thin: false },
+            next
+          );
+        }
+      ],
+      done
+    );
+  });
+
+  after((done) => {
+    async.series(
+      [
+        (next) => client.destroyPool({ name: POOL }, () => next()),
+        (next) => {
+          client.close();
+          common.stopAll(next);
+        }
+      ],
+      done
+    );
+  });
+
+  it('should report the pool capacity and used space', (done) => {
+    scrape('/metrics', (err, status, body) => {
+      if (err) return done(err);
+      assert.strictEqual(status, 200);
+      const capacity = sampleValue(body, 'mayastor_pool_capacity_bytes', {
+        name: POOL
+      });
+      const used = sampleValue(body, 'mayastor_pool_used_bytes', {
+        name: POOL
+      });
+      assert.isAbove(capacity, 0);
+      assert.isAtLeast(used, 8 * 1024 * 1024);
+      assert.isAtMost(used, capacity);
+      done();
+    });
+  });
+
+  it('should report the IO statistics of the replica', (done) => {
+    scrape('/metrics', (err, status, body) => {
+      if (err) return done(err);
+      assert.strictEqual(
+        sampleValue(body, 'mayastor_replica_size_bytes', { uuid: UUID }),
+        8 * 1024 * 1024
+      );
+      assert.strictEqual(
+        sampleValue(body, 'mayastor_replica_reads_total', {
+          uuid: UUID,
+          pool: POOL
+        }),
+        0
+      );
+      assert.strictEqual(
+        sampleValue(body, 'mayastor_replica_latency_seconds_bucket', {
+          uuid: UUID,
+          le: '+Inf'
+        }),
+        0
+      );
+      assert.strictEqual(
+        sampleValue(body, 'mayastor_replica_latency_seconds_count', {
+          uuid: UUID
+        }),
+        0
+      );
+      done();
+    });
+  });
+
+  it('should not serve anything else', (done) => {
+    scrape('/', (err, status) => {
+      if (err) return done(err);
+      assert.strictEqual(status, 404);
+      done();
+    });
+  });
+});
@@ -566,6 +566,22 @@ describe('nexus', function () {
       });
     });
 
+    it('should report the written data in the stats of the nexus', (done) => {
+      client.statNexus({ uuid: UUID }, (err, res) => {
+        if (err) return done(err);
+        assert.equal(res.uuid, UUID);
+        assert.isAtLeast(parseInt(res.stats.num_write_ops), 1);
+        assert.isAtLeast(parseInt(res.stats.bytes_written), 512);
+        assert.lengthOf(res.children, 3 + doIscsiReplica + doUring());
+        assert.isAtLeast(parseInt(res.children[0].stats.num_write_ops), 1);
+        res.children.forEach((child) => {
+          assert.equal(child.read_errors, 0);
+          assert.equal(child.write_errors, 0);
+        });
+        done();
+      });
+    });
+
     it('should un-publish the NBD nexus device', (done) => {
       client.unpublishNexus({ uuid: UUID }, (err, res) => {
         if (err) done(err);
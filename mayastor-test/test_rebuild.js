@@ -393,6 +393,104 @@ describe('rebuild tests', function () {
     });
   });
 
+  describe('limiting rebuild', function () {
+    beforeEach(async () => {
+      await client.addChildNexus().sendMessage(addChildArgs);
+      await client.startRebuild().sendMessage({
+        uuid: rebuildArgs.uuid,
+        uri: rebuildArgs.uri,
+        limits: { bandwidthMbps: 1, concurrency: 1 }
+      });
+    });
+
+    afterEach(async () => {
+      await client.setRebuildLimits().sendMessage({
+        limits: { bandwidthMbps: 0 }
+      });
+      await client.stopRebuild().sendMessage(rebuildArgs);
+      // TODO: Check for rebuild stop rather than sleeping
+      await sleep(250); // Give time for the rebuild to stop
+      await client.removeChildNexus().sendMessage(rebuildArgs);
+    });
+
+    it('check rebuild is still running', async () => {
+      // 100MiB at 1MiB/s take way longer than that
+      await sleep(500);
+      await checkRebuildState('running');
+    });
+
+    it('change limits of the rebuild', async () => {
+      await client.setRebuildLimits().sendMessage({
+        uuid: rebuildArgs.uuid,
+        uri: rebuildArgs.uri,
+        limits: { bandwidthMbps: 2, concurrency: 4 }
+      });
+      await checkRebuildState('running');
+    });
+
+    it('fail to set invalid concurrency', async () => {
+      try {
+        await client.setRebuildLimits().sendMessage({
+          uuid: rebuildArgs.uuid,
+          uri: rebuildArgs.uri,
+          limits: { bandwidthMbps: 2, concurrency: 1000 }
+        });
+      } catch (err) {
+        assert.equal(err.code, grpc.status.INVALID_ARGUMENT);
+        return;
+      }
+      throw new Error('Expected invalid concurrency to fail');
+    });
+
+    it('limit all rebuilds together', async () => {
+      await client.setRebuildLimits().sendMessage({
+        limits: { bandwidthMbps: 1 }
+      });
+      await checkRebuildState('running');
+    });
+  });
+
+  describe('rebuild statistics', function () {
+    beforeEach(async () => {
+      await client.addChildNexus().sendMessage(addChildArgs);
+      await client.startRebuild().sendMessage({
+        uuid: rebuildArgs.uuid,
+        uri: rebuildArgs.uri,
+        limits: { bandwidthMbps: 1, concurrency: 1 }
+      });
+    });
+
+    afterEach(async () => {
+      await client.removeChildNexus().sendMessage(rebuildArgs);
+    });
+
+    it('get statistics of the running rebuild', async () => {
+      await sleep(500);
+      const res = await client.getRebuildStats().sendMessage(rebuildArgs);
+      assert.equal(res.state, 'running');
+      assert.isAbove(res.stats.blocksTotal, res.stats.blocksRecovered);
+      assert.isAbove(res.stats.blocksTransferred, 0);
+      assert.isAbove(res.stats.startTime, 0);
+      assert.equal(res.stats.tasksTotal, 1);
+
+      await client.stopRebuild().sendMessage(rebuildArgs);
+      // TODO: Check for rebuild stop rather than sleeping
+      await sleep(250); // Give time for the rebuild to stop
+    });
+
+    it('find the stopped rebuild in the history', async () => {
+      await client.stopRebuild().sendMessage(rebuildArgs);
+      // TODO: Check for rebuild stop rather than sleeping
+      await sleep(250); // Give time for the rebuild to stop
+
+      const res = await client.getRebuildHistory().sendMessage(rebuildArgs);
+      assert.isAtLeast(res.rebuilds.length, 1);
+      assert.equal(res.rebuilds[0].uri, rebuildArgs.uri);
+      assert.equal(res.rebuilds[0].state, 'stopped');
+      assert.equal(res.rebuilds[0].error, '');
+    });
+  });
+
   describe('set child online', function () {
     beforeEach(async () => {
       await client.addChildNexus().sendMessage(addChildArgs);
@@ -7,16 +7,20 @@ pub use nexus::{
         Nexus,
         NexusState,
         NexusStatus,
+        ReadPolicy,
         VerboseError,
+        WritePolicy,
     },
     nexus_child::ChildStatus,
     nexus_child_error_store::{ActionType, NexusErrStore, QueryType},
+    nexus_dirty_log::DirtyLog,
     nexus_label::{GPTHeader, GptEntry},
     nexus_metadata_content::{
         NexusConfig,
-        NexusConfigVersion1,
-        NexusConfigVersion2,
-        NexusConfigVersion3,
+        NexusDirtyLog,
+        NexusShareProtocol,
+        NexusTopology,
+        NexusTopologyChild,
     },
 };
 
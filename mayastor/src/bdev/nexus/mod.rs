@@ -15,10 +15,12 @@ macro_rules! c_str {
 pub mod nexus_bdev;
 pub mod nexus_bdev_children;
 pub mod nexus_bdev_rebuild;
+pub mod nexus_bdev_scrub;
 mod nexus_channel;
 pub(crate) mod nexus_child;
 pub(crate) mod nexus_child_error_store;
 mod nexus_config;
+pub mod nexus_dirty_log;
 pub mod nexus_fn_table;
 pub mod nexus_io;
 pub mod nexus_iscsi;
@@ -28,7 +30,10 @@ pub mod nexus_metadata_content;
 pub mod nexus_module;
 pub mod nexus_nbd;
 pub mod nexus_nvmf;
+pub mod nexus_persistence;
+pub mod nexus_rebuild_checkpoint;
 pub mod nexus_share;
+pub mod nexus_snapshot;
 
 /// public function which simply calls register module
 pub fn register_module() {
@@ -5,6 +5,7 @@
 //! application needs synchronous mirroring may be required.
 
 use std::{
+    collections::VecDeque,
     fmt,
     fmt::{Display, Formatter},
     os::raw::c_void,
@@ -12,16 +13,19 @@ use std::{
 
 use futures::channel::oneshot;
 use nix::errno::Errno;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
 use tonic::{Code, Status};
 
 use spdk_sys::{
     spdk_bdev,
+    spdk_bdev_abort,
     spdk_bdev_desc,
     spdk_bdev_flush_blocks,
     spdk_bdev_io,
     spdk_bdev_io_get_buf,
+    spdk_bdev_io_get_io_channel,
+    spdk_bdev_notify_blockcnt_change,
     spdk_bdev_readv_blocks,
     spdk_bdev_register,
     spdk_bdev_reset,
@@ -29,6 +33,7 @@ use spdk_sys::{
     spdk_bdev_unregister,
     spdk_bdev_write_zeroes_blocks,
     spdk_bdev_writev_blocks,
+    spdk_get_ticks,
     spdk_io_channel,
     spdk_io_device_register,
     spdk_io_device_unregister,
@@ -39,19 +44,24 @@ use crate::{
         nexus,
         nexus::{
             instances,
+            nexus_bdev_rebuild::RebuildRecord,
             nexus_channel::{DREvent, NexusChannel, NexusChannelInner},
             nexus_child::{ChildError, ChildState, ChildStatus, NexusChild},
-            nexus_io::{io_status, Bio},
+            nexus_io::{io_status, io_type, Bio},
             nexus_iscsi::{NexusIscsiError, NexusIscsiTarget},
             nexus_label::LabelError,
+            nexus_metadata_content::{NexusShareProtocol, NexusTopology},
             nexus_nbd::{NbdDisk, NbdError},
             nexus_nvmf::{NexusNvmfError, NexusNvmfTarget},
         },
     },
     core::{Bdev, DmaError},
+    events::{self, EventKind},
     ffihelper::errno_result_from_i32,
+    metrics,
     nexus_uri::{bdev_destroy, NexusBdevError},
     rebuild::RebuildError,
+    scrub::ScrubError,
 };
 
 /// Obtain the full error chain
@@ -116,6 +126,8 @@ pub enum Error {
     WriteLabel { source: LabelError, name: String },
     #[snafu(display("Failed to read label from a child of nexus {}", name))]
     ReadLabel { source: ChildError, name: String },
+    #[snafu(display("Failed to probe label of nexus {}", name))]
+    ProbeLabel { source: LabelError, name: String },
     #[snafu(display("Labels of the nexus {} are not the same", name))]
     CheckLabels { name: String },
     #[snafu(display("Failed to write protective MBR of nexus {}", name))]
@@ -141,6 +153,8 @@ pub enum Error {
     ChildMissing { child: String, name: String },
     #[snafu(display("Child {} of nexus {} has no error store", child, name))]
     ChildMissingErrStore { child: String, name: String },
+    #[snafu(display("Error store of nexus {} is disabled", name))]
+    ErrStoreDisabled { name: String },
     #[snafu(display("Failed to open child {} of nexus {}", child, name))]
     OpenChild {
         source: ChildError,
@@ -211,10 +225,47 @@ pub enum Error {
     },
     #[snafu(display("Invalid ShareProtocol value {}", sp_value))]
     InvalidShareProtocol { sp_value: i32 },
+    #[snafu(display("Invalid ReadPolicy value {}", rp_value))]
+    InvalidReadPolicy { rp_value: i32 },
+    #[snafu(display("Invalid WritePolicy value {}", wp_value))]
+    InvalidWritePolicy { wp_value: i32 },
     #[snafu(display("Failed to create nexus {}", name))]
     NexusCreate { name: String },
+    #[snafu(display(
+        "Nexus {} cannot shrink from {} to {} bytes",
+        name,
+        current,
+        requested
+    ))]
+    InvalidResize {
+        name: String,
+        current: u64,
+        requested: u64,
+    },
+    #[snafu(display("Nexus {} must be online to be resized", name))]
+    ResizeNotOnline { name: String },
+    #[snafu(display(
+        "Children of nexus {} are too small for {} bytes",
+        name,
+        size
+    ))]
+    ChildrenTooSmall { name: String, size: u64 },
+    #[snafu(display("Failed to resize nexus {}", name))]
+    ResizeNexus { source: Errno, name: String },
+    #[snafu(display("Nexus {} must be online to be snapshotted", name))]
+    SnapshotNotOnline { name: String },
+    #[snafu(display("Failed to quiesce IO of nexus {}", name))]
+    QuiesceNexus { source: Errno, name: String },
+    #[snafu(display("Failed to snapshot child {} of nexus {}", child, name))]
+    CreateSnapshot {
+        source: crate::snapshot::Error,
+        child: String,
+        name: String,
+    },
     #[snafu(display("Failed to destroy nexus {}", name))]
     NexusDestroy { name: String },
+    #[snafu(display("Failed to get IO statistics of nexus {}", name))]
+    NexusStats { source: Errno, name: String },
     #[snafu(display(
         "Child {} of nexus {} is not degraded but {}",
         child,
@@ -226,6 +277,14 @@ pub enum Error {
         name: String,
         state: String,
     },
+    #[snafu(display("Nexus {} must be online to be scrubbed", name))]
+    ScrubNotOnline { name: String },
+    #[snafu(display("Failed to create scrub job of nexus {}", name))]
+    CreateScrubError { source: ScrubError, name: String },
+    #[snafu(display("Scrub job not found for nexus {}", name))]
+    ScrubJobNotFound { source: ScrubError, name: String },
+    #[snafu(display("Failed to execute scrub operation on nexus {}", name))]
+    ScrubOperationError { source: ScrubError, name: String },
 }
 
 impl From<Error> for tonic::Status {
@@ -264,6 +323,67 @@ impl From<Error> for tonic::Status {
             Error::ChildNotFound {
                 ..
             } => Status::not_found(e.to_string()),
+            Error::ChildMissing {
+                ..
+            } => Status::not_found(e.to_string()),
+            Error::ChildMissingErrStore {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::ErrStoreDisabled {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::InvalidReadPolicy {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::InvalidWritePolicy {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::InvalidResize {
+                ..
+            } => Status::invalid_argument(e.to_string()),
+            Error::ResizeNotOnline {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::ChildrenTooSmall {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::SnapshotNotOnline {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::CreateRebuildError {
+                source:
+                    RebuildError::InvalidConcurrency {
+                        ..
+                    },
+                ..
+            } => Status::invalid_argument(e.verbose()),
+            Error::RebuildOperationError {
+                source:
+                    RebuildError::InvalidConcurrency {
+                        ..
+                    },
+                ..
+            } => Status::invalid_argument(e.verbose()),
+            Error::ScrubNotOnline {
+                ..
+            } => Status::failed_precondition(e.to_string()),
+            Error::CreateScrubError {
+                source:
+                    ScrubError::InvalidAuthority {
+                        ..
+                    },
+                ..
+            } => Status::invalid_argument(e.verbose()),
+            Error::CreateScrubError {
+                source:
+                    ScrubError::JobAlreadyExists {
+                        ..
+                    },
+                ..
+            } => Status::already_exists(e.verbose()),
+            Error::ScrubJobNotFound {
+                ..
+            } => Status::not_found(e.to_string()),
             e => Status::new(Code::Internal, e.to_string()),
         }
     }
@@ -313,6 +433,19 @@ pub struct Nexus {
     pub(crate) share_handle: Option<String>,
     /// enum containing the protocol-specific target used to publish the nexus
     pub nexus_target: Option<NexusTarget>,
+    /// policy used to select the child to read from
+    pub(crate) read_policy: ReadPolicy,
+    /// policy used to decide when a write has succeeded
+    pub(crate) write_policy: WritePolicy,
+    /// status of the nexus last published on the event bus
+    published_status: Option<NexusStatus>,
+    /// the rebuilds which have come to an end, most recent first
+    pub(crate) rebuild_history: VecDeque<RebuildRecord>,
+    /// the topology last written to the MayaMeta partition of the children
+    pub(crate) topology: Option<NexusTopology>,
+    /// the protocol the nexus is meant to be shared with, which outlives the
+    /// share torn down when the nexus is destroyed
+    pub(crate) share_protocol: NexusShareProtocol,
 }
 
 unsafe impl core::marker::Sync for Nexus {}
@@ -328,6 +461,68 @@ pub enum NexusStatus {
     Online,
 }
 
+/// Policy used to select the child which services a read, the statistics
+/// these are based on are kept per core
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum ReadPolicy {
+    /// rotate between the children
+    RoundRobin,
+    /// the child with the fewest reads in flight
+    LeastOutstanding,
+    /// the child with the lowest observed read latency
+    LowestLatency,
+    /// rotate between the local children, and only fall back to the remote
+    /// (nvmf, iscsi) children when there are no local ones
+    PreferLocal,
+}
+
+impl Default for ReadPolicy {
+    fn default() -> Self {
+        ReadPolicy::RoundRobin
+    }
+}
+
+impl ToString for ReadPolicy {
+    fn to_string(&self) -> String {
+        match *self {
+            ReadPolicy::RoundRobin => "round_robin",
+            ReadPolicy::LeastOutstanding => "least_outstanding",
+            ReadPolicy::LowestLatency => "lowest_latency",
+            ReadPolicy::PreferLocal => "prefer_local",
+        }
+        .parse()
+        .unwrap()
+    }
+}
+
+/// Policy used to decide when a write, unmap or write zeroes IO which is
+/// sent to all children has succeeded
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub enum WritePolicy {
+    /// every child must complete the IO successfully
+    All,
+    /// a majority of the children must complete the IO successfully, the
+    /// children which failed it are faulted and scheduled for a rebuild
+    Quorum,
+}
+
+impl Default for WritePolicy {
+    fn default() -> Self {
+        WritePolicy::All
+    }
+}
+
+impl ToString for WritePolicy {
+    fn to_string(&self) -> String {
+        match *self {
+            WritePolicy::All => "all",
+            WritePolicy::Quorum => "quorum",
+        }
+        .parse()
+        .unwrap()
+    }
+}
+
 #[derive(Debug, Serialize, Clone, Copy, PartialEq, PartialOrd)]
 pub enum NexusState {
     /// nexus created but no children attached
@@ -402,6 +597,12 @@ impl Nexus {
             share_handle: None,
             size,
             nexus_target: None,
+            read_policy: ReadPolicy::default(),
+            write_policy: WritePolicy::default(),
+            published_status: None,
+            rebuild_history: VecDeque::new(),
+            topology: None,
+            share_protocol: NexusShareProtocol::None,
         });
 
         n.bdev.set_uuid(match uuid {
@@ -429,6 +630,42 @@ impl Nexus {
         self.state = state;
         state
     }
+    /// set the policy used to select the child to read from
+    pub fn set_read_policy(&mut self, policy: ReadPolicy) {
+        if self.read_policy != policy {
+            info!(
+                "{}: read policy changed from {} to {}",
+                self.name,
+                self.read_policy.to_string(),
+                policy.to_string()
+            );
+            self.read_policy = policy;
+        }
+    }
+
+    /// returns the policy used to select the child to read from
+    pub fn read_policy(&self) -> ReadPolicy {
+        self.read_policy
+    }
+
+    /// set the policy used to decide when a write has succeeded
+    pub fn set_write_policy(&mut self, policy: WritePolicy) {
+        if self.write_policy != policy {
+            info!(
+                "{}: write policy changed from {} to {}",
+                self.name,
+                self.write_policy.to_string(),
+                policy.to_string()
+            );
+            self.write_policy = policy;
+        }
+    }
+
+    /// returns the policy used to decide when a write has succeeded
+    pub fn write_policy(&self) -> WritePolicy {
+        self.write_policy
+    }
+
     /// returns the size in bytes of the nexus instance
     pub fn size(&self) -> u64 {
         u64::from(self.bdev.block_len()) * self.bdev.num_blocks()
@@ -453,6 +690,26 @@ impl Nexus {
             "{}: Dynamic reconfiguration event: {:?} completed {:?}",
             self.name, event, result
         );
+
+        self.publish_status(Some(&event));
+        self.persist_topology().await;
+    }
+
+    /// Publish the status of the nexus and of its children on the event bus
+    /// for those which have changed.
+    pub(crate) fn publish_status(&mut self, reason: Option<&DREvent>) {
+        for child in self.children.iter_mut() {
+            child.publish_status(reason);
+        }
+
+        let status = self.status();
+        if self.published_status != Some(status) {
+            self.published_status = Some(status);
+            events::publish(EventKind::NexusStatus {
+                nexus: self.name.clone(),
+                status,
+            });
+        }
     }
 
     /// Opens the Nexus instance for IO
@@ -461,7 +718,14 @@ impl Nexus {
 
         self.try_open_children()?;
         self.sync_labels().await?;
-        self.register()
+        // the children which are behind must not serve any IO, which starts
+        // as soon as the bdev is registered
+        self.mark_checkpointed_children().await;
+        self.mark_stale_children().await;
+        self.register()?;
+
+        self.publish_status(None);
+        Ok(())
     }
 
     pub async fn sync_labels(&mut self) -> Result<(), Error> {
@@ -472,6 +736,12 @@ impl Nexus {
         // Now register the bdev but update its size first
         // to ensure we adhere to the partitions.
         self.data_ent_offset = label.offset();
+
+        // a nexus created without a size spans the data partition of the
+        // children, this is how the nexus which mirrors a pool is created
+        if self.size == 0 {
+            self.size = label.get_block_count() * self.bdev.block_len() as u64;
+        }
         let size_blocks = self.size / self.bdev.block_len() as u64;
 
         self.bdev.set_block_count(std::cmp::min(
@@ -481,6 +751,81 @@ impl Nexus {
             label.get_block_count(),
         ));
 
+        self.persist_topology().await;
+        Ok(())
+    }
+
+    /// Grow the nexus to `size` bytes while it is online. When the data
+    /// partition is too small for the new size, because the children have
+    /// been grown, the label is rewritten to span the children first.
+    /// The frontends are notified of the new capacity through the bdev layer.
+    /// The size is rounded down to a multiple of the block length.
+    pub async fn resize(&mut self, size: u64) -> Result<(), Error> {
+        let block_len = self.bdev.block_len() as u64;
+        let size = size - size % block_len;
+        let current = self.size();
+        if size < current {
+            return Err(Error::InvalidResize {
+                name: self.name.clone(),
+                current,
+                requested: size,
+            });
+        }
+
+        // a degraded child would miss the new label
+        if self.status() != NexusStatus::Online {
+            return Err(Error::ResizeNotOnline {
+                name: self.name.clone(),
+            });
+        }
+
+        let size_blocks = size / block_len;
+
+        let child = self
+            .children
+            .iter()
+            .find(|c| c.state == ChildState::Open)
+            .ok_or_else(|| Error::ResizeNotOnline {
+                name: self.name.clone(),
+            })?;
+        let mut label = child.probe_label().await.context(ProbeLabel {
+            name: self.name.clone(),
+        })?;
+
+        if label.get_block_count() < size_blocks {
+            let grown = self.grow_label(&label);
+            if grown.offset() != label.offset()
+                || grown.get_block_count() < size_blocks
+            {
+                return Err(Error::ChildrenTooSmall {
+                    name: self.name.clone(),
+                    size,
+                });
+            }
+
+            self.write_all_labels(&grown).await.context(WriteLabel {
+                name: self.name.clone(),
+            })?;
+            info!("{}: label grown:\n{}", self.name, grown);
+            label = grown;
+        }
+
+        let num_blocks = std::cmp::min(size_blocks, label.get_block_count());
+        let rc = unsafe {
+            spdk_bdev_notify_blockcnt_change(self.bdev.as_ptr(), num_blocks)
+        };
+        errno_result_from_i32((), rc).context(ResizeNexus {
+            name: self.name.clone(),
+        })?;
+        self.size = num_blocks * block_len;
+        self.persist_topology().await;
+
+        info!(
+            "{}: resized from {} to {} bytes",
+            self.name,
+            current,
+            self.size()
+        );
         Ok(())
     }
 
@@ -525,12 +870,15 @@ impl Nexus {
             }
         }
 
-        let _ = self.unshare().await;
+        // the protocol stays in the topology for the nexus to be shared
+        // with again once it is recovered
+        let _ = self.unshare_target().await;
         assert_eq!(self.share_handle, None);
 
-        for child in self.children.iter() {
-            self.stop_rebuild(&child.name).await.ok();
-        }
+        self.suspend_rebuilds().await;
+        self.remove_scrub().await;
+        // no writes can be missed anymore
+        self.seal_dirty_logs().await;
 
         for child in self.children.iter_mut() {
             let _ = child.close();
@@ -629,9 +977,30 @@ impl Nexus {
         parent_io: *mut c_void,
     ) {
         let mut pio = Bio(parent_io as *mut _);
+        let is_read = Bio::io_type(pio.0) == Some(io_type::READ);
+        let is_write = matches!(
+            Bio::io_type(pio.0),
+            Some(io_type::WRITE)
+                | Some(io_type::UNMAP)
+                | Some(io_type::WRITE_ZEROES)
+        );
+
+        // update the read statistics used by the read policy
+        if is_read {
+            let ctx = pio.ctx_as_mut_ref();
+            let (child, submitted) = (ctx.read_child, ctx.submitted);
+            NexusChannel::inner_from_channel(spdk_bdev_io_get_io_channel(
+                pio.0,
+            ))
+            .read_completed(child, (*child_io).bdev, submitted);
+        }
 
         // if any child IO has failed record this within the io context
+        let mut failed_io = child_io as *const spdk_bdev_io;
         if !success {
+            let timed_out =
+                pio.ctx_as_mut_ref().timed_out == (*child_io).bdev as *const _;
+
             trace!(
                 "child IO {:?} ({}) of parent {:?} failed",
                 Bio(child_io),
@@ -639,13 +1008,206 @@ impl Nexus {
                 pio
             );
 
-            pio.ctx_as_mut_ref().status = io_status::FAILED;
+            // a failed read is transparently retried on another child, the
+            // error is still recorded against the child it failed on
+            if is_read {
+                let nexus = pio.nexus_as_ref();
+                nexus.error_record_add(
+                    (*child_io).bdev,
+                    io_type::READ,
+                    io_status::FAILED,
+                    pio.offset(),
+                    pio.num_blocks(),
+                );
+                if nexus.readv_retry(pio.0) {
+                    Bio::io_free(child_io);
+                    return;
+                }
+                // already recorded above
+                failed_io = std::ptr::null();
+            }
+
+            let nexus = pio.nexus_as_ref();
+            if is_write && timed_out {
+                // the child has been faulted after the IO timed out, the IO
+                // completes from the remaining children
+                let ctx = pio.ctx_as_mut_ref();
+                ctx.failed += 1;
+                if ctx.failed >= ctx.children {
+                    ctx.status = io_status::FAILED;
+                }
+            } else if is_write && nexus.write_policy == WritePolicy::Quorum {
+                // the IO only fails once a majority of the children it was
+                // sent to have failed it, the others are faulted. Until then
+                // the child must not serve reads as it misses this write.
+                NexusChannel::inner_from_channel(spdk_bdev_io_get_io_channel(
+                    pio.0,
+                ))
+                .write_failed((*child_io).bdev);
+                nexus.child_write_failed(
+                    (*child_io).bdev,
+                    pio.offset(),
+                    pio.num_blocks(),
+                );
+                let ctx = pio.ctx_as_mut_ref();
+                ctx.failed += 1;
+                if ctx.children - ctx.failed < ctx.children / 2 + 1 {
+                    ctx.status = io_status::FAILED;
+                }
+            } else {
+                pio.ctx_as_mut_ref().status = io_status::FAILED;
+            }
+        } else if is_write
+            && NexusChannel::inner_from_channel(spdk_bdev_io_get_io_channel(
+                pio.0,
+            ))
+            .is_reader((*child_io).bdev)
+        {
+            pio.ctx_as_mut_ref().written += 1;
         }
-        pio.assess(child_io, success);
+
+        // a write which timed out on a child only completes from the others
+        // if it has reached a child which is read from, the children being
+        // rebuilt hold only part of the data
+        let ctx = pio.ctx_as_mut_ref();
+        if is_write
+            && ctx.in_flight == 1
+            && !ctx.timed_out.is_null()
+            && ctx.written == 0
+        {
+            ctx.status = io_status::FAILED;
+        }
+        pio.assess(failed_io, success);
         // always free the child IO
         Bio::io_free(child_io);
     }
 
+    /// Called for every child IO which has been outstanding for longer than
+    /// the child timeout. The IO is aborted on the child, which is faulted,
+    /// and the parent IO completes from the remaining children.
+    pub(crate) extern "C" fn child_io_timeout(
+        _ctx: *mut c_void,
+        child_io: *mut spdk_bdev_io,
+    ) {
+        unsafe {
+            // the descriptor of the child is also used for label and
+            // metadata IO which we leave alone
+            if (*child_io).internal.cb.map(|f| f as usize)
+                != Some(Self::io_completion as usize)
+            {
+                return;
+            }
+
+            let mut pio = Bio((*child_io).internal.caller_ctx as *mut _);
+            let bdev = (*child_io).bdev as *const spdk_bdev;
+
+            // the callback is repeated for as long as the IO is outstanding
+            if pio.ctx_as_mut_ref().timed_out == bdev {
+                return;
+            }
+
+            // there is no other child to complete the IO from, the children
+            // being rebuilt do not count as they are not read from
+            let channels = NexusChannel::inner_from_channel(
+                spdk_bdev_io_get_io_channel(pio.0),
+            );
+            if channels.is_reader(bdev) && channels.readers() < 2 {
+                return;
+            }
+
+            pio.ctx_as_mut_ref().timed_out = bdev;
+
+            let nexus = pio.nexus_as_ref();
+            warn!(
+                "{}: IO {:?} timed out on child {}, aborting",
+                nexus.name,
+                pio,
+                Bdev::from(bdev as *mut spdk_bdev).name()
+            );
+
+            let rc = spdk_bdev_abort(
+                (*child_io).internal.desc,
+                spdk_bdev_io_get_io_channel(child_io),
+                pio.0 as *mut c_void,
+                Some(Self::abort_completion),
+                std::ptr::null_mut(),
+            );
+            if rc != 0 {
+                // not every bdev supports aborts, eg: the error bdev
+                warn!(
+                    "{}: failed to abort IO {:?}: {}, resetting the child",
+                    nexus.name,
+                    pio,
+                    Errno::from_i32(-rc)
+                );
+                Self::reset_timed_out(
+                    (*child_io).internal.desc,
+                    spdk_bdev_io_get_io_channel(child_io),
+                );
+            }
+
+            let range = match Bio::io_type(pio.0) {
+                Some(io_type::WRITE)
+                | Some(io_type::UNMAP)
+                | Some(io_type::WRITE_ZEROES) => {
+                    Some((pio.offset(), pio.num_blocks()))
+                }
+                _ => None,
+            };
+            nexus.child_io_timed_out(bdev, range);
+        }
+    }
+
+    /// completion of the abort of a timed out child IO, which is reset
+    /// instead if the IO could not be aborted so that it completes either way
+    extern "C" fn abort_completion(
+        io: *mut spdk_bdev_io,
+        success: bool,
+        _ctx: *mut c_void,
+    ) {
+        if !success {
+            warn!("failed to abort timed out child IO, resetting the child");
+            unsafe {
+                Self::reset_timed_out(
+                    (*io).internal.desc,
+                    spdk_bdev_io_get_io_channel(io),
+                );
+            }
+        }
+        Bio::io_free(io);
+    }
+
+    /// reset the child on which an IO has timed out, failing all of its
+    /// outstanding IOs
+    fn reset_timed_out(desc: *mut spdk_bdev_desc, ch: *mut spdk_io_channel) {
+        let rc = unsafe {
+            spdk_bdev_reset(
+                desc,
+                ch,
+                Some(Self::reset_completion),
+                std::ptr::null_mut(),
+            )
+        };
+        if rc != 0 {
+            error!(
+                "failed to reset child with a timed out IO: {}",
+                Errno::from_i32(-rc)
+            );
+        }
+    }
+
+    /// completion of the reset of a child with a timed out IO
+    extern "C" fn reset_completion(
+        io: *mut spdk_bdev_io,
+        success: bool,
+        _ctx: *mut c_void,
+    ) {
+        if !success {
+            warn!("failed to reset child with a timed out IO");
+        }
+        Bio::io_free(io);
+    }
+
     /// callback when the IO has buffer associated with itself
     extern "C" fn nexus_get_buf_cb(
         ch: *mut spdk_io_channel,
@@ -658,13 +1220,16 @@ impl Nexus {
             warn!("{}: Failed to get io buffer for io {:?}", nexus.name, bio);
         }
 
-        let ch = NexusChannel::inner_from_channel(ch);
-        let (desc, ch) = ch.ch[ch.previous].io_tuple();
+        let channels = NexusChannel::inner_from_channel(ch);
+        let child = Bio(io).ctx_as_mut_ref().read_child;
+        let (desc, ch) = channels.ch[child].io_tuple();
         let ret = Self::readv_impl(io, desc, ch);
         if ret != 0 {
             let bio = Bio(io);
             let nexus = bio.nexus_as_ref();
             error!("{}: Failed to submit IO {:?}", nexus.name, bio);
+        } else {
+            channels.read_submitted(child);
         }
     }
 
@@ -679,8 +1244,11 @@ impl Nexus {
         // we use RR to read from the children also, set that we only need
         // to read from one child before we complete the IO to the callee.
         io.ctx_as_mut_ref().in_flight = 1;
+        io.ctx_as_mut_ref().read_retries = 0;
 
         let child = channels.child_select();
+        io.ctx_as_mut_ref().read_child = child;
+        io.ctx_as_mut_ref().submitted = unsafe { spdk_get_ticks() };
 
         // if there is no buffer space for us allocated within the request
         // allocate it now, taking care of proper alignment
@@ -707,7 +1275,52 @@ impl Nexus {
             );
 
             io.fail();
+        } else {
+            channels.read_submitted(child);
+        }
+    }
+
+    /// retry a failed read on the next child that can be read from, returns
+    /// false if there is no child left to retry the read on
+    fn readv_retry(&self, pio: *mut spdk_bdev_io) -> bool {
+        let mut io = Bio(pio);
+        let channels = NexusChannel::inner_from_channel(unsafe {
+            spdk_bdev_io_get_io_channel(pio)
+        });
+
+        // every other child gets one attempt; the channels may have been
+        // reconfigured in the meantime, in which case this is best effort
+        let readers = channels.ch.len() - channels.write_only;
+        let ctx = io.ctx_as_mut_ref();
+        ctx.read_retries += 1;
+        if readers == 0 || ctx.read_retries as usize >= readers {
+            return false;
+        }
+
+        let mut child = (ctx.read_child + 1) % readers;
+        while channels.unreadable(child) {
+            ctx.read_retries += 1;
+            if ctx.read_retries as usize >= readers {
+                return false;
+            }
+            child = (child + 1) % readers;
         }
+        ctx.read_child = child;
+        ctx.submitted = unsafe { spdk_get_ticks() };
+
+        debug!(
+            "{}: retrying failed read {:?} on child {}",
+            self.name,
+            io,
+            channels.ch[child].get_bdev().name()
+        );
+
+        let (desc, ch) = channels.ch[child].io_tuple();
+        if Self::readv_impl(pio, desc, ch) != 0 {
+            return false;
+        }
+        channels.read_submitted(child);
+        true
     }
 
     /// do the actual read
@@ -773,8 +1386,14 @@ impl Nexus {
         channels: &NexusChannelInner,
     ) {
         let mut io = Bio(pio);
+        // record the write for any child that is missing it
+        channels.mark_dirty(io.offset(), io.num_blocks());
         // in case of writes, we want to write to all underlying children
-        io.ctx_as_mut_ref().in_flight = channels.ch.len() as i8;
+        let ctx = io.ctx_as_mut_ref();
+        ctx.in_flight = channels.ch.len() as i8;
+        ctx.children = ctx.in_flight;
+        ctx.failed = 0;
+        ctx.written = 0;
         let results = channels
             .ch
             .iter()
@@ -809,7 +1428,12 @@ impl Nexus {
         channels: &NexusChannelInner,
     ) {
         let mut io = Bio(pio);
-        io.ctx_as_mut_ref().in_flight = channels.ch.len() as i8;
+        channels.mark_dirty(io.offset(), io.num_blocks());
+        let ctx = io.ctx_as_mut_ref();
+        ctx.in_flight = channels.ch.len() as i8;
+        ctx.children = ctx.in_flight;
+        ctx.failed = 0;
+        ctx.written = 0;
         let results = channels
             .ch
             .iter()
@@ -873,7 +1497,12 @@ impl Nexus {
         channels: &NexusChannelInner,
     ) {
         let mut io = Bio(pio);
-        io.ctx_as_mut_ref().in_flight = channels.ch.len() as i8;
+        channels.mark_dirty(io.offset(), io.num_blocks());
+        let ctx = io.ctx_as_mut_ref();
+        ctx.in_flight = channels.ch.len() as i8;
+        ctx.children = ctx.in_flight;
+        ctx.failed = 0;
+        ctx.written = 0;
         let results = channels
             .ch
             .iter()
@@ -994,6 +1623,13 @@ pub async fn nexus_create(
 
         Ok(_) => nexus_list.push(ni),
     }
+
+    // pick up the rebuilds where they were left off
+    if let Some(nexus) = nexus_lookup(name) {
+        metrics::enable_latency_histogram(nexus.bdev.clone()).await;
+        nexus.resume_rebuilds().await;
+        nexus.rebuild_stale_children().await;
+    }
     Ok(())
 }
 
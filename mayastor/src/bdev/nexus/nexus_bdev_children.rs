@@ -15,21 +15,38 @@
 //! 'fault_child` will do the same as `offline_child` except, it will not close
 //! the child.
 //!
+//! Both `offline_child` and `fault_child` start recording the writes which the
+//! child misses in its dirty region log, which allows `online_child` to only
+//! rebuild the regions that have changed in the meantime.
+//!
 //! `add_child` will construct a new `NexusChild` and add the bdev given by the
 //! uri to the nexus. The nexus will transition to degraded mode as the new
 //! child requires rebuild first. If the rebuild flag is set then the rebuild
 //! is also started otherwise it has to be started through `start_rebuild`.
 //!
+//! With the write quorum policy a child which fails a write is faulted by
+//! `child_write_failed` while the write itself succeeds, and is brought back
+//! by `recover_child` which rebuilds the regions it missed.
+//!
+//! An IO which times out on a child is aborted and the child is retired by
+//! `child_io_timed_out`, the IO then completes from the other children. A
+//! write is only acknowledged if one of the children it reached is read from,
+//! the children being rebuilt hold only part of the data, and the last healthy
+//! child is never retired.
+//!
 //! When reconfiguring the nexus, we traverse all our children, create new IO
 //! channels for all children that are in the open state.
 
 use futures::future::join_all;
 use snafu::ResultExt;
 
+use spdk_sys::spdk_bdev;
+
 use crate::{
     bdev::{
         nexus::{
             nexus_bdev::{
+                nexus_lookup,
                 CreateChild,
                 DestroyChild,
                 Error,
@@ -49,7 +66,7 @@ use crate::{
         },
         VerboseError,
     },
-    core::Bdev,
+    core::{Bdev, Cores, Reactors},
     nexus_uri::{bdev_create, bdev_destroy, NexusBdevError},
 };
 
@@ -190,6 +207,7 @@ impl Nexus {
                     // todo: how to signal this?
                 }
 
+                self.publish_status(None);
                 Ok(self.status())
             }
             Err(e) => {
@@ -218,6 +236,7 @@ impl Nexus {
         }
 
         self.cancel_child_rebuild_jobs(uri).await;
+        self.cancel_child_scrub(uri).await;
 
         let idx = match self.children.iter().position(|c| c.name == uri) {
             None => return Ok(()),
@@ -230,6 +249,12 @@ impl Nexus {
         let mut child = self.children.remove(idx);
         self.child_count -= 1;
         self.reconfigure(DREvent::ChildRemove).await;
+        child.publish_status(Some(&DREvent::ChildRemove));
+
+        // drop the persisted dirty log of the child as well
+        if child.dirty_log.take().is_some() {
+            self.persist_dirty_logs().await;
+        }
 
         child.destroy().await.context(DestroyChild {
             name: self.name.clone(),
@@ -245,6 +270,7 @@ impl Nexus {
         trace!("{}: Offline child request for {}", self.name, name);
 
         self.cancel_child_rebuild_jobs(name).await;
+        self.cancel_child_scrub(name).await;
 
         if let Some(child) = self.children.iter_mut().find(|c| c.name == name) {
             child.offline();
@@ -255,7 +281,11 @@ impl Nexus {
             });
         }
 
+        // keep track of the writes the child misses so that only those
+        // need to be rebuilt when it comes back online
+        self.track_child_writes(name);
         self.reconfigure(DREvent::ChildOffline).await;
+        self.persist_dirty_logs().await;
 
         Ok(self.status())
     }
@@ -272,11 +302,14 @@ impl Nexus {
         }
 
         self.cancel_child_rebuild_jobs(name).await;
+        self.cancel_child_scrub(name).await;
 
         if let Some(child) = self.children.iter_mut().find(|c| c.name == name) {
             if child.status() != ChildStatus::Faulted {
                 child.fault();
+                self.track_child_writes(name);
                 self.reconfigure(DREvent::ChildFault).await;
+                self.persist_dirty_logs().await;
             }
             Ok(())
         } else {
@@ -287,6 +320,253 @@ impl Nexus {
         }
     }
 
+    /// Called from the IO path when a child failed a write while the write
+    /// quorum policy is in effect. Faulting the child requires a
+    /// reconfiguration, so it is done from the management core.
+    pub(crate) fn child_write_failed(
+        &self,
+        bdev: *const spdk_bdev,
+        offset: u64,
+        num_blocks: u64,
+    ) {
+        let nexus_name = self.name.clone();
+        let mgmt_reactor = Reactors::get_by_core(Cores::first()).unwrap();
+        mgmt_reactor.send_future(async move {
+            Nexus::fault_write_minority(nexus_name, bdev, offset, num_blocks)
+                .await;
+        });
+    }
+
+    /// Fault a child which failed a write and bring it back through a
+    /// rebuild of the regions it missed. A child that fails writes while it
+    /// is being rebuilt is left faulted for the control plane to replace.
+    async fn fault_write_minority(
+        name: String,
+        bdev: *const spdk_bdev,
+        offset: u64,
+        num_blocks: u64,
+    ) {
+        let nexus = match nexus_lookup(&name) {
+            Some(nexus) => nexus,
+            None => return,
+        };
+
+        let (child_name, status, rebuilding) =
+            match nexus.children.iter().find(|c| {
+                c.bdev.as_ref().map(|b| b.as_ptr() as *const _) == Some(bdev)
+            }) {
+                Some(c) => (c.name.clone(), c.status(), c.rebuilding()),
+                None => return,
+            };
+
+        if status == ChildStatus::Online {
+            // never fault the last healthy child, the write has failed
+            // anyway if it was the only one left
+            if nexus
+                .children
+                .iter()
+                .filter(|c| c.status() == ChildStatus::Online)
+                .count()
+                < 2
+            {
+                return;
+            }
+            warn!(
+                "{}: faulting child {} which failed a write",
+                nexus.name, child_name
+            );
+            if let Err(e) = nexus.fault_child(&child_name).await {
+                error!(
+                    "{}: failed to fault child {}: {}",
+                    nexus.name,
+                    child_name,
+                    e.verbose()
+                );
+                return;
+            }
+        } else if rebuilding {
+            warn!(
+                "{}: child {} failed a write while being rebuilt",
+                nexus.name, child_name
+            );
+            if let Err(e) = nexus.fault_child(&child_name).await {
+                error!(
+                    "{}: failed to fault child {}: {}",
+                    nexus.name,
+                    child_name,
+                    e.verbose()
+                );
+            }
+            return;
+        }
+
+        // the failed write itself is missing from the child as well
+        if let Some(log) = nexus
+            .children
+            .iter()
+            .find(|c| c.name == child_name)
+            .and_then(|c| c.dirty_log.as_ref())
+        {
+            log.mark(offset, num_blocks);
+        }
+
+        if status == ChildStatus::Online {
+            if let Err(e) = nexus.recover_child(&child_name).await {
+                error!(
+                    "{}: failed to rebuild child {}: {}",
+                    nexus.name,
+                    child_name,
+                    e.verbose()
+                );
+            }
+        }
+    }
+
+    /// Called from the IO path when an IO timed out on a child and has been
+    /// aborted. The range of a write is passed along as the child misses it.
+    pub(crate) fn child_io_timed_out(
+        &self,
+        bdev: *const spdk_bdev,
+        range: Option<(u64, u64)>,
+    ) {
+        let nexus_name = self.name.clone();
+        let mgmt_reactor = Reactors::get_by_core(Cores::first()).unwrap();
+        mgmt_reactor.send_future(async move {
+            Nexus::fault_timed_out_child(nexus_name, bdev, range).await;
+        });
+    }
+
+    /// Retire a child on which an IO timed out
+    async fn fault_timed_out_child(
+        name: String,
+        bdev: *const spdk_bdev,
+        range: Option<(u64, u64)>,
+    ) {
+        let nexus = match nexus_lookup(&name) {
+            Some(nexus) => nexus,
+            None => return,
+        };
+
+        let (child_name, status) = match nexus.children.iter().find(|c| {
+            c.bdev.as_ref().map(|b| b.as_ptr() as *const _) == Some(bdev)
+        }) {
+            Some(c) if c.state == ChildState::Open => {
+                (c.name.clone(), c.status())
+            }
+            _ => return,
+        };
+
+        // never fault the last healthy child, the IO has failed anyway if it
+        // was the only one left
+        if status == ChildStatus::Online
+            && nexus
+                .children
+                .iter()
+                .filter(|c| c.status() == ChildStatus::Online)
+                .count()
+                < 2
+        {
+            return;
+        }
+
+        warn!(
+            "{}: faulting child {} after an IO timed out",
+            nexus.name, child_name
+        );
+        if let Err(e) = nexus.fault_child(&child_name).await {
+            error!(
+                "{}: failed to fault child {}: {}",
+                nexus.name,
+                child_name,
+                e.verbose()
+            );
+            return;
+        }
+
+        if let Some((offset, num_blocks)) = range {
+            if let Some(log) = nexus
+                .children
+                .iter()
+                .find(|c| c.name == child_name)
+                .and_then(|c| c.dirty_log.as_ref())
+            {
+                log.mark(offset, num_blocks);
+            }
+        }
+    }
+
+    /// Reopen a child which the nexus faulted after it failed a write and
+    /// start rebuilding it
+    pub(crate) async fn recover_child(
+        &mut self,
+        name: &str,
+    ) -> Result<NexusStatus, Error> {
+        trace!("{}: recover child request for {}", self.name, name);
+
+        if let Some(child) = self.children.iter_mut().find(|c| c.name == name) {
+            child.repair(self.size).context(OpenChild {
+                child: name.to_owned(),
+                name: self.name.clone(),
+            })?;
+            child.out_of_sync(true);
+            self.start_rebuild(name).await.map(|_| {})?;
+            Ok(self.status())
+        } else {
+            Err(Error::ChildNotFound {
+                name: self.name.clone(),
+                child: name.to_owned(),
+            })
+        }
+    }
+
+    /// Take a child out of service because of the errors it returned and
+    /// bring it back through a full rebuild. None of its contents can be
+    /// trusted, so no dirty log is kept and its error records are dropped.
+    pub(crate) async fn retire_child(
+        &mut self,
+        name: &str,
+    ) -> Result<NexusStatus, Error> {
+        trace!("{}: retire child request for {}", self.name, name);
+
+        // without another healthy child there is nothing to rebuild from
+        if !self
+            .children
+            .iter()
+            .any(|c| c.name != name && c.status() == ChildStatus::Online)
+        {
+            return Err(Error::NoRebuildSource {
+                name: self.name.clone(),
+            });
+        }
+
+        self.cancel_child_rebuild_jobs(name).await;
+        self.cancel_child_scrub(name).await;
+
+        if let Some(child) = self.children.iter_mut().find(|c| c.name == name) {
+            child.offline();
+            child.dirty_log = None;
+        } else {
+            return Err(Error::ChildNotFound {
+                name: self.name.clone(),
+                child: name.to_owned(),
+            });
+        }
+        self.reconfigure(DREvent::ChildOffline).await;
+
+        let size = self.size;
+        let child = self.children.iter_mut().find(|c| c.name == name).unwrap();
+        child.online(size).context(OpenChild {
+            child: name.to_owned(),
+            name: self.name.clone(),
+        })?;
+        child.out_of_sync(true);
+        if let Some(store) = child.err_store.as_mut() {
+            store.clear();
+        }
+        self.start_rebuild(name).await.map(|_| {})?;
+        Ok(self.status())
+    }
+
     /// online a child and reconfigure the IO channels. The child is already
     /// registered, but simply not opened. This can be required in case where
     /// a child is misbehaving.
@@ -302,6 +582,9 @@ impl Nexus {
                 name: self.name.clone(),
             })?;
             child.out_of_sync(true);
+            // only the regions written to while the child was offline need
+            // to be rebuilt, if we still know which ones they are
+            self.restore_dirty_log(name).await;
             self.start_rebuild(name).await.map(|_| {})?;
             Ok(self.status())
         } else {
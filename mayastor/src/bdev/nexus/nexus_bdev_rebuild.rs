@@ -1,7 +1,9 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
 use futures::channel::oneshot::Receiver;
 use snafu::ResultExt;
 
-use rpc::mayastor::{RebuildProgressReply, RebuildStateReply};
+use rpc::mayastor as rpc;
 
 use crate::{
     bdev::{
@@ -16,14 +18,97 @@ use crate::{
                 RemoveRebuildJob,
             },
             nexus_channel::DREvent,
-            nexus_child::{ChildState, ChildStatus},
+            nexus_child::ChildStatus,
         },
         VerboseError,
     },
     core::Reactors,
-    rebuild::{ClientOperations, RebuildError, RebuildJob, RebuildState},
+    rebuild::{
+        ClientOperations,
+        RebuildJob,
+        RebuildLimits,
+        RebuildState,
+        RebuildStats,
+    },
 };
 
+/// number of ended rebuilds kept in the history of a nexus
+const REBUILD_HISTORY_SIZE: usize = 32;
+
+/// A rebuild of a child which has come to an end, either way
+#[derive(Debug, Clone)]
+pub struct RebuildRecord {
+    /// uri of the rebuilt child
+    pub child: String,
+    /// uri of the child rebuilt from, the last one if it was switched
+    pub source: String,
+    /// final state of the rebuild
+    pub state: RebuildState,
+    /// the error the rebuild failed with, if any
+    pub error: Option<String>,
+    /// statistics of the rebuild at its end
+    pub stats: RebuildStats,
+    /// when the rebuild came to an end
+    pub end_time: SystemTime,
+}
+
+fn millis_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+impl From<RebuildStats> for rpc::RebuildStats {
+    fn from(stats: RebuildStats) -> Self {
+        Self {
+            blocks_total: stats.blocks_total,
+            blocks_recovered: stats.blocks_recovered,
+            blocks_transferred: stats.blocks_transferred,
+            progress: stats.progress as u32,
+            segment_size_blks: stats.segment_size_blks,
+            block_size: stats.block_size,
+            tasks_total: stats.tasks_total as u32,
+            tasks_active: stats.tasks_active as u32,
+            start_time: stats.start_time.map_or(0, millis_since_epoch),
+            elapsed_ms: stats.elapsed.as_millis() as u64,
+            throughput: stats.throughput,
+            remaining_ms: stats
+                .remaining
+                .map_or(0, |remaining| remaining.as_millis() as u64),
+            verifying: stats.verifying,
+            mismatches_found: stats.mismatches_found,
+            mismatches_fixed: stats.mismatches_fixed,
+            segments_retried: stats.segments_retried,
+        }
+    }
+}
+
+impl From<RebuildRecord> for rpc::RebuildRecord {
+    fn from(record: RebuildRecord) -> Self {
+        Self {
+            uri: record.child,
+            source: record.source,
+            state: record.state.to_string(),
+            error: record.error.unwrap_or_default(),
+            stats: Some(record.stats.into()),
+            end_time: millis_since_epoch(record.end_time),
+        }
+    }
+}
+
+impl From<rpc::RebuildLimits> for RebuildLimits {
+    fn from(limits: rpc::RebuildLimits) -> Self {
+        let default = RebuildLimits::default();
+        Self {
+            bandwidth_mbps: limits.bandwidth_mbps,
+            concurrency: if limits.concurrency == 0 {
+                default.concurrency
+            } else {
+                limits.concurrency as usize
+            },
+        }
+    }
+}
+
 impl Nexus {
     /// Starts a rebuild job and returns a receiver channel
     /// which can be used to await the rebuild completion
@@ -31,23 +116,91 @@ impl Nexus {
         &mut self,
         name: &str,
     ) -> Result<Receiver<RebuildState>, Error> {
-        trace!("{}: start rebuild request for {}", self.name, name);
+        self.start_rebuild_with_limits(name, RebuildLimits::default())
+            .await
+    }
 
-        let src_child_name = match self
-            .children
-            .iter()
-            .find(|c| c.state == ChildState::Open && c.name != name)
-        {
+    /// Starts a rebuild job which is kept within the given limits and
+    /// returns a receiver channel which can be used to await the rebuild
+    /// completion
+    pub async fn start_rebuild_with_limits(
+        &mut self,
+        name: &str,
+        limits: RebuildLimits,
+    ) -> Result<Receiver<RebuildState>, Error> {
+        self.start_rebuild_from(name, &[], limits, 0, false).await
+    }
+
+    /// Starts a single rebuild job for child `name` and the `more` children,
+    /// which reads each segment from the source once and writes it to all of
+    /// them, and compares them against the source afterwards if asked to
+    /// verify. Returns a receiver channel which can be used to await the
+    /// rebuild completion
+    pub async fn start_rebuild_many(
+        &mut self,
+        name: &str,
+        more: &[String],
+        limits: RebuildLimits,
+        verify: bool,
+    ) -> Result<Receiver<RebuildState>, Error> {
+        self.start_rebuild_from(name, more, limits, 0, verify).await
+    }
+
+    /// Starts a rebuild job which skips the first `blk` blocks of the data
+    /// partition, as these have been rebuilt by a previous job already
+    pub(crate) async fn start_rebuild_from(
+        &mut self,
+        name: &str,
+        more: &[String],
+        limits: RebuildLimits,
+        blk: u64,
+        verify: bool,
+    ) -> Result<Receiver<RebuildState>, Error> {
+        trace!(
+            "{}: start rebuild request for {} and {:?} with limits {:?} from block {}, verify {}",
+            self.name,
+            name,
+            more,
+            limits,
+            blk,
+            verify
+        );
+
+        for other in more {
+            match self.children.iter().find(|c| &c.name == other) {
+                Some(c) if c.status() == ChildStatus::Degraded => {}
+                Some(c) => {
+                    return Err(Error::ChildNotDegraded {
+                        child: other.clone(),
+                        name: self.name.clone(),
+                        state: c.status().to_string(),
+                    })
+                }
+                None => {
+                    return Err(Error::ChildNotFound {
+                        child: other.clone(),
+                        name: self.name.clone(),
+                    })
+                }
+            }
+        }
+
+        // the other children which are out of sync are no source either
+        let src_child_name = match self.children.iter().find(|c| {
+            c.status() == ChildStatus::Online
+                && c.name != name
+                && !more.contains(&c.name)
+        }) {
             Some(child) => Ok(child.name.clone()),
             None => Err(Error::NoRebuildSource {
                 name: self.name.clone(),
             }),
         }?;
 
-        let dst_child_name =
+        let (dst_child_name, dirty_log) =
             match self.children.iter_mut().find(|c| c.name == name) {
                 Some(c) if c.status() == ChildStatus::Degraded => {
-                    Ok(c.name.clone())
+                    Ok((c.name.clone(), c.dirty_log.clone()))
                 }
                 Some(c) => Err(Error::ChildNotDegraded {
                     child: name.to_owned(),
@@ -68,6 +221,9 @@ impl Nexus {
                 start: self.data_ent_offset,
                 end: self.bdev.num_blocks() + self.data_ent_offset,
             },
+            dirty_log,
+            limits,
+            verify,
             |nexus, job| {
                 Reactors::current().send_future(async move {
                     Nexus::notify_rebuild(nexus, job).await;
@@ -79,7 +235,27 @@ impl Nexus {
             name: self.name.clone(),
         })?;
 
-        // We're now rebuilding the `dst_child` which means it HAS to become an
+        if let Err(e) = more.iter().try_for_each(|c| job.add_destination(c)) {
+            // the job has not started yet so it can simply go
+            let _ = RebuildJob::remove(name);
+            return Err(e).context(CreateRebuildError {
+                child: name.to_owned(),
+                name: self.name.clone(),
+            });
+        }
+
+        if blk > 0 {
+            if let Err(e) = job.resume_from(blk) {
+                let _ = RebuildJob::remove(name);
+                return Err(e).context(CreateRebuildError {
+                    child: name.to_owned(),
+                    name: self.name.clone(),
+                });
+            }
+        }
+
+        // We're now rebuilding the `dst_child` (and any of the `more`
+        // children) which means it HAS to become an
         // active participant in the frontend nexus bdev for Writes.
         // This is because the rebuild job copies from src to target child
         // sequentially, from start to the end.
@@ -89,6 +265,14 @@ impl Nexus {
         // rebuilt ranges in sync with the other children.
         self.reconfigure(DREvent::ChildRebuild).await;
 
+        // the destinations are out of sync from here on, so the rebuild has
+        // to be resumed even if it never gets as far as the first update
+        for destination in job.destinations() {
+            let checkpoint = self.rebuild_checkpoint(job, &destination);
+            self.persist_rebuild_checkpoint(&destination, Some(&checkpoint))
+                .await;
+        }
+
         job.as_client().start().context(RebuildOperationError {
             job: name.to_owned(),
             name: self.name.clone(),
@@ -107,6 +291,31 @@ impl Nexus {
         }
     }
 
+    /// Terminates all rebuild jobs of the nexus and waits for them to be
+    /// gone, keeping their checkpoints so that the rebuilds are resumed when
+    /// the nexus is created again
+    pub(crate) async fn suspend_rebuilds(&mut self) {
+        let mut jobs = Vec::new();
+        for child in self.children.iter() {
+            if let Ok(rj) = self.get_rebuild_job(&child.name) {
+                // the job might still make some progress, which is fine
+                let checkpoint = self.rebuild_checkpoint(rj, &child.name);
+                jobs.push((
+                    child.name.clone(),
+                    checkpoint,
+                    rj.as_client().terminate(),
+                ));
+            }
+        }
+
+        for (name, checkpoint, terminated) in jobs {
+            // the channel is dropped along with the job once it is removed
+            let _ = terminated.await;
+            self.persist_rebuild_checkpoint(&name, Some(&checkpoint))
+                .await;
+        }
+    }
+
     /// Stop a rebuild job in the background
     pub async fn stop_rebuild(&self, name: &str) -> Result<(), Error> {
         match self.get_rebuild_job(name) {
@@ -138,13 +347,26 @@ impl Nexus {
         })
     }
 
+    /// Change the limits of a rebuild job, also while it is running
+    pub async fn set_rebuild_limits(
+        &self,
+        name: &str,
+        limits: RebuildLimits,
+    ) -> Result<(), Error> {
+        let rj = self.get_rebuild_job(name)?.as_client();
+        rj.set_limits(limits).context(RebuildOperationError {
+            job: name.to_owned(),
+            name: self.name.clone(),
+        })
+    }
+
     /// Return the state of a rebuild job
     pub async fn get_rebuild_state(
         &mut self,
         name: &str,
-    ) -> Result<RebuildStateReply, Error> {
+    ) -> Result<rpc::RebuildStateReply, Error> {
         let rj = self.get_rebuild_job(name)?;
-        Ok(RebuildStateReply {
+        Ok(rpc::RebuildStateReply {
             state: rj.state().to_string(),
         })
     }
@@ -153,17 +375,81 @@ impl Nexus {
     pub fn get_rebuild_progress(
         &self,
         name: &str,
-    ) -> Result<RebuildProgressReply, Error> {
+    ) -> Result<rpc::RebuildProgressReply, Error> {
         let rj = self.get_rebuild_job(name)?;
 
-        Ok(RebuildProgressReply {
-            progress: rj.as_client().stats().progress as u32,
+        let stats = rj.as_client().stats();
+
+        Ok(rpc::RebuildProgressReply {
+            progress: stats.progress as u32,
+            verifying: stats.verifying,
+            mismatches_found: stats.mismatches_found,
+            mismatches_fixed: stats.mismatches_fixed,
         })
     }
 
+    /// Returns the statistics of the rebuild of child target `name`
+    pub fn get_rebuild_stats(
+        &self,
+        name: &str,
+    ) -> Result<rpc::RebuildStatsReply, Error> {
+        let rj = self.get_rebuild_job(name)?;
+
+        Ok(rpc::RebuildStatsReply {
+            state: rj.state().to_string(),
+            source: rj.source.clone(),
+            stats: Some(rj.as_client().stats().into()),
+        })
+    }
+
+    /// Returns the rebuilds of child `name`, or of all the children, which
+    /// have come to an end, most recent first
+    pub fn get_rebuild_history(
+        &self,
+        name: Option<&str>,
+    ) -> rpc::RebuildHistoryReply {
+        rpc::RebuildHistoryReply {
+            rebuilds: self
+                .rebuild_history
+                .iter()
+                .filter(|r| name.map_or(true, |name| r.child == name))
+                .cloned()
+                .map(|r| r.into())
+                .collect(),
+        }
+    }
+
+    /// Keep the outcome of the rebuild job in the history of the nexus, so
+    /// that it can still be looked into once the job is gone
+    fn record_rebuild(&mut self, job: &RebuildJob) {
+        let stats = job.stats();
+        for (child, error) in job.dropped_destinations() {
+            self.rebuild_history.push_front(RebuildRecord {
+                child: child.clone(),
+                source: job.source.clone(),
+                state: RebuildState::Failed,
+                error: Some(error.verbose()),
+                stats: stats.clone(),
+                end_time: SystemTime::now(),
+            });
+        }
+        for child in job.destinations() {
+            self.rebuild_history.push_front(RebuildRecord {
+                child,
+                source: job.source.clone(),
+                state: job.state(),
+                error: job.error.as_ref().map(|e| e.verbose()),
+                stats: stats.clone(),
+                end_time: SystemTime::now(),
+            });
+        }
+        self.rebuild_history.truncate(REBUILD_HISTORY_SIZE);
+    }
+
     /// Cancels all rebuilds jobs associated with the child
     /// If any job is found with the child as a destination then the job is
-    /// stopped. If any job is found with the child as a source then
+    /// stopped, and replaced with a new one for any other children it was
+    /// rebuilding. If any job is found with the child as a source then
     /// the job is replaced with a new one with another healthy child
     /// as src, if found
     /// todo: how to proceed if no healthy child is found?
@@ -174,23 +460,49 @@ impl Nexus {
 
         // terminates all jobs with the child as a source
         src_jobs.iter_mut().for_each(|j| {
-            replace_jobs
-                .push((j.destination.clone(), j.as_client().terminate()));
+            replace_jobs.push((
+                j.destinations(),
+                j.limits(),
+                j.verify(),
+                j.as_client().terminate(),
+            ));
         });
 
+        // terminates the only possible job with the child as a destination,
+        // the other children rebuilt by it start all over again as they miss
+        // the writes in between the jobs
+        if let Ok(rj) = self.get_rebuild_job(name) {
+            let others = rj
+                .destinations()
+                .into_iter()
+                .filter(|d| d != name)
+                .collect::<Vec<_>>();
+            if !others.is_empty() {
+                replace_jobs.push((
+                    others,
+                    rj.limits(),
+                    rj.verify(),
+                    rj.as_client().terminate(),
+                ));
+            }
+        }
+
         for job in replace_jobs {
             // before we can start a new rebuild we need to wait
             // for the previous rebuild to complete
-            if let Err(e) = job.1.await {
+            if let Err(e) = job.3.await {
                 error!("Error {} when waiting for the job to terminate", e);
             }
 
-            if let Err(e) = self.start_rebuild(&job.0).await {
+            // the new job is kept within the limits of the old one
+            if let Err(e) = self
+                .start_rebuild_many(&job.0[0], &job.0[1 ..], job.1, job.2)
+                .await
+            {
                 error!("Failed to recreate rebuild: {}", e.verbose());
             }
         }
 
-        // terminates the only possible job with the child as a destination
         self.terminate_rebuild(name);
     }
 
@@ -221,60 +533,106 @@ impl Nexus {
         Ok(job)
     }
 
-    /// On rebuild job completion it updates the child and the nexus
+    /// Fault the children which the job has given up on as they failed to
+    /// be written to, while it carries on rebuilding the others. There is
+    /// nothing to resume for them either.
+    async fn fault_dropped_destinations(&mut self, job: &RebuildJob) {
+        let mut faulted = Vec::new();
+        for (destination, error) in job.dropped_destinations() {
+            match self.children.iter_mut().find(|c| &c.name == destination) {
+                Some(child) if child.status() != ChildStatus::Faulted => {
+                    child.fault();
+                    error!(
+                        "Rebuild job for child {} of nexus {} gave up on it, error: {}",
+                        destination,
+                        self.name,
+                        error.verbose(),
+                    );
+                    faulted.push(destination.clone());
+                }
+                _ => {}
+            }
+        }
+        if faulted.is_empty() {
+            return;
+        }
+
+        self.reconfigure(DREvent::ChildFault).await;
+        for destination in faulted {
+            self.persist_rebuild_checkpoint(&destination, None).await;
+        }
+    }
+
+    /// On rebuild job completion it updates the children and the nexus
     /// based on the rebuild job's final state
     async fn on_rebuild_complete_job(
         &mut self,
         job: &RebuildJob,
     ) -> Result<(), Error> {
-        let recovering_child = self.get_child_by_name(&job.destination)?;
-
-        match job.state() {
-            RebuildState::Completed => {
-                recovering_child.out_of_sync(false);
-                info!(
-                    "Child {} has been rebuilt successfully",
-                    recovering_child.name
-                );
+        let mut result = Ok(());
+        let mut drop_dirty_log = false;
 
-                assert_eq!(recovering_child.status(), ChildStatus::Online);
-            }
-            RebuildState::Stopped => {
-                info!(
-                    "Rebuild job for child {} of nexus {} stopped",
-                    &job.destination, &self.name,
-                );
-            }
-            RebuildState::Failed => {
-                // rebuild has failed so we need to set the child as faulted
-                // allowing the control plane to replace it with another
-                if let Some(RebuildError::ReadIoError {
-                    ..
-                }) = job.error
-                {
-                    // todo: retry rebuild using another child as source?
+        self.fault_dropped_destinations(job).await;
+
+        for destination in job.destinations() {
+            let recovering_child = match self.get_child_by_name(&destination) {
+                Ok(child) => child,
+                Err(e) => {
+                    result = Err(e);
+                    continue;
+                }
+            };
+
+            match job.state() {
+                RebuildState::Completed => {
+                    recovering_child.out_of_sync(false);
+                    // the child is back in sync so it no longer misses writes
+                    drop_dirty_log |=
+                        recovering_child.dirty_log.take().is_some();
+                    info!(
+                        "Child {} has been rebuilt successfully",
+                        recovering_child.name
+                    );
+
+                    assert_eq!(recovering_child.status(), ChildStatus::Online);
+                }
+                RebuildState::Stopped => {
+                    info!(
+                        "Rebuild job for child {} of nexus {} stopped",
+                        &destination, &self.name,
+                    );
+                }
+                RebuildState::Failed => {
+                    // rebuild has failed, even after retrying the segments
+                    // from other sources, so we need to set the child as
+                    // faulted allowing the control plane to replace it with
+                    // another
+                    recovering_child.fault();
+                    error!(
+                        "Rebuild job for child {} of nexus {} failed, error: {}",
+                        &destination,
+                        &self.name,
+                        job.error_desc(),
+                    );
+                }
+                _ => {
+                    recovering_child.fault();
+                    error!(
+                        "Rebuild job for child {} of nexus {} failed with state {:?}",
+                        &destination,
+                        &self.name,
+                        job.state(),
+                    );
                 }
-                recovering_child.fault();
-                error!(
-                    "Rebuild job for child {} of nexus {} failed, error: {}",
-                    &job.destination,
-                    &self.name,
-                    job.error_desc(),
-                );
-            }
-            _ => {
-                recovering_child.fault();
-                error!(
-                    "Rebuild job for child {} of nexus {} failed with state {:?}",
-                    &job.destination,
-                    &self.name,
-                    job.state(),
-                );
             }
         }
 
         self.reconfigure(DREvent::ChildRebuild).await;
-        Ok(())
+
+        if drop_dirty_log {
+            self.persist_dirty_logs().await;
+        }
+        result
     }
 
     async fn on_rebuild_update(&mut self, job: String) -> Result<(), Error> {
@@ -284,11 +642,31 @@ impl Nexus {
         })?;
 
         if !j.state().done() {
-            // Leave all states as they are
+            self.fault_dropped_destinations(j).await;
+            // record how far a running job got, leave all states as they are
+            if j.state() == RebuildState::Running {
+                for destination in j.destinations() {
+                    let checkpoint = self.rebuild_checkpoint(j, &destination);
+                    self.persist_rebuild_checkpoint(
+                        &destination,
+                        Some(&checkpoint),
+                    )
+                    .await;
+                }
+            }
             return Ok(());
         }
 
+        self.record_rebuild(&j);
         let complete_err = self.on_rebuild_complete_job(&j).await;
+        // whichever way the job ended there is nothing left to resume
+        for destination in j
+            .destinations()
+            .into_iter()
+            .chain(j.dropped_destinations().iter().map(|(d, _)| d.clone()))
+        {
+            self.persist_rebuild_checkpoint(&destination, None).await;
+        }
         let remove_err = RebuildJob::remove(&job)
             .context(RemoveRebuildJob {
                 child: job,
@@ -0,0 +1,153 @@
+//!
+//! Scrub of a nexus, which compares the data of all of its online children
+//! and reports the ranges where they differ. Given a child to trust, the
+//! others are repaired from it as well.
+
+use snafu::ResultExt;
+
+use rpc::mayastor as rpc;
+
+use crate::{
+    bdev::{
+        nexus::{
+            nexus_bdev::{
+                CreateScrubError,
+                Error,
+                Nexus,
+                NexusStatus,
+                ScrubJobNotFound,
+                ScrubOperationError,
+            },
+            nexus_child::ChildStatus,
+        },
+        VerboseError,
+    },
+    scrub::{ScrubJob, ScrubMismatch},
+};
+
+impl From<ScrubMismatch> for rpc::ScrubMismatch {
+    fn from(mismatch: ScrubMismatch) -> Self {
+        Self {
+            offset: mismatch.offset,
+            num_blocks: mismatch.num_blocks,
+            uris: mismatch.children,
+            repaired: mismatch.repaired,
+        }
+    }
+}
+
+impl Nexus {
+    /// Starts a scrub of all the online children of the nexus, which repairs
+    /// them from the child `authority` if given one. The results of a
+    /// previous scrub which is done with are dropped.
+    pub async fn start_scrub(
+        &mut self,
+        authority: Option<&str>,
+    ) -> Result<(), Error> {
+        trace!(
+            "{}: start scrub request repairing from {:?}",
+            self.name,
+            authority
+        );
+
+        // the children of a degraded nexus are known to differ
+        if self.status() != NexusStatus::Online {
+            return Err(Error::ScrubNotOnline {
+                name: self.name.clone(),
+            });
+        }
+
+        let children = self
+            .children
+            .iter()
+            .filter(|c| c.status() == ChildStatus::Online)
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+
+        let job = ScrubJob::create(
+            &self.name,
+            &children,
+            authority,
+            std::ops::Range::<u64> {
+                start: self.data_ent_offset,
+                end: self.bdev.num_blocks() + self.data_ent_offset,
+            },
+        )
+        .context(CreateScrubError {
+            name: self.name.clone(),
+        })?;
+
+        job.start().context(ScrubOperationError {
+            name: self.name.clone(),
+        })
+    }
+
+    /// Stop the scrub, its results are kept
+    pub async fn stop_scrub(&self) -> Result<(), Error> {
+        self.get_scrub_job()?.stop().context(ScrubOperationError {
+            name: self.name.clone(),
+        })
+    }
+
+    /// Pause the scrub
+    pub async fn pause_scrub(&self) -> Result<(), Error> {
+        self.get_scrub_job()?.pause().context(ScrubOperationError {
+            name: self.name.clone(),
+        })
+    }
+
+    /// Resume the paused scrub
+    pub async fn resume_scrub(&self) -> Result<(), Error> {
+        self.get_scrub_job()?.resume().context(ScrubOperationError {
+            name: self.name.clone(),
+        })
+    }
+
+    /// Returns the progress of the scrub and the ranges where the children
+    /// differ, also once the scrub is done with
+    pub fn get_scrub_progress(&self) -> Result<rpc::ScrubProgressReply, Error> {
+        let job = self.get_scrub_job()?;
+        let stats = job.stats();
+
+        Ok(rpc::ScrubProgressReply {
+            state: job.state().to_string(),
+            progress: stats.progress as u32,
+            blocks_total: stats.blocks_total,
+            blocks_scrubbed: stats.blocks_scrubbed,
+            authority: job.authority.clone().unwrap_or_default(),
+            error: job.error.as_ref().map(|e| e.verbose()).unwrap_or_default(),
+            mismatches: stats
+                .mismatches
+                .into_iter()
+                .map(|m| m.into())
+                .collect(),
+        })
+    }
+
+    /// Stops the scrub if child `name` is one of the children it compares,
+    /// as it is about to be taken out of service
+    pub(crate) async fn cancel_child_scrub(&self, name: &str) {
+        if let Ok(job) = ScrubJob::lookup(&self.name) {
+            if job.children.iter().any(|c| c == name) {
+                let _ = job.terminate().await;
+            }
+        }
+    }
+
+    /// Stops the scrub and drops its results, as the nexus is going away
+    pub(crate) async fn remove_scrub(&self) {
+        if let Ok(job) = ScrubJob::lookup(&self.name) {
+            let _ = job.terminate().await;
+            let _ = ScrubJob::remove(&self.name);
+        }
+    }
+
+    fn get_scrub_job(&self) -> Result<&mut ScrubJob, Error> {
+        let job = ScrubJob::lookup(&self.name).context(ScrubJobNotFound {
+            name: self.name.clone(),
+        })?;
+
+        assert_eq!(job.nexus, self.name);
+        Ok(job)
+    }
+}
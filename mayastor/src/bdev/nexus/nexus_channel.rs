@@ -1,10 +1,12 @@
 //!
 //! IO is driven by means of so called channels.
-use std::{convert::TryFrom, ffi::c_void};
+use std::{convert::TryFrom, ffi::c_void, sync::Arc};
 
 use spdk_sys::{
+    spdk_bdev,
     spdk_for_each_channel,
     spdk_for_each_channel_continue,
+    spdk_get_ticks,
     spdk_io_channel,
     spdk_io_channel_iter,
     spdk_io_channel_iter_get_channel,
@@ -12,10 +14,14 @@ use spdk_sys::{
 };
 
 use crate::{
-    bdev::{nexus::nexus_child::ChildStatus, Nexus},
+    bdev::{nexus::nexus_child::ChildStatus, DirtyLog, Nexus, ReadPolicy},
     core::BdevHandle,
 };
 
+/// with the lowest latency policy, every so many reads are sent round robin
+/// so that the latency of the other children keeps on being sampled
+const LATENCY_PROBE_INTERVAL: u64 = 64;
+
 /// io channel, per core
 #[repr(C)]
 #[derive(Debug)]
@@ -29,9 +35,66 @@ pub(crate) struct NexusChannelInner {
     pub(crate) ch: Vec<BdevHandle>,
     pub(crate) write_only: usize,
     pub(crate) previous: usize,
+    /// read statistics of the children, in the same order as `ch`
+    read_stats: Vec<ReadStats>,
+    /// number of reads submitted through this channel
+    reads: u64,
+    /// dirty logs of the children which are missing the writes
+    dirty_logs: Vec<Arc<DirtyLog>>,
     device: *mut c_void,
 }
 
+/// Per core read statistics of a child used by the read policies
+#[derive(Debug, Default)]
+struct ReadStats {
+    /// reads submitted to the child which have not completed yet
+    outstanding: u32,
+    /// moving average of the read latency in ticks
+    latency: u64,
+    /// the child lives on this node
+    local: bool,
+    /// the child has failed a write and is no longer read from, even before
+    /// it is faulted and the channels are refreshed
+    failed: bool,
+}
+
+/// Select the reader to send the next read to out of those whose statistics
+/// are given, `previous` being the one selected last and `reads` the number
+/// of reads selected so far
+fn select_reader(
+    policy: ReadPolicy,
+    stats: &[ReadStats],
+    previous: usize,
+    reads: u64,
+) -> usize {
+    let readers = stats.len();
+
+    // candidates in round robin order, starting after the previous child
+    // so that ties are broken by rotating between the children
+    let mut order = (1 ..= readers)
+        .map(|i| (previous + i) % readers)
+        .filter(|c| !stats[*c].failed);
+
+    match policy {
+        ReadPolicy::RoundRobin => order.next(),
+        ReadPolicy::LeastOutstanding => {
+            order.min_by_key(|c| stats[*c].outstanding)
+        }
+        ReadPolicy::LowestLatency => {
+            if reads % LATENCY_PROBE_INTERVAL == 0 {
+                order.next()
+            } else {
+                order.min_by_key(|c| stats[*c].latency)
+            }
+        }
+        ReadPolicy::PreferLocal => order
+            .clone()
+            .find(|c| stats[*c].local)
+            .or_else(|| order.next()),
+    }
+    .unwrap_or(0)
+}
+
 #[derive(Debug)]
 /// Dynamic Reconfiguration Events occur when a child is added or removed
 pub enum DREvent {
@@ -48,16 +111,93 @@ pub enum DREvent {
 }
 
 impl NexusChannelInner {
-    /// very simplistic routine to rotate between children for read operations
+    /// select the child to read from according to the read policy of the
+    /// nexus
     pub(crate) fn child_select(&mut self) -> usize {
-        if self.previous != self.ch.len() - self.write_only - 1 {
-            self.previous += 1;
-        } else {
-            self.previous = 0;
-        }
+        let nexus = unsafe { Nexus::from_raw(self.device) };
+        let readers = self.readers();
+
+        self.previous = select_reader(
+            nexus.read_policy,
+            &self.read_stats[.. readers],
+            self.previous,
+            self.reads,
+        );
+        self.reads += 1;
         self.previous
     }
 
+    /// Mark the given nexus block range as dirty for every child which is
+    /// recording the writes it misses
+    pub(crate) fn mark_dirty(&self, offset: u64, num_blocks: u64) {
+        self.dirty_logs
+            .iter()
+            .for_each(|log| log.mark(offset, num_blocks));
+    }
+
+    /// Stop reading from the child of `bdev` which has failed a write that
+    /// the nexus completed from the other children. The child is faulted
+    /// asynchronously and until the channels are refreshed it must not
+    /// serve any reads as it misses the data of that write.
+    pub(crate) fn write_failed(&mut self, bdev: *const spdk_bdev) {
+        if let Some(child) = self
+            .ch
+            .iter()
+            .position(|h| h.get_bdev().as_ptr() as *const _ == bdev)
+        {
+            self.read_stats[child].failed = true;
+        }
+    }
+
+    /// returns true if the child of `bdev` is read from, as opposed to a
+    /// child being rebuilt which is only written to
+    pub(crate) fn is_reader(&self, bdev: *const spdk_bdev) -> bool {
+        self.ch[.. self.ch.len() - self.write_only]
+            .iter()
+            .any(|h| h.get_bdev().as_ptr() as *const _ == bdev)
+    }
+
+    /// number of children which are read from
+    pub(crate) fn readers(&self) -> usize {
+        self.ch.len() - self.write_only
+    }
+
+    /// returns true if child `child` must not be read from
+    pub(crate) fn unreadable(&self, child: usize) -> bool {
+        self.read_stats.get(child).map_or(true, |s| s.failed)
+    }
+
+    /// account for a read submitted to child `child`
+    pub(crate) fn read_submitted(&mut self, child: usize) {
+        if let Some(stats) = self.read_stats.get_mut(child) {
+            stats.outstanding += 1;
+        }
+    }
+
+    /// account for a read submitted to child `child` at `submitted` ticks
+    /// which has now completed on `bdev`
+    pub(crate) fn read_completed(
+        &mut self,
+        child: usize,
+        bdev: *mut spdk_bdev,
+        submitted: u64,
+    ) {
+        // the channels may have been refreshed while the read was in flight
+        if child >= self.ch.len() || self.ch[child].get_bdev().as_ptr() != bdev
+        {
+            return;
+        }
+
+        let latency = unsafe { spdk_get_ticks() } - submitted;
+        let stats = &mut self.read_stats[child];
+        stats.outstanding = stats.outstanding.saturating_sub(1);
+        stats.latency = if stats.latency == 0 {
+            latency
+        } else {
+            (stats.latency * 7 + latency) / 8
+        };
+    }
+
     /// refreshing our channels simply means that we either have a child going
     /// online or offline. We don't know which child has gone, or was added, so
     /// we simply put back all the channels, and reopen the bdevs that are in
@@ -81,8 +221,10 @@ impl NexusChannelInner {
         // clearing the values will drop any existing handles in the
         // channel
         self.ch.clear();
+        self.read_stats.clear();
         self.previous = 0;
         self.write_only = 0;
+        self.dirty_logs = nexus.dirty_logs();
 
         // iterate to over all our children which are in the open state
         nexus
@@ -92,7 +234,11 @@ impl NexusChannelInner {
             .for_each(|c| {
                 self.ch.push(
                     BdevHandle::try_from(c.get_descriptor().unwrap()).unwrap(),
-                )
+                );
+                self.read_stats.push(ReadStats {
+                    local: c.is_local(),
+                    ..Default::default()
+                });
             });
 
         if !self.ch.is_empty() {
@@ -105,7 +251,11 @@ impl NexusChannelInner {
                     self.ch.push(
                         BdevHandle::try_from(c.get_descriptor().unwrap())
                             .unwrap(),
-                    )
+                    );
+                    self.read_stats.push(ReadStats {
+                        local: c.is_local(),
+                        ..Default::default()
+                    });
                 })
                 .for_each(drop);
         }
@@ -135,6 +285,9 @@ impl NexusChannel {
             ch: Vec::new(),
             previous: 0,
             write_only: 0,
+            read_stats: Vec::new(),
+            reads: 0,
+            dirty_logs: nexus.dirty_logs(),
             device,
         });
 
@@ -145,7 +298,11 @@ impl NexusChannel {
             .map(|c| {
                 channels.ch.push(
                     BdevHandle::try_from(c.get_descriptor().unwrap()).unwrap(),
-                )
+                );
+                channels.read_stats.push(ReadStats {
+                    local: c.is_local(),
+                    ..Default::default()
+                });
             })
             .for_each(drop);
         ch.inner = Box::into_raw(channels);
@@ -158,6 +315,8 @@ impl NexusChannel {
         debug!("{} Destroying IO channels", nexus.bdev.name());
         let inner = NexusChannel::from_raw(ctx).inner_mut();
         inner.ch.clear();
+        inner.read_stats.clear();
+        inner.dirty_logs.clear();
     }
 
     /// function called when we receive a Dynamic Reconfigure event (DR)
@@ -231,3 +390,120 @@ impl NexusChannel {
         unsafe { &mut *self.inner }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(outstanding: u32, latency: u64, local: bool) -> ReadStats {
+        ReadStats {
+            outstanding,
+            latency,
+            local,
+            failed: false,
+        }
+    }
+
+    fn failed() -> ReadStats {
+        ReadStats {
+            failed: true,
+            ..Default::default()
+        }
+    }
+
+    // select `count` readers in a row the way the channel does
+    fn select(
+        policy: ReadPolicy,
+        stats: &[ReadStats],
+        count: u64,
+    ) -> Vec<usize> {
+        let mut previous = 0;
+        (1 ..= count)
+            .map(|reads| {
+                previous = select_reader(policy, stats, previous, reads);
+                previous
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_robin() {
+        let s =
+            vec![stats(0, 0, false), stats(0, 0, false), stats(0, 0, false)];
+        assert_eq!(select(ReadPolicy::RoundRobin, &s, 4), vec![1, 2, 0, 1]);
+    }
+
+    #[test]
+    fn least_outstanding() {
+        let s =
+            vec![stats(3, 0, false), stats(1, 0, false), stats(2, 0, false)];
+        assert_eq!(select(ReadPolicy::LeastOutstanding, &s, 2), vec![1, 1]);
+
+        // ties rotate between the children
+        let s =
+            vec![stats(1, 0, false), stats(4, 0, false), stats(1, 0, false)];
+        assert_eq!(select(ReadPolicy::LeastOutstanding, &s, 3), vec![2, 0, 2]);
+    }
+
+    #[test]
+    fn lowest_latency() {
+        let s = vec![
+            stats(0, 300, false),
+            stats(0, 100, false),
+            stats(0, 200, false),
+        ];
+        assert_eq!(select(ReadPolicy::LowestLatency, &s, 3), vec![1, 1, 1]);
+
+        // every so many reads the next child is probed instead
+        assert_eq!(
+            select_reader(
+                ReadPolicy::LowestLatency,
+                &s,
+                1,
+                LATENCY_PROBE_INTERVAL
+            ),
+            2
+        );
+        assert_eq!(
+            select_reader(
+                ReadPolicy::LowestLatency,
+                &s,
+                1,
+                LATENCY_PROBE_INTERVAL + 1
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn prefer_local() {
+        let s = vec![stats(0, 0, false), stats(0, 0, true), stats(0, 0, false)];
+        assert_eq!(select(ReadPolicy::PreferLocal, &s, 3), vec![1, 1, 1]);
+
+        // the local children take turns
+        let s = vec![stats(0, 0, true), stats(0, 0, false), stats(0, 0, true)];
+        assert_eq!(select(ReadPolicy::PreferLocal, &s, 3), vec![2, 0, 2]);
+
+        // without a local child the reads are sent round robin
+        let s = vec![stats(0, 0, false), stats(0, 0, false)];
+        assert_eq!(select(ReadPolicy::PreferLocal, &s, 3), vec![1, 0, 1]);
+    }
+
+    #[test]
+    fn failed_write() {
+        // a child which failed a write is skipped whatever the policy
+        let s = vec![stats(0, 0, false), failed(), stats(0, 0, false)];
+        assert_eq!(select(ReadPolicy::RoundRobin, &s, 3), vec![2, 0, 2]);
+        let s = vec![stats(2, 0, false), failed(), stats(1, 0, false)];
+        assert_eq!(select(ReadPolicy::LeastOutstanding, &s, 2), vec![2, 2]);
+        let s = vec![stats(0, 200, false), failed(), stats(0, 300, false)];
+        assert_eq!(select(ReadPolicy::LowestLatency, &s, 2), vec![0, 0]);
+        let s = vec![stats(0, 0, false), failed(), stats(0, 0, false)];
+        assert_eq!(select(ReadPolicy::PreferLocal, &s, 2), vec![2, 0]);
+    }
+
+    #[test]
+    fn no_readers() {
+        assert_eq!(select_reader(ReadPolicy::RoundRobin, &[], 0, 0), 0);
+    }
+}
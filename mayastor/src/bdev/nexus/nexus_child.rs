@@ -1,14 +1,20 @@
 use std::{convert::TryFrom, fmt::Display, sync::Arc};
 
 use nix::errno::Errno;
-use serde::{export::Formatter, Serialize};
+use serde::{export::Formatter, Deserialize, Serialize};
 use snafu::{ResultExt, Snafu};
+use url::Url;
 
-use spdk_sys::{spdk_bdev_module_release_bdev, spdk_io_channel};
+use spdk_sys::{
+    spdk_bdev_module_release_bdev,
+    spdk_bdev_set_timeout,
+    spdk_io_channel,
+};
 
 use crate::{
-    bdev::NexusErrStore,
+    bdev::{nexus::nexus_channel::DREvent, DirtyLog, Nexus, NexusErrStore},
     core::{Bdev, BdevHandle, CoreError, Descriptor, DmaBuf},
+    events::{self, EventKind},
     nexus_uri::{bdev_destroy, NexusBdevError},
     rebuild::{ClientOperations, RebuildJob},
     subsys::Config,
@@ -52,7 +58,7 @@ pub enum ChildIoError {
     InvalidDescriptor { name: String },
 }
 
-#[derive(Debug, Clone, Copy, Serialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
 pub enum ChildStatus {
     /// available for RW
     Online,
@@ -154,6 +160,12 @@ pub struct NexusChild {
     /// record of most-recent IO errors
     #[serde(skip_serializing)]
     pub(crate) err_store: Option<NexusErrStore>,
+    /// regions written to while the child was out of the IO path
+    #[serde(skip_serializing)]
+    pub(crate) dirty_log: Option<Arc<DirtyLog>>,
+    /// status of the child last published on the event bus
+    #[serde(skip_serializing)]
+    published_status: Option<ChildStatus>,
 }
 
 impl Display for NexusChild {
@@ -233,6 +245,23 @@ impl NexusChild {
                 Some(NexusErrStore::new(cfg.err_store_opts.err_store_size));
         };
 
+        if cfg.nexus_opts.child_timeout_sec > 0 {
+            let rc = unsafe {
+                spdk_bdev_set_timeout(
+                    self.desc.as_ref().unwrap().as_ptr(),
+                    cfg.nexus_opts.child_timeout_sec,
+                    Some(Nexus::child_io_timeout),
+                    std::ptr::null_mut(),
+                )
+            };
+            if rc != 0 {
+                warn!(
+                    "{}: failed to set the IO timeout of child {}: {}",
+                    self.parent, self.name, rc
+                );
+            }
+        }
+
         self.state = ChildState::Open;
 
         debug!("{}: child {} opened successfully", self.parent, self.name);
@@ -245,8 +274,24 @@ impl NexusChild {
         self.close();
         self.status_reasons.fatal_error();
     }
+    /// Reopen a child which has been faulted by the nexus because it failed
+    /// a write that a quorum of the other children acknowledged. Unlike
+    /// other faults this one can be recovered from by a rebuild.
+    pub(crate) fn repair(
+        &mut self,
+        parent_size: u64,
+    ) -> Result<String, ChildError> {
+        if !self.status_reasons.fatal_error {
+            return Err(ChildError::ChildInvalid {});
+        }
+        self.status_reasons.fatal_error = false;
+        self.open(parent_size).map_err(|e| {
+            self.status_reasons.fatal_error();
+            e
+        })
+    }
     /// Set the child as out of sync with the nexus
-    /// It requires a full rebuild before it can service IO
+    /// It requires a rebuild before it can service IO
     /// and remains degraded until such time
     pub(crate) fn out_of_sync(&mut self, out_of_sync: bool) {
         self.status_reasons.out_of_sync(out_of_sync);
@@ -308,6 +353,22 @@ impl NexusChild {
         }
     }
 
+    /// Publish the status of the child on the event bus if it has changed
+    /// since it was published the last time, along with the dynamic
+    /// reconfiguration of the nexus which changed it, if any.
+    pub(crate) fn publish_status(&mut self, reason: Option<&DREvent>) {
+        let status = self.status();
+        if self.published_status != Some(status) {
+            self.published_status = Some(status);
+            events::publish(EventKind::ChildStatus {
+                nexus: self.parent.clone(),
+                child: self.name.clone(),
+                status,
+                reason: reason.map(|r| format!("{:?}", r)),
+            });
+        }
+    }
+
     pub(crate) fn rebuilding(&self) -> bool {
         match RebuildJob::lookup(&self.name) {
             Ok(_) => {
@@ -363,6 +424,8 @@ impl NexusChild {
             status_reasons: Default::default(),
             bdev_handle: None,
             err_store: None,
+            dirty_log: None,
+            published_status: None,
         }
     }
 
@@ -378,6 +441,15 @@ impl NexusChild {
         }
     }
 
+    /// returns if the child lives on this node, i.e. it is not reached over
+    /// the network
+    pub fn is_local(&self) -> bool {
+        match Url::parse(&self.name) {
+            Ok(url) => !matches!(url.scheme(), "nvmf" | "iscsi"),
+            Err(_) => true,
+        }
+    }
+
     /// returns if a child can be written to
     pub fn can_rw(&self) -> bool {
         self.state == ChildState::Open && self.status() != ChildStatus::Faulted
@@ -12,23 +12,25 @@ use crate::{
         nexus_bdev,
         nexus_bdev::{
             nexus_lookup,
-            Error::{ChildMissing, ChildMissingErrStore},
+            Error::{ChildMissing, ChildMissingErrStore, ErrStoreDisabled},
             Nexus,
         },
         nexus_child::{ChildState, NexusChild},
         nexus_io::{io_status, io_type},
+        VerboseError,
     },
     core::{Cores, Reactors},
-    subsys::Config,
+    events::{self, EventKind},
+    subsys::{config::opts::ErrStoreOpts, Config},
 };
 
 #[derive(Copy, Clone)]
 pub struct NexusChildErrorRecord {
-    io_offset: u64,
-    io_num_blocks: u64,
-    timestamp: Instant,
-    io_error: i32,
-    io_op: spdk_bdev_io_type,
+    pub io_offset: u64,
+    pub io_num_blocks: u64,
+    pub timestamp: Instant,
+    pub io_error: i32,
+    pub io_op: spdk_bdev_io_type,
 }
 
 impl Default for NexusChildErrorRecord {
@@ -51,16 +53,33 @@ pub struct NexusErrStore {
 
 // this controls what kind of search to perform in the error store
 pub enum QueryType {
-    Total, /* determine the total number of errors stored
-            * other types, e.g. MostAttempts may be added in future
-            * revisions */
+    // determine the total number of errors stored
+    Total,
+    // determine the highest number of errors stored within any window of
+    // the given length
+    MaxRate(Duration),
 }
 
+// what to do with a child whose errors exceed the configured limits
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum ActionType {
-    Ignore,
-    Fault,
+    Ignore, // nothing at all
+    Fault,  // fault the child, leaving it to the control plane to replace
+    Retire, // offline the child and bring it back through a full rebuild
+    Warn,   // only publish an event about it
+}
+
+impl Display for ActionType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result<(), Error> {
+        let action = match self {
+            ActionType::Ignore => "ignore",
+            ActionType::Fault => "fault",
+            ActionType::Retire => "retire",
+            ActionType::Warn => "warn",
+        };
+        write!(f, "{}", action)
+    }
 }
 
 impl NexusErrStore {
@@ -70,6 +89,12 @@ impl NexusErrStore {
     pub const FLUSH_FLAG: u32 = 1 << (io_type::FLUSH - 1);
     pub const RESET_FLAG: u32 = 1 << (io_type::RESET - 1);
 
+    pub const ALL_OP_FLAGS: u32 = Self::READ_FLAG
+        | Self::WRITE_FLAG
+        | Self::UNMAP_FLAG
+        | Self::FLUSH_FLAG
+        | Self::RESET_FLAG;
+
     pub const IO_FAILED_FLAG: u32 = 1;
 
     // the following definitions are for the error_store unit test
@@ -182,6 +207,69 @@ impl NexusErrStore {
         err_count
     }
 
+    #[inline]
+    fn max_errors_in_window(
+        &self,
+        io_op_flags: u32,
+        io_error_flags: u32,
+        target_timestamp: Option<Instant>,
+        window: Duration,
+    ) -> u32 {
+        // the records are added in the order the errors reach the management
+        // core, which is not necessarily the order they occurred in
+        let mut times = self
+            .list(io_op_flags, io_error_flags, target_timestamp)
+            .iter()
+            .map(|r| r.timestamp)
+            .collect::<Vec<_>>();
+        times.sort();
+
+        let mut start = 0;
+        let mut max_count = 0;
+        for end in 0 .. times.len() {
+            while times[end].duration_since(times[start]) >= window {
+                start += 1;
+            }
+            max_count = max_count.max(end - start + 1);
+        }
+        max_count as u32
+    }
+
+    /// Forget all the records.
+    pub fn clear(&mut self) {
+        self.no_of_records = 0;
+        self.next_record_index = 0;
+    }
+
+    /// Return the records matching the flags and the time, the most recent
+    /// first.
+    pub fn list(
+        &self,
+        io_op_flags: u32,
+        io_error_flags: u32,
+        target_timestamp: Option<Instant>,
+    ) -> Vec<NexusChildErrorRecord> {
+        let mut idx = self.next_record_index;
+        let mut records = Vec::new();
+
+        for _ in 0 .. self.no_of_records {
+            if idx > 0 {
+                idx -= 1;
+            } else {
+                idx = self.records.len() - 1;
+            }
+            if Self::has_error(
+                &self.records[idx],
+                io_op_flags,
+                io_error_flags,
+                target_timestamp,
+            ) {
+                records.push(self.records[idx]);
+            }
+        }
+        records
+    }
+
     #[inline]
     pub fn query(
         &self,
@@ -194,6 +282,12 @@ impl NexusErrStore {
             QueryType::Total => {
                 self.count_errors(io_op_flags, io_error_flags, target_timestamp)
             }
+            QueryType::MaxRate(window) => self.max_errors_in_window(
+                io_op_flags,
+                io_error_flags,
+                target_timestamp,
+                window,
+            ),
         }
     }
 
@@ -288,6 +382,8 @@ impl Nexus {
             if child.bdev.as_ref().unwrap().as_ptr() as *const _ == bdev {
                 if child.state == ChildState::Open {
                     if child.err_store.is_some() {
+                        let opts = &Config::get().err_store_opts;
+                        let exceeded = Self::assess_child(&child, opts);
                         child.err_store.as_mut().unwrap().add_record(
                             io_op_type,
                             io_error_type,
@@ -295,23 +391,24 @@ impl Nexus {
                             io_num_blocks,
                             now,
                         );
-                        let cfg = Config::get();
-                        if cfg.err_store_opts.action == ActionType::Fault
-                            && !Self::assess_child(
-                                &child,
-                                cfg.err_store_opts.max_errors,
-                                cfg.err_store_opts.retention_ns,
-                                QueryType::Total,
-                            )
-                        {
-                            let child_name = child.name.clone();
-                            info!("Faulting child {}", child_name);
-                            if nexus.fault_child(&child_name).await.is_err() {
-                                error!(
-                                    "Failed to fault the child {}",
-                                    child_name,
-                                );
+                        if opts.action == ActionType::Ignore {
+                            return;
+                        }
+                        if let Some(reason) = Self::assess_child(&child, opts) {
+                            // warn only once when a limit is first exceeded
+                            if opts.action == ActionType::Warn
+                                && exceeded.is_some()
+                            {
+                                return;
                             }
+                            let child_name = child.name.clone();
+                            nexus
+                                .child_errors_exceeded(
+                                    &child_name,
+                                    opts.action.clone(),
+                                    reason,
+                                )
+                                .await;
                         }
                     } else {
                         let child_name = child.name.clone();
@@ -372,24 +469,139 @@ impl Nexus {
         }
     }
 
-    // Returns false if the child is deemed faulted. This is determined by
-    // by the number, type and time stamp of the errors stored in the error
-    // store, filtered by the passed-in flags and compared against a
-    // configurable limit.
-    fn assess_child(
-        child: &NexusChild,
-        max_errors: u32,
-        retention_ns: u64,
-        query_type: QueryType,
-    ) -> bool {
-        let earliest_time =
-            Instant::now().checked_sub(Duration::from_nanos(retention_ns));
-        let res = child.err_store.as_ref().unwrap().query(
-            NexusErrStore::READ_FLAG | NexusErrStore::WRITE_FLAG,
-            NexusErrStore::IO_FAILED_FLAG,
-            earliest_time, // can be None
-            query_type,
-        );
-        res <= max_errors
+    pub fn error_record_list(
+        &self,
+        child_name: &str,
+        io_op_flags: u32,
+        io_error_flags: u32,
+        age_nano: Option<u64>, // None for any age
+    ) -> Result<Vec<NexusChildErrorRecord>, nexus_bdev::Error> {
+        let earliest_time = match age_nano {
+            // can also be None if earlier than the node has been up
+            Some(a) => Instant::now().checked_sub(Duration::from_nanos(a)),
+            None => None,
+        };
+        let cfg = Config::get();
+        if !cfg.err_store_opts.enable_err_store {
+            return Err(ErrStoreDisabled {
+                name: self.name.clone(),
+            });
+        }
+        match self.children.iter().find(|c| c.name == child_name) {
+            Some(child) => match child.err_store.as_ref() {
+                Some(store) => {
+                    Ok(store.list(io_op_flags, io_error_flags, earliest_time))
+                }
+                None => Err(ChildMissingErrStore {
+                    child: child_name.to_string(),
+                    name: self.name.clone(),
+                }),
+            },
+            None => Err(ChildMissing {
+                child: child_name.to_string(),
+                name: self.name.clone(),
+            }),
+        }
+    }
+
+    // Returns the reason if the child has exceeded any of the configured
+    // limits. This is determined by the number, type and time stamp of the
+    // errors stored in the error store, and optionally by the highest number
+    // of errors that occurred within a window of the configured length.
+    fn assess_child(child: &NexusChild, opts: &ErrStoreOpts) -> Option<String> {
+        let store = child.err_store.as_ref().unwrap();
+        let earliest_time = Instant::now() // can be None
+            .checked_sub(Duration::from_nanos(opts.retention_ns));
+
+        let limits = [
+            (
+                "errors",
+                NexusErrStore::READ_FLAG | NexusErrStore::WRITE_FLAG,
+                Some(opts.max_errors),
+            ),
+            (
+                "read errors",
+                NexusErrStore::READ_FLAG,
+                opts.max_read_errors,
+            ),
+            (
+                "write errors",
+                NexusErrStore::WRITE_FLAG,
+                opts.max_write_errors,
+            ),
+        ];
+        for (kind, io_op_flags, max_errors) in limits.iter() {
+            if let Some(max_errors) = max_errors {
+                let res = store.query(
+                    *io_op_flags,
+                    NexusErrStore::IO_FAILED_FLAG,
+                    earliest_time,
+                    QueryType::Total,
+                );
+                if res > *max_errors {
+                    return Some(format!(
+                        "{} {} exceed the limit of {}",
+                        res, kind, max_errors
+                    ));
+                }
+            }
+        }
+
+        if opts.rate_window_ns > 0 {
+            let window = Duration::from_nanos(opts.rate_window_ns);
+            let res = store.query(
+                NexusErrStore::READ_FLAG | NexusErrStore::WRITE_FLAG,
+                NexusErrStore::IO_FAILED_FLAG,
+                earliest_time,
+                QueryType::MaxRate(window),
+            );
+            if res > opts.max_errors_per_window {
+                return Some(format!(
+                    "{} errors within {:?} exceed the limit of {}",
+                    res, window, opts.max_errors_per_window
+                ));
+            }
+        }
+        None
+    }
+
+    // Carry out the configured action on the child which has exceeded an
+    // error limit and let the subscribers know about it.
+    async fn child_errors_exceeded(
+        &mut self,
+        child_name: &str,
+        action: ActionType,
+        reason: String,
+    ) {
+        match action {
+            ActionType::Ignore => return,
+            ActionType::Fault => {
+                info!("Faulting child {}: {}", child_name, reason);
+                if self.fault_child(child_name).await.is_err() {
+                    error!("Failed to fault the child {}", child_name);
+                    return;
+                }
+            }
+            ActionType::Retire => {
+                info!("Retiring child {}: {}", child_name, reason);
+                if let Err(e) = self.retire_child(child_name).await {
+                    error!(
+                        "Failed to retire the child {}: {}",
+                        child_name,
+                        e.verbose()
+                    );
+                    return;
+                }
+            }
+            ActionType::Warn => {
+                warn!("{}: child {}: {}", self.name, child_name, reason);
+            }
+        }
+        events::publish(EventKind::ChildErrors {
+            nexus: self.name.clone(),
+            child: child_name.to_string(),
+            action,
+            reason,
+        });
     }
 }
@@ -0,0 +1,427 @@
+//!
+//! Dirty region log (DRL) of a nexus child.
+//!
+//! When a child is taken out of the IO path (offlined or faulted) the nexus
+//! keeps on writing to the remaining children. Rather than rebuilding the
+//! whole child when it comes back, every write which the child misses is
+//! recorded in a coarse bitmap of fixed sized regions. When the child is
+//! onlined again only the regions marked as dirty are copied by the rebuild.
+//!
+//! Marking is done from the IO path on any core, so the bitmap is made of
+//! atomic words and the log is shared between the child, its rebuild job and
+//! the IO channels by means of an `Arc`. The channels pick up the logs when
+//! they are refreshed, which is part of taking a child out of the IO path.
+//!
+//! The log is persisted on the "MayaMeta" partition of the healthy children
+//! whenever a child drops out of or comes back into the IO path. It is not
+//! rewritten as further regions go dirty, so a log written while the nexus
+//! takes writes only tells that the child is stale and it is rebuilt in
+//! full. Once the nexus is destroyed no write can be missed anymore, the
+//! logs written then are marked as clean and these allow the partial
+//! rebuild after a restart.
+
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::SystemTime,
+};
+
+use crate::{
+    bdev::{
+        nexus::{
+            nexus_bdev::Nexus,
+            nexus_child::{ChildState, ChildStatus, NexusChild},
+            nexus_metadata::{MetaDataError, NexusMetaData},
+            nexus_metadata_content::{NexusConfig, NexusDirtyLog},
+        },
+        VerboseError,
+    },
+    rebuild::{RebuildJob, SEGMENT_SIZE},
+};
+
+/// Upper bound on the number of regions tracked per child, which bounds the
+/// size of the log stored on the MayaMeta partition to 8KiB
+const DIRTY_LOG_MAX_REGIONS: u64 = 64 * 1024;
+
+const BITS: u64 = 64;
+
+/// Bitmap of the regions of the nexus data partition that have been written
+/// to while a child was out of the IO path
+#[derive(Debug)]
+pub struct DirtyLog {
+    /// number of blocks of the nexus covered by the log
+    num_blocks: u64,
+    /// size of each region in blocks
+    region_blks: u64,
+    /// one bit per region
+    bitmap: Vec<AtomicU64>,
+}
+
+impl DirtyLog {
+    /// Create a new empty log covering `num_blocks` of the nexus. The region
+    /// size is always a multiple of the rebuild segment size so that a
+    /// rebuild segment never straddles two regions.
+    pub fn new(num_blocks: u64, block_size: u64) -> Self {
+        let segment_blks = std::cmp::max(SEGMENT_SIZE / block_size, 1);
+        let min_region_blks =
+            (num_blocks + DIRTY_LOG_MAX_REGIONS - 1) / DIRTY_LOG_MAX_REGIONS;
+        let region_blks = std::cmp::max(
+            (min_region_blks + segment_blks - 1) / segment_blks * segment_blks,
+            segment_blks,
+        );
+
+        Self::with_bitmap(num_blocks, region_blks, Vec::new())
+    }
+
+    fn with_bitmap(num_blocks: u64, region_blks: u64, words: Vec<u64>) -> Self {
+        let regions = (num_blocks + region_blks - 1) / region_blks;
+        let words_needed = ((regions + BITS - 1) / BITS) as usize;
+        let mut bitmap = words
+            .into_iter()
+            .take(words_needed)
+            .map(AtomicU64::new)
+            .collect::<Vec<_>>();
+        bitmap.resize_with(words_needed, || AtomicU64::new(0));
+
+        Self {
+            num_blocks,
+            region_blks,
+            bitmap,
+        }
+    }
+
+    /// Mark the regions covered by the given nexus block range as dirty
+    pub fn mark(&self, offset: u64, num_blocks: u64) {
+        if num_blocks == 0 || offset >= self.num_blocks {
+            return;
+        }
+
+        let last = std::cmp::min(offset + num_blocks, self.num_blocks) - 1;
+        for region in offset / self.region_blks ..= last / self.region_blks {
+            self.bitmap[(region / BITS) as usize]
+                .fetch_or(1 << (region % BITS), Ordering::Relaxed);
+        }
+    }
+
+    /// Returns true if the region is dirty
+    fn is_dirty(&self, region: u64) -> bool {
+        self.bitmap[(region / BITS) as usize].load(Ordering::Relaxed)
+            & (1 << (region % BITS))
+            != 0
+    }
+
+    /// Returns the first block at or after `blk` which belongs to a dirty
+    /// region, if any
+    pub fn next_dirty(&self, blk: u64) -> Option<u64> {
+        let regions =
+            (self.num_blocks + self.region_blks - 1) / self.region_blks;
+        let first = blk / self.region_blks;
+
+        (first .. regions).find(|r| self.is_dirty(*r)).map(|r| {
+            if r == first {
+                blk
+            } else {
+                r * self.region_blks
+            }
+        })
+    }
+
+    /// Number of blocks covered by dirty regions
+    pub fn dirty_blocks(&self) -> u64 {
+        self.dirty_blocks_from(0)
+    }
+
+    /// Number of blocks at or after `blk` covered by dirty regions
+    pub fn dirty_blocks_from(&self, blk: u64) -> u64 {
+        let regions =
+            (self.num_blocks + self.region_blks - 1) / self.region_blks;
+
+        (blk / self.region_blks .. regions)
+            .filter(|r| self.is_dirty(*r))
+            .map(|r| {
+                // the last region may be shorter than the others
+                let end =
+                    std::cmp::min((r + 1) * self.region_blks, self.num_blocks);
+                end - std::cmp::max(r * self.region_blks, blk)
+            })
+            .sum()
+    }
+
+    /// Convert the log into its on-disk representation
+    pub fn to_config(&self, child: &str, clean: bool) -> NexusDirtyLog {
+        NexusDirtyLog {
+            child: child.to_string(),
+            clean,
+            num_blocks: self.num_blocks,
+            region_blks: self.region_blks,
+            bitmap: self
+                .bitmap
+                .iter()
+                .map(|w| w.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+
+    /// Construct a log from its on-disk representation
+    pub fn from_config(config: &NexusDirtyLog) -> Option<Self> {
+        if config.region_blks == 0 {
+            return None;
+        }
+        Some(Self::with_bitmap(
+            config.num_blocks,
+            config.region_blks,
+            config.bitmap.clone(),
+        ))
+    }
+}
+
+impl NexusChild {
+    /// Start recording the writes this child misses, unless already doing so
+    pub(crate) fn track_writes(&mut self, num_blocks: u64, block_size: u64) {
+        if self.dirty_log.is_none() {
+            self.dirty_log =
+                Some(Arc::new(DirtyLog::new(num_blocks, block_size)));
+        }
+    }
+
+    /// Replace all dirty logs stored on the MayaMeta partition of this child
+    /// with the given list
+    async fn write_dirty_logs(
+        &mut self,
+        logs: &[NexusConfig],
+    ) -> Result<(), MetaDataError> {
+        let mut metadata = match self.get_metadata().await {
+            Ok(metadata) => metadata,
+            Err(_) => self.create_metadata().await?,
+        };
+
+        self.delete_dirty_logs(&mut metadata).await?;
+
+        let now = SystemTime::now();
+        for log in logs {
+            self.append_config_object(&mut metadata, log, &now).await?;
+        }
+        Ok(())
+    }
+
+    /// Remove all dirty log objects from the MayaMeta partition
+    async fn delete_dirty_logs(
+        &mut self,
+        metadata: &mut NexusMetaData,
+    ) -> Result<(), MetaDataError> {
+        let objects = self.probe_all_config_objects(metadata).await?;
+        for (i, _) in objects
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, o)| matches!(o, NexusConfig::DirtyLog(_)))
+        {
+            self.delete_config_object(metadata, i as u32).await?;
+        }
+        Ok(())
+    }
+
+    /// Read all the dirty logs stored on the MayaMeta partition
+    async fn read_dirty_logs(
+        &self,
+    ) -> Result<Vec<NexusDirtyLog>, MetaDataError> {
+        let metadata = self.get_metadata().await?;
+        Ok(self
+            .probe_all_config_objects(&metadata)
+            .await?
+            .into_iter()
+            .filter_map(|o| match o {
+                NexusConfig::DirtyLog(log) => Some(log),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+impl Nexus {
+    /// The dirty logs of the children which are recording the writes they
+    /// miss, for the IO channels to mark
+    pub(crate) fn dirty_logs(&self) -> Vec<Arc<DirtyLog>> {
+        self.children
+            .iter()
+            .filter_map(|c| c.dirty_log.clone())
+            .collect()
+    }
+
+    /// Start recording the writes missed by child `name`
+    pub(crate) fn track_child_writes(&mut self, name: &str) {
+        let num_blocks = self.bdev.num_blocks();
+        let block_size = self.bdev.block_len() as u64;
+        if let Some(child) = self.children.iter_mut().find(|c| c.name == name) {
+            child.track_writes(num_blocks, block_size);
+        }
+    }
+
+    /// Write the dirty logs of all children to the MayaMeta partition of the
+    /// online children, replacing the logs previously stored there.
+    /// This is best effort, failures only cost us a full rebuild later on.
+    pub(crate) async fn persist_dirty_logs(&mut self) {
+        self.write_dirty_logs(false).await
+    }
+
+    /// Write the dirty logs as clean, which must only be done once the nexus
+    /// no longer takes any writes
+    pub(crate) async fn seal_dirty_logs(&mut self) {
+        self.write_dirty_logs(true).await
+    }
+
+    async fn write_dirty_logs(&mut self, clean: bool) {
+        let logs = self
+            .children
+            .iter()
+            .filter_map(|c| {
+                c.dirty_log
+                    .as_ref()
+                    .map(|l| NexusConfig::DirtyLog(l.to_config(&c.name, clean)))
+            })
+            .collect::<Vec<_>>();
+
+        for child in self
+            .children
+            .iter_mut()
+            .filter(|c| c.status() == ChildStatus::Online)
+        {
+            if let Err(e) = child.write_dirty_logs(&logs).await {
+                warn!(
+                    "{}: failed to persist dirty logs on child {}: {}",
+                    self.name, child.name, e
+                );
+            }
+        }
+    }
+
+    /// Read the dirty logs stored on the online children. A child never
+    /// holds its own log, and of the copies of a log which are found the
+    /// one which is not clean, if any, is returned.
+    async fn read_dirty_logs(&self) -> Vec<NexusDirtyLog> {
+        let mut logs: Vec<NexusDirtyLog> = Vec::new();
+        for child in self
+            .children
+            .iter()
+            .filter(|c| c.status() == ChildStatus::Online)
+        {
+            match child.read_dirty_logs().await {
+                Ok(found) => {
+                    for log in
+                        found.into_iter().filter(|l| l.child != child.name)
+                    {
+                        match logs.iter_mut().find(|o| o.child == log.child) {
+                            Some(o) if o.clean && !log.clean => *o = log,
+                            Some(_) => {}
+                            None => logs.push(log),
+                        }
+                    }
+                }
+                Err(e) => {
+                    debug!(
+                        "{}: failed to read dirty logs from child {}: {}",
+                        self.name, child.name, e
+                    );
+                }
+            }
+        }
+        logs
+    }
+
+    /// Names of the children of which a dirty log is stored on the online
+    /// children, these missed writes while out of the IO path
+    pub(crate) async fn stale_children(&self) -> Vec<String> {
+        self.read_dirty_logs()
+            .await
+            .into_iter()
+            .map(|l| l.child)
+            .filter(|name| self.children.iter().any(|c| &c.name == name))
+            .collect()
+    }
+
+    /// Mark the children which missed writes while they were out of the IO
+    /// path as out of sync, even if they could be opened again. This has to
+    /// be done before the nexus takes any IO.
+    pub(crate) async fn mark_stale_children(&mut self) {
+        for name in self.stale_children().await {
+            if let Some(child) = self
+                .children
+                .iter_mut()
+                .find(|c| c.name == name && c.status() == ChildStatus::Online)
+            {
+                info!("{}: child {} is stale", self.name, name);
+                child.out_of_sync(true);
+            }
+        }
+    }
+
+    /// Rebuild the stale children, from their dirty log if a clean one is
+    /// found, which are not already being rebuilt from a checkpoint
+    pub(crate) async fn rebuild_stale_children(&mut self) {
+        let stale = self
+            .children
+            .iter()
+            .filter(|c| {
+                c.state == ChildState::Open
+                    && c.status() == ChildStatus::Degraded
+                    && RebuildJob::lookup(&c.name).is_err()
+            })
+            .map(|c| c.name.clone())
+            .collect::<Vec<_>>();
+
+        for name in stale {
+            info!("{}: rebuilding stale child {}", self.name, name);
+            self.restore_dirty_log(&name).await;
+            if let Err(e) = self.start_rebuild(&name).await {
+                error!(
+                    "{}: failed to rebuild child {}: {}",
+                    self.name,
+                    name,
+                    e.verbose()
+                );
+            }
+        }
+
+        // the logs on disk miss the writes from here on
+        self.persist_dirty_logs().await;
+    }
+
+    /// Load the dirty log of child `name` from the MayaMeta partition of the
+    /// online children, if the child is not already tracking its writes.
+    /// Only a clean log can be trusted, without one the child is rebuilt in
+    /// full.
+    pub(crate) async fn restore_dirty_log(&mut self, name: &str) {
+        let num_blocks = self.bdev.num_blocks();
+
+        match self.children.iter().find(|c| c.name == name) {
+            Some(child) if child.dirty_log.is_none() => {}
+            _ => return,
+        }
+
+        let restored = self
+            .read_dirty_logs()
+            .await
+            .into_iter()
+            .find(|l| l.child == name);
+
+        // a log for a differently sized nexus is of no use to us
+        if let Some(log) = restored
+            .filter(|l| l.clean && l.num_blocks == num_blocks)
+            .and_then(|l| DirtyLog::from_config(&l))
+        {
+            info!(
+                "{}: restored dirty log of child {} from metadata",
+                self.name, name
+            );
+            if let Some(child) =
+                self.children.iter_mut().find(|c| c.name == name)
+            {
+                child.dirty_log = Some(Arc::new(log));
+            }
+            // the copies on disk miss the writes from here on
+            self.persist_dirty_logs().await;
+        }
+    }
+}
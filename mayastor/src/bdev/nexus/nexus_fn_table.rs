@@ -18,7 +18,7 @@ use crate::bdev::nexus::{
     instances,
     nexus_bdev::Nexus,
     nexus_channel::NexusChannel,
-    nexus_io::{io_type, Bio},
+    nexus_io::{io_status, io_type, Bio},
 };
 
 static NEXUS_FN_TBL: Lazy<NexusFnTable> = Lazy::new(NexusFnTable::new);
@@ -96,6 +96,12 @@ impl NexusFnTable {
         if let Some(io_type) = Bio::io_type(io) {
             let mut nio = Bio(io);
             let mut ch = NexusChannel::inner_from_channel(channel);
+
+            // the IO context is not zeroed when the IO is allocated
+            let ctx = nio.ctx_as_mut_ref();
+            ctx.status = io_status::SUCCESS;
+            ctx.timed_out = std::ptr::null();
+
             let nexus = nio.nexus_as_ref();
 
             match io_type {
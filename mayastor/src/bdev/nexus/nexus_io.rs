@@ -3,7 +3,12 @@ use std::fmt::{Debug, Formatter};
 
 use libc::c_void;
 
-use spdk_sys::{spdk_bdev_free_io, spdk_bdev_io, spdk_bdev_io_complete};
+use spdk_sys::{
+    spdk_bdev,
+    spdk_bdev_free_io,
+    spdk_bdev_io,
+    spdk_bdev_io_complete,
+};
 
 use crate::{
     bdev::nexus::nexus_bdev::{Nexus, NEXUS_PRODUCT_ID},
@@ -17,6 +22,20 @@ pub struct NioCtx {
     pub(crate) in_flight: i8,
     /// status of the IO
     pub(crate) status: i32,
+    /// index of the child channel a read was submitted to
+    pub(crate) read_child: usize,
+    /// time in ticks at which a read was submitted
+    pub(crate) submitted: u64,
+    /// number of times a failed read has been retried on another child
+    pub(crate) read_retries: u8,
+    /// number of children a write was sent to
+    pub(crate) children: i8,
+    /// number of children which failed a write
+    pub(crate) failed: i8,
+    /// number of children read from which completed a write
+    pub(crate) written: i8,
+    /// child bdev on which the IO has been aborted after timing out
+    pub(crate) timed_out: *const spdk_bdev,
 }
 
 /// BIO is a wrapper to provides a "less unsafe" wrappers around raw
@@ -227,6 +227,22 @@ impl Nexus {
         }
     }
 
+    /// Generate a label spanning the current size of the children which
+    /// keeps the GUIDs and the partition layout of the given label, only the
+    /// data partition is extended to the end of the children.
+    pub(crate) fn grow_label(&mut self, current: &NexusLabel) -> NexusLabel {
+        let mut label = self.generate_label();
+
+        for (new, old) in label.partitions.iter_mut().zip(&current.partitions) {
+            new.ent_guid = old.ent_guid;
+        }
+
+        label.primary.table_crc = GptEntry::checksum(&label.partitions);
+        label.primary.checksum();
+        label.secondary = label.primary.to_backup();
+        label
+    }
+
     fn get_primary_data(
         &self,
         label: &NexusLabel,
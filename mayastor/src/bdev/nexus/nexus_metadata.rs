@@ -16,7 +16,7 @@
 //! ## Example
 //! Sample code to create a new index and add a config object:
 //!
-//!    let config: NexusConfig = NexusConfig::Version1(NexusConfigVersion1 {
+//!    let config: NexusConfig = NexusConfig::Topology(NexusTopology {
 //!       ...
 //!    });
 //!    let now = SystemTime::now();
@@ -1,43 +1,65 @@
 //! Definitions of objects that may be stored on the "MayaMeta" partition.
-//! Note that the definitions provided here are purely for demonstration
-//! (and testing) purposes at present.
-//! The intent is that these structures will define precisely what
-//! content is to be stored on the "MayaMeta" partition.
-use std::collections::HashMap;
+use std::time::SystemTime;
 
 use serde::{Deserialize, Serialize};
 
+use crate::bdev::nexus::nexus_child::ChildStatus;
+
+/// Dirty region log of a child which is out of sync with the nexus,
+/// see `nexus_dirty_log`
 #[derive(Debug, Deserialize, PartialEq, Default, Serialize, Clone)]
-pub struct NexusConfigVersion1 {
-    pub name: String,
-    pub tags: Vec<String>,
-    pub revision: u32,
-    pub checksum: u32,
-    pub data: String,
+pub struct NexusDirtyLog {
+    pub child: String,
+    /// written once the nexus stopped taking writes, only then does the log
+    /// hold all the writes the child missed
+    pub clean: bool,
+    pub num_blocks: u64,
+    pub region_blks: u64,
+    pub bitmap: Vec<u64>,
 }
 
+/// How far the rebuild of a child got, see `nexus_rebuild_checkpoint`
 #[derive(Debug, Deserialize, PartialEq, Default, Serialize, Clone)]
-pub struct NexusConfigVersion2 {
-    pub name: String,
-    pub tags: Vec<String>,
-    pub revision: u32,
-    pub checksum: u32,
-    pub data: String,
-    pub count: u16,
+pub struct NexusRebuildCheckpoint {
+    pub source: String,
+    pub destination: String,
+    pub num_blocks: u64,
+    pub next_blk: u64,
 }
 
-#[derive(Debug, Deserialize, PartialEq, Default, Serialize, Clone)]
-pub struct NexusConfigVersion3 {
-    pub name: String,
-    pub revision: u32,
-    pub checksum: u32,
-    pub data: Vec<String>,
+/// Protocol the nexus is shared with
+#[derive(Debug, Deserialize, PartialEq, Serialize, Clone, Copy)]
+pub enum NexusShareProtocol {
+    None,
+    Nbd,
+    Iscsi,
+    Nvmf,
+}
+
+/// A child as recorded in the topology of the nexus
+#[derive(Debug, Deserialize, PartialEq, Serialize, Clone)]
+pub struct NexusTopologyChild {
+    pub uri: String,
+    pub status: ChildStatus,
+    /// generation of the topology in which the status last changed
+    pub generation: u64,
+}
+
+/// Topology of the nexus, see `nexus_persistence`
+#[derive(Debug, Deserialize, PartialEq, Serialize, Clone)]
+pub struct NexusTopology {
+    pub uuid: String,
+    pub size: u64,
+    /// incremented every time the topology changes
+    pub generation: u64,
+    pub children: Vec<NexusTopologyChild>,
+    pub share: NexusShareProtocol,
+    pub created: SystemTime,
 }
 
 #[derive(Debug, Deserialize, PartialEq, Serialize, Clone)]
 pub enum NexusConfig {
-    Version1(NexusConfigVersion1),
-    Version2(NexusConfigVersion2),
-    Version3(NexusConfigVersion3),
-    Version4(HashMap<String, String>),
+    DirtyLog(NexusDirtyLog),
+    RebuildCheckpoint(NexusRebuildCheckpoint),
+    Topology(NexusTopology),
 }
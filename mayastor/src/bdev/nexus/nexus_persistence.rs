@@ -0,0 +1,198 @@
+//!
+//! Persistence of the topology of a nexus.
+//!
+//! Whenever the labels of the children are synced, a child changes state or
+//! the nexus is (un)shared, the topology of the nexus is written to the
+//! "MayaMeta" partition of the healthy children, replacing the one
+//! previously stored there. It records the uuid, the size, the children
+//! along with their status and the protocol the nexus is shared with, which
+//! is all that is needed to recover the nexus from the disks alone. The
+//! share torn down when the nexus is destroyed is not recorded, only an
+//! explicit unshare is.
+//!
+//! A child which drops out of the IO path keeps the topology written before
+//! it did, so where the copies differ the one of the highest generation is
+//! the current one.
+
+use std::time::SystemTime;
+
+use crate::bdev::nexus::{
+    nexus_bdev::Nexus,
+    nexus_child::{ChildStatus, NexusChild},
+    nexus_metadata::MetaDataError,
+    nexus_metadata_content::{
+        NexusConfig,
+        NexusShareProtocol,
+        NexusTopology,
+        NexusTopologyChild,
+    },
+};
+
+impl NexusChild {
+    /// Replace the topology stored on the MayaMeta partition of this child
+    async fn write_topology(
+        &mut self,
+        topology: &NexusConfig,
+    ) -> Result<(), MetaDataError> {
+        let mut metadata = match self.get_metadata().await {
+            Ok(metadata) => metadata,
+            Err(_) => self.create_metadata().await?,
+        };
+
+        // the new topology is appended before the old one is deleted so that
+        // the child is never left without one
+        let objects = self.probe_all_config_objects(&metadata).await?;
+        self.append_config_object(&mut metadata, topology, &SystemTime::now())
+            .await?;
+
+        for (i, _) in objects
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, o)| matches!(o, NexusConfig::Topology(_)))
+        {
+            self.delete_config_object(&mut metadata, i as u32).await?;
+        }
+        Ok(())
+    }
+
+    /// Read the topology of the nexus stored on the MayaMeta partition
+    pub async fn read_topology(
+        &self,
+    ) -> Result<Option<NexusTopology>, MetaDataError> {
+        let metadata = self.get_metadata().await?;
+        Ok(self
+            .probe_all_config_objects(&metadata)
+            .await?
+            .into_iter()
+            .rev()
+            .find_map(|o| match o {
+                NexusConfig::Topology(topology) => Some(topology),
+                _ => None,
+            }))
+    }
+}
+
+impl Nexus {
+    /// The topology last written to the children
+    pub fn topology(&self) -> Option<&NexusTopology> {
+        self.topology.as_ref()
+    }
+
+    /// Read the most recent topology of this nexus from the online children
+    pub async fn read_topology(&self) -> Option<NexusTopology> {
+        let uuid = self.bdev.uuid_as_string();
+        let mut latest: Option<NexusTopology> = None;
+
+        for child in self
+            .children
+            .iter()
+            .filter(|c| c.status() == ChildStatus::Online)
+        {
+            match child.read_topology().await {
+                Ok(Some(topology)) if topology.uuid == uuid => {
+                    if latest
+                        .as_ref()
+                        .map_or(true, |l| l.generation < topology.generation)
+                    {
+                        latest = Some(topology);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    debug!(
+                        "{}: failed to read topology from child {}: {}",
+                        self.name, child.name, e
+                    );
+                }
+            }
+        }
+        latest
+    }
+
+    /// Current topology of the nexus, or None if it has not changed since it
+    /// was last written to the children
+    fn next_topology(&self) -> Option<NexusTopology> {
+        let previous = self.topology.as_ref();
+        let generation = previous.map_or(0, |p| p.generation + 1);
+
+        let children = self
+            .children
+            .iter()
+            .map(|c| {
+                let status = c.status();
+                NexusTopologyChild {
+                    uri: c.name.clone(),
+                    status,
+                    generation: previous
+                        .and_then(|p| {
+                            p.children.iter().find(|o| o.uri == c.name)
+                        })
+                        .filter(|o| o.status == status)
+                        .map_or(generation, |o| o.generation),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let topology = NexusTopology {
+            uuid: self.bdev.uuid_as_string(),
+            size: self.size,
+            generation,
+            children,
+            share: self.share_protocol,
+            created: previous.map_or_else(SystemTime::now, |p| p.created),
+        };
+
+        match previous {
+            Some(p)
+                if p.uuid == topology.uuid
+                    && p.size == topology.size
+                    && p.children == topology.children
+                    && p.share == topology.share =>
+            {
+                None
+            }
+            _ => Some(topology),
+        }
+    }
+
+    /// Write the topology of the nexus to the MayaMeta partition of the
+    /// online children if it has changed. The first time round the
+    /// topology is picked up from the children, so that the creation time,
+    /// the generations and the share protocol carry on from where the nexus
+    /// left off.
+    pub(crate) async fn persist_topology(&mut self) {
+        if self.topology.is_none() {
+            self.topology = self.read_topology().await;
+            if let Some(topology) = &self.topology {
+                if self.nexus_target.is_none() {
+                    self.share_protocol = topology.share;
+                }
+            }
+        }
+
+        let topology = match self.next_topology() {
+            Some(topology) => topology,
+            None => return,
+        };
+        debug!(
+            "{}: persisting topology generation {}",
+            self.name, topology.generation
+        );
+
+        let config = NexusConfig::Topology(topology.clone());
+        for child in self
+            .children
+            .iter_mut()
+            .filter(|c| c.status() == ChildStatus::Online)
+        {
+            if let Err(e) = child.write_topology(&config).await {
+                warn!(
+                    "{}: failed to persist topology on child {}: {}",
+                    self.name, child.name, e
+                );
+            }
+        }
+        self.topology = Some(topology);
+    }
+}
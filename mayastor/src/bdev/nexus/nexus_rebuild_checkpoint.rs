@@ -0,0 +1,245 @@
+//!
+//! Rebuild checkpoints of a nexus.
+//!
+//! A running rebuild job records how far it got on the "MayaMeta" partition
+//! of the healthy children every so often. The destination child receives
+//! all the writes while it is being rebuilt, so everything before the
+//! checkpoint stays in sync. When the nexus is created again with the same
+//! children, after a restart or a destroy, the rebuild is resumed from the
+//! checkpoint rather than started all over again. Without its source, or
+//! once the nexus has been resized, the child is rebuilt in full instead.
+//! Either way the child is out of sync from the moment the nexus is opened.
+//!
+//! The checkpoint is dropped as soon as the job is done with, whichever way,
+//! with the exception of the nexus itself being destroyed.
+
+use std::time::SystemTime;
+
+use crate::{
+    bdev::{
+        nexus::{
+            nexus_bdev::Nexus,
+            nexus_child::{ChildState, ChildStatus, NexusChild},
+            nexus_metadata::MetaDataError,
+            nexus_metadata_content::{NexusConfig, NexusRebuildCheckpoint},
+        },
+        VerboseError,
+    },
+    rebuild::{RebuildJob, RebuildLimits},
+};
+
+impl NexusChild {
+    /// Replace the checkpoint of the rebuild of child `name` stored on the
+    /// MayaMeta partition of this child, or just remove it
+    async fn write_rebuild_checkpoint(
+        &mut self,
+        name: &str,
+        checkpoint: Option<&NexusConfig>,
+    ) -> Result<(), MetaDataError> {
+        let mut metadata = match self.get_metadata().await {
+            Ok(metadata) => metadata,
+            // nothing to remove
+            Err(_) if checkpoint.is_none() => return Ok(()),
+            Err(_) => self.create_metadata().await?,
+        };
+
+        // the new checkpoint goes in before the old ones go, so that there
+        // is always one to resume from
+        let objects = self.probe_all_config_objects(&metadata).await?;
+        if let Some(checkpoint) = checkpoint {
+            self.append_config_object(
+                &mut metadata,
+                checkpoint,
+                &SystemTime::now(),
+            )
+            .await?;
+        }
+
+        for (i, _) in objects.iter().enumerate().rev().filter(|(_, o)| {
+            matches!(o, NexusConfig::RebuildCheckpoint(c) if c.destination == name)
+        }) {
+            self.delete_config_object(&mut metadata, i as u32).await?;
+        }
+        Ok(())
+    }
+
+    /// Read all the rebuild checkpoints stored on the MayaMeta partition
+    async fn read_rebuild_checkpoints(
+        &self,
+    ) -> Result<Vec<NexusRebuildCheckpoint>, MetaDataError> {
+        let metadata = self.get_metadata().await?;
+        Ok(self
+            .probe_all_config_objects(&metadata)
+            .await?
+            .into_iter()
+            .filter_map(|o| match o {
+                NexusConfig::RebuildCheckpoint(c) => Some(c),
+                _ => None,
+            })
+            .collect())
+    }
+}
+
+impl Nexus {
+    /// Checkpoint of the given rebuild job of child `destination` in its
+    /// on-disk representation
+    pub(crate) fn rebuild_checkpoint(
+        &self,
+        job: &RebuildJob,
+        destination: &str,
+    ) -> NexusConfig {
+        NexusConfig::RebuildCheckpoint(NexusRebuildCheckpoint {
+            source: job.source.clone(),
+            destination: destination.to_string(),
+            num_blocks: self.bdev.num_blocks(),
+            next_blk: job.checkpoint(),
+        })
+    }
+
+    /// Write the checkpoint of the rebuild of child `name` to the MayaMeta
+    /// partition of the online children, or remove it from there.
+    /// This is best effort, failures only cost us some rebuild progress.
+    pub(crate) async fn persist_rebuild_checkpoint(
+        &mut self,
+        name: &str,
+        checkpoint: Option<&NexusConfig>,
+    ) {
+        for child in self
+            .children
+            .iter_mut()
+            .filter(|c| c.status() == ChildStatus::Online)
+        {
+            if let Err(e) =
+                child.write_rebuild_checkpoint(name, checkpoint).await
+            {
+                warn!(
+                    "{}: failed to persist rebuild checkpoint of {} on child {}: {}",
+                    self.name, name, child.name, e
+                );
+            }
+        }
+    }
+
+    /// Read the rebuild checkpoints found on the online children. A child
+    /// never holds the checkpoint of its own rebuild, and where the copies
+    /// differ the earliest one is the safe one to go with.
+    async fn read_rebuild_checkpoints(&self) -> Vec<NexusRebuildCheckpoint> {
+        let mut checkpoints: Vec<NexusRebuildCheckpoint> = Vec::new();
+        for child in self
+            .children
+            .iter()
+            .filter(|c| c.status() == ChildStatus::Online)
+        {
+            match child.read_rebuild_checkpoints().await {
+                Ok(found) => {
+                    for c in found
+                        .into_iter()
+                        .filter(|c| c.destination != child.name)
+                    {
+                        match checkpoints
+                            .iter_mut()
+                            .find(|o| o.destination == c.destination)
+                        {
+                            Some(o) => o.next_blk = o.next_blk.min(c.next_blk),
+                            None => checkpoints.push(c),
+                        }
+                    }
+                }
+                Err(e) => {
+                    debug!(
+                        "{}: failed to read rebuild checkpoints from child {}: {}",
+                        self.name, child.name, e
+                    );
+                }
+            }
+        }
+        checkpoints
+    }
+
+    /// Mark the children which were being rebuilt when the nexus went away
+    /// as out of sync, whether or not their rebuild can be resumed. This has
+    /// to be done before the nexus takes any IO.
+    pub(crate) async fn mark_checkpointed_children(&mut self) {
+        for checkpoint in self.read_rebuild_checkpoints().await {
+            if let Some(child) = self.children.iter_mut().find(|c| {
+                c.name == checkpoint.destination
+                    && c.status() == ChildStatus::Online
+            }) {
+                info!(
+                    "{}: child {} was being rebuilt",
+                    self.name, checkpoint.destination
+                );
+                child.out_of_sync(true);
+            }
+        }
+    }
+
+    /// Resume the rebuilds which were in progress when the nexus went away,
+    /// from the checkpoints found on the online children. A checkpoint is of
+    /// no use once its source is gone or the nexus has been resized, the
+    /// child is then rebuilt in full from any healthy child.
+    pub(crate) async fn resume_rebuilds(&mut self) {
+        let num_blocks = self.bdev.num_blocks();
+
+        for checkpoint in self.read_rebuild_checkpoints().await {
+            let name = checkpoint.destination.clone();
+            match self.children.iter().find(|c| c.name == name) {
+                Some(c)
+                    if c.state == ChildState::Open
+                        && c.status() == ChildStatus::Degraded => {}
+                _ => continue,
+            }
+
+            let resumable = checkpoint.num_blocks == num_blocks
+                && self.children.iter().any(|c| {
+                    c.name == checkpoint.source
+                        && c.status() == ChildStatus::Online
+                });
+
+            if resumable {
+                info!(
+                    "{}: resuming rebuild of child {} from block {}",
+                    self.name, name, checkpoint.next_blk
+                );
+                self.restore_dirty_log(&name).await;
+                match self
+                    .start_rebuild_from(
+                        &name,
+                        &[],
+                        RebuildLimits::default(),
+                        checkpoint.next_blk,
+                        false,
+                    )
+                    .await
+                {
+                    Ok(_) => continue,
+                    Err(e) => {
+                        error!(
+                            "{}: failed to resume rebuild of child {}: {}",
+                            self.name,
+                            name,
+                            e.verbose()
+                        );
+                    }
+                }
+            }
+
+            // the child is only partly rebuilt, so neither the checkpoint nor
+            // its dirty log tell what it misses
+            info!("{}: rebuilding child {} in full", self.name, name);
+            if let Some(child) =
+                self.children.iter_mut().find(|c| c.name == name)
+            {
+                child.dirty_log = None;
+            }
+            if let Err(e) = self.start_rebuild(&name).await {
+                error!(
+                    "{}: failed to rebuild child {}: {}",
+                    self.name,
+                    name,
+                    e.verbose()
+                );
+            }
+        }
+    }
+}
@@ -19,6 +19,7 @@ use crate::{
             ShareNvmfNexus,
         },
         nexus_iscsi::NexusIscsiTarget,
+        nexus_metadata_content::NexusShareProtocol,
         nexus_nbd::NbdDisk,
         nexus_nvmf::NexusNvmfTarget,
     },
@@ -151,14 +152,30 @@ impl Nexus {
             }
         };
         self.share_handle = Some(name);
+        self.share_protocol = match share_protocol {
+            ShareProtocolNexus::NexusNbd => NexusShareProtocol::Nbd,
+            ShareProtocolNexus::NexusIscsi => NexusShareProtocol::Iscsi,
+            ShareProtocolNexus::NexusNvmf => NexusShareProtocol::Nvmf,
+        };
+        self.persist_topology().await;
         Ok(device_id)
     }
 
-    /// Undo share operation on nexus. To the chain of bdevs are all claimed
+    /// Undo share operation on nexus. The nexus is no longer meant to be
+    /// shared, which is recorded in its topology.
+    pub async fn unshare(&mut self) -> Result<(), Error> {
+        self.unshare_target().await?;
+        self.share_protocol = NexusShareProtocol::None;
+        self.persist_topology().await;
+        Ok(())
+    }
+
+    /// Tear down the share of the nexus, leaving the protocol it is meant to
+    /// be shared with as it is. To the chain of bdevs are all claimed
     /// where the top-level dev is claimed by the subsystem that exports the
     /// bdev. As such, we must first destroy the share and move our way down
     /// from there.
-    pub async fn unshare(&mut self) -> Result<(), Error> {
+    pub(crate) async fn unshare_target(&mut self) -> Result<(), Error> {
         match self.nexus_target.take() {
             Some(NexusTarget::NbdDisk(disk)) => {
                 disk.destroy();
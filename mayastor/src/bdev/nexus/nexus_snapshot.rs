@@ -0,0 +1,149 @@
+//!
+//! Consistent snapshot of all children of a nexus.
+//!
+//! Writes to the nexus are quiesced by locking the LBA range of the whole
+//! nexus, which waits for the outstanding writes to complete and holds back
+//! the new ones, after which every child is snapshotted with the same time.
+//! Only the children which are replicas local to this node can be
+//! snapshotted, as the snapshot is taken through the lvol store of the
+//! replica. The others, typically replicas on other nodes, are reported back
+//! for the control plane to snapshot them on their node.
+
+use snafu::ResultExt;
+
+use crate::{
+    bdev::nexus::{
+        nexus_bdev::{Error, Nexus, NexusStatus, QuiesceNexus},
+        nexus_child::NexusChild,
+    },
+    core::{Bdev, RangeContext},
+    replica::Replica,
+    snapshot::{snapshot_time, Snapshot},
+};
+
+impl NexusChild {
+    /// Return the local replica backing this child, if any. A replica can be
+    /// opened either directly by its bdev name or through the loopback nvmf
+    /// target, in which case the uuid is the last component of the nqn.
+    fn local_replica(&self) -> Option<Replica> {
+        self.bdev
+            .as_ref()
+            .and_then(|bdev| Replica::lookup(&bdev.name()))
+            .or_else(|| {
+                self.name
+                    .rsplit(|c| c == '/' || c == ':')
+                    .next()
+                    .and_then(Replica::lookup)
+            })
+    }
+}
+
+/// Snapshots of a nexus
+pub struct NexusSnapshot {
+    /// the snapshots of the children which are local replicas
+    pub snapshots: Vec<Snapshot>,
+    /// the children which have not been snapshotted
+    pub remote: Vec<String>,
+}
+
+impl Nexus {
+    /// Create a snapshot of every child of the nexus which is a local
+    /// replica. The snapshots share the same creation time and capture the
+    /// same content, as no write can hit the nexus while they are taken. If
+    /// any snapshot fails, those already created are destroyed again.
+    pub async fn create_snapshot(&self) -> Result<NexusSnapshot, Error> {
+        if self.status() != NexusStatus::Online {
+            return Err(Error::SnapshotNotOnline {
+                name: self.name.clone(),
+            });
+        }
+
+        let mut replicas = Vec::new();
+        let mut remote = Vec::new();
+        for child in &self.children {
+            match child.local_replica() {
+                Some(replica) => replicas.push((child.name.clone(), replica)),
+                None => remote.push(child.name.clone()),
+            }
+        }
+        if replicas.is_empty() {
+            return Ok(NexusSnapshot {
+                snapshots: Vec::new(),
+                remote,
+            });
+        }
+
+        let descriptor =
+            Bdev::open_by_name(&self.name, false).map_err(|_| {
+                Error::NexusNotFound {
+                    name: self.name.clone(),
+                }
+            })?;
+        let ch = descriptor
+            .get_channel()
+            .expect("Failed to get nexus channel");
+
+        // The RangeContext must outlive both the lock and the unlock as they
+        // reference it by a raw pointer.
+        let mut ctx = RangeContext::new(0, self.bdev.num_blocks());
+        descriptor.lock_lba_range(&mut ctx, &ch).await.context(
+            QuiesceNexus {
+                name: self.name.clone(),
+            },
+        )?;
+
+        let created = snapshot_time();
+        let mut snapshots = Vec::new();
+        let mut result = Ok(());
+        for (child, replica) in &replicas {
+            match Snapshot::create(replica, created).await {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(source) => {
+                    result = Err(Error::CreateSnapshot {
+                        source,
+                        child: child.clone(),
+                        name: self.name.clone(),
+                    });
+                    break;
+                }
+            }
+        }
+
+        // the snapshots are cleaned up whichever of the two failed
+        let unlocked = descriptor
+            .unlock_lba_range(&mut ctx, &ch)
+            .await
+            .context(QuiesceNexus {
+                name: self.name.clone(),
+            });
+        match unlocked {
+            Err(e) if result.is_ok() => result = Err(e),
+            Err(e) => error!("{}: failed to resume IO: {}", self.name, e),
+            Ok(()) => {}
+        }
+
+        if let Err(e) = result {
+            for snapshot in snapshots {
+                let name = snapshot.get_name().to_owned();
+                if let Err(error) = snapshot.destroy().await {
+                    error!(
+                        "{}: failed to destroy partial snapshot {}: {}",
+                        self.name, name, error
+                    );
+                }
+            }
+            return Err(e);
+        }
+
+        info!(
+            "{}: created snapshots of {} children, {} are not local",
+            self.name,
+            snapshots.len(),
+            remote.len()
+        );
+        Ok(NexusSnapshot {
+            snapshots,
+            remote,
+        })
+    }
+}
@@ -2,6 +2,7 @@ use crate::{context::Context, parse_size};
 use ::rpc::mayastor as rpc;
 use byte_unit::Byte;
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tonic::{Code, Status};
 
 pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
@@ -25,6 +26,27 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .multiple(true)
                 .index(3)
                 .help("list of children to add"),
+        )
+        .arg(
+            Arg::with_name("read-policy")
+                .short("r")
+                .long("read-policy")
+                .value_name("POLICY")
+                .possible_values(&[
+                    "round_robin",
+                    "least_outstanding",
+                    "lowest_latency",
+                    "prefer_local",
+                ])
+                .help("policy used to select the child to read from"),
+        )
+        .arg(
+            Arg::with_name("write-policy")
+                .short("w")
+                .long("write-policy")
+                .value_name("POLICY")
+                .possible_values(&["all", "quorum"])
+                .help("policy used to decide when a write has succeeded"),
         );
 
     let destroy = SubCommand::with_name("destroy")
@@ -36,6 +58,21 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .help("uuid for the nexus"),
         );
 
+    let resize = SubCommand::with_name("resize")
+        .about("grow the nexus to the given size")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid for the nexus"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .required(true)
+                .index(2)
+                .help("new size in mb"),
+        );
+
     let publish = SubCommand::with_name("publish")
         .about("publish the nexus")
         .arg(Arg::with_name("protocol").short("p").long("protocol").value_name("PROTOCOL")
@@ -109,6 +146,47 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .help("uuid of nexus"),
         );
 
+    let stats = SubCommand::with_name("stats")
+        .about("IO stats of the nexus and its children")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        );
+
+    let errors = SubCommand::with_name("errors")
+        .about("list failed IOs recorded in the error store of a child")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of nexus"),
+        )
+        .arg(
+            Arg::with_name("uri")
+                .required(true)
+                .index(2)
+                .help("uri of the child"),
+        )
+        .arg(
+            Arg::with_name("op")
+                .short("o")
+                .long("op")
+                .value_name("OP")
+                .multiple(true)
+                .use_delimiter(true)
+                .possible_values(&["read", "write", "unmap", "flush", "reset"])
+                .help("types of IO to list (default: all)"),
+        )
+        .arg(
+            Arg::with_name("age")
+                .short("a")
+                .long("age")
+                .value_name("SECONDS")
+                .help("list only the errors which are not older"),
+        );
+
     SubCommand::with_name("nexus")
         .settings(&[
             AppSettings::SubcommandRequiredElseHelp,
@@ -118,12 +196,15 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .about("Nexus device management")
         .subcommand(create)
         .subcommand(destroy)
+        .subcommand(resize)
         .subcommand(publish)
         .subcommand(add)
         .subcommand(remove)
         .subcommand(unpublish)
         .subcommand(list)
         .subcommand(children)
+        .subcommand(stats)
+        .subcommand(errors)
 }
 
 pub async fn handler(
@@ -133,8 +214,11 @@ pub async fn handler(
     match matches.subcommand() {
         ("create", Some(args)) => nexus_create(ctx, &args).await,
         ("destroy", Some(args)) => nexus_destroy(ctx, &args).await,
+        ("resize", Some(args)) => nexus_resize(ctx, &args).await,
         ("list", Some(args)) => nexus_list(ctx, &args).await,
         ("children", Some(args)) => nexus_children(ctx, &args).await,
+        ("stats", Some(args)) => nexus_stats(ctx, &args).await,
+        ("errors", Some(args)) => nexus_errors(ctx, &args).await,
         ("publish", Some(args)) => nexus_publish(ctx, &args).await,
         ("unpublish", Some(args)) => nexus_unpublish(ctx, &args).await,
         ("add", Some(args)) => nexus_add(ctx, &args).await,
@@ -158,6 +242,28 @@ async fn nexus_create(
         .split_whitespace()
         .map(|c| c.to_string())
         .collect::<Vec<String>>();
+    let read_policy = match matches.value_of("read-policy") {
+        None | Some("round_robin") => rpc::ReadPolicy::ReadRoundRobin,
+        Some("least_outstanding") => rpc::ReadPolicy::ReadLeastOutstanding,
+        Some("lowest_latency") => rpc::ReadPolicy::ReadLowestLatency,
+        Some("prefer_local") => rpc::ReadPolicy::ReadPreferLocal,
+        Some(_) => {
+            return Err(Status::new(
+                Code::Internal,
+                "Invalid value of read policy".to_owned(),
+            ));
+        }
+    };
+    let write_policy = match matches.value_of("write-policy") {
+        None | Some("all") => rpc::WritePolicy::WriteAll,
+        Some("quorum") => rpc::WritePolicy::WriteQuorum,
+        Some(_) => {
+            return Err(Status::new(
+                Code::Internal,
+                "Invalid value of write policy".to_owned(),
+            ));
+        }
+    };
 
     ctx.v2(&format!(
         "Creating nexus {} of size {} ",
@@ -171,6 +277,8 @@ async fn nexus_create(
             uuid: uuid.clone(),
             size,
             children,
+            read_policy: read_policy.into(),
+            write_policy: write_policy.into(),
         })
         .await?;
     ctx.v1(&format!("Nexus {} created", uuid));
@@ -193,6 +301,26 @@ async fn nexus_destroy(
     Ok(())
 }
 
+async fn nexus_resize(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let size = parse_size(matches.value_of("size").unwrap())
+        .map_err(|s| Status::invalid_argument(format!("Bad size '{}'", s)))?;
+
+    ctx.v2(&format!("Resizing nexus {} to {}", uuid, ctx.units(size)));
+    let size = size.get_bytes() as u64;
+    ctx.client
+        .resize_nexus(rpc::ResizeNexusRequest {
+            uuid: uuid.clone(),
+            size,
+        })
+        .await?;
+    ctx.v1(&format!("Nexus {} resized", uuid));
+    Ok(())
+}
+
 async fn nexus_list(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
@@ -273,6 +401,144 @@ async fn nexus_children(
     Ok(())
 }
 
+async fn nexus_stats(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    ctx.v2(&format!("Requesting stats of nexus {}", uuid));
+    let resp = ctx
+        .client
+        .stat_nexus(rpc::StatNexusRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    let reply = resp.get_ref();
+
+    // average latency of an operation in microseconds
+    let latency = |total: u64, ops: u64| {
+        if ops == 0 {
+            "-".to_string()
+        } else {
+            (total / ops).to_string()
+        }
+    };
+    let row = |name: &str, stats: Option<&rpc::Stats>, errors: Vec<String>| {
+        let mut row = vec![name.to_string()];
+        match stats {
+            Some(s) => row.extend(vec![
+                s.num_read_ops.to_string(),
+                s.num_write_ops.to_string(),
+                ctx.units(Byte::from_bytes(s.bytes_read.into())),
+                ctx.units(Byte::from_bytes(s.bytes_written.into())),
+                latency(s.read_latency_us, s.num_read_ops),
+                latency(s.write_latency_us, s.num_write_ops),
+            ]),
+            None => row.extend(vec!["-".to_string(); 6]),
+        }
+        row.extend(errors);
+        row
+    };
+
+    let mut table = vec![row(
+        &reply.uuid,
+        reply.stats.as_ref(),
+        vec!["-".to_string(), "-".to_string()],
+    )];
+    for c in &reply.children {
+        table.push(row(
+            &c.uri,
+            c.stats.as_ref(),
+            vec![c.read_errors.to_string(), c.write_errors.to_string()],
+        ));
+    }
+    ctx.print_list(
+        vec![
+            "NAME",
+            ">RDCNT",
+            ">WRCNT",
+            ">RDBYTES",
+            ">WRBYTES",
+            ">RDLAT_US",
+            ">WRLAT_US",
+            ">RDERR",
+            ">WRERR",
+        ],
+        table,
+    );
+    Ok(())
+}
+
+async fn nexus_errors(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let uri = matches.value_of("uri").unwrap().to_string();
+    // the flags of IO types are the same as in SPDK
+    let io_op_flags = matches
+        .values_of("op")
+        .map(|ops| {
+            ops.map(|op| match op {
+                "read" => 1,
+                "write" => 1 << 1,
+                "unmap" => 1 << 2,
+                "flush" => 1 << 3,
+                _ => 1 << 4,
+            })
+            .fold(0, |flags, flag| flags | flag)
+        })
+        .unwrap_or(0);
+    let age_ns = match matches.value_of("age") {
+        Some(age) => {
+            age.parse::<u64>().map_err(|_| {
+                Status::invalid_argument(format!("Bad age '{}'", age))
+            })? * 1_000_000_000
+        }
+        None => 0,
+    };
+
+    ctx.v2(&format!(
+        "Requesting errors of child {} of nexus {}",
+        uri, uuid
+    ));
+    let resp = ctx
+        .client
+        .get_child_errors(rpc::ChildErrorsRequest {
+            uuid,
+            uri,
+            io_op_flags,
+            io_error_flags: 0,
+            age_ns,
+        })
+        .await?;
+    let errors = &resp.get_ref().errors;
+    if errors.is_empty() {
+        ctx.v1("No errors found");
+        return Ok(());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let table = errors
+        .iter()
+        .map(|e| {
+            let age = now.saturating_sub(e.timestamp);
+            vec![
+                format!("{}.{:03}", age / 1000, age % 1000),
+                io_type_to_str(e.io_op).to_string(),
+                e.io_offset.to_string(),
+                e.io_num_blocks.to_string(),
+                e.io_error.to_string(),
+            ]
+        })
+        .collect();
+    ctx.print_list(vec![">AGE_S", "OP", ">OFFSET", ">BLOCKS", ">ERROR"], table);
+    Ok(())
+}
+
 async fn nexus_publish(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
@@ -379,3 +645,14 @@ fn child_state_to_str(idx: i32) -> &'static str {
         rpc::ChildState::ChildFaulted => "faulted",
     }
 }
+
+fn io_type_to_str(idx: i32) -> &'static str {
+    match rpc::IoType::from_i32(idx) {
+        Some(rpc::IoType::Read) => "read",
+        Some(rpc::IoType::Write) => "write",
+        Some(rpc::IoType::Unmap) => "unmap",
+        Some(rpc::IoType::Flush) => "flush",
+        Some(rpc::IoType::Reset) => "reset",
+        _ => "other",
+    }
+}
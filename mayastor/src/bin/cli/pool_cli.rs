@@ -21,6 +21,14 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .value_name("IF")
                 .help("I/O interface for the underlying devices"),
         )
+        .arg(
+            Arg::with_name("layout")
+                .short("l")
+                .long("layout")
+                .value_name("LAYOUT")
+                .possible_values(&["stripe", "mirror"])
+                .help("Layout of the disks if there is more than one"),
+        )
         .arg(
             Arg::with_name("pool")
                 .required(true)
@@ -42,6 +50,20 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .index(1)
                 .help("Storage pool name"),
         );
+    let add_mirror = SubCommand::with_name("add-mirror")
+        .about("Add a mirror to a mirrored storage pool")
+        .arg(
+            Arg::with_name("pool")
+                .required(true)
+                .index(1)
+                .help("Storage pool name"),
+        )
+        .arg(
+            Arg::with_name("disk")
+                .required(true)
+                .index(2)
+                .help("Disk device file"),
+        );
     SubCommand::with_name("pool")
         .settings(&[
             AppSettings::SubcommandRequiredElseHelp,
@@ -51,6 +73,7 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .about("Storage pool management")
         .subcommand(create)
         .subcommand(destroy)
+        .subcommand(add_mirror)
         .subcommand(SubCommand::with_name("list").about("List storage pools"))
 }
 
@@ -61,6 +84,7 @@ pub async fn handler(
     match matches.subcommand() {
         ("create", Some(args)) => create(ctx, args).await,
         ("destroy", Some(args)) => destroy(ctx, args).await,
+        ("add-mirror", Some(args)) => add_mirror(ctx, args).await,
         ("list", Some(args)) => list(ctx, args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
@@ -88,6 +112,11 @@ async fn create(
             "Invalid value of I/O interface".to_owned(),
         )),
     }?;
+    let layout = match matches.value_of("layout") {
+        None | Some("stripe") => rpc::PoolLayout::PoolStripe as i32,
+        Some("mirror") => rpc::PoolLayout::PoolMirror as i32,
+        Some(_) => unreachable!(),
+    };
 
     ctx.v2(&format!("Creating pool {}", name));
     ctx.client
@@ -96,6 +125,7 @@ async fn create(
             disks,
             block_size,
             io_if,
+            layout,
         })
         .await?;
     ctx.v1(&format!("Created pool {}", name));
@@ -118,6 +148,24 @@ async fn destroy(
     Ok(())
 }
 
+async fn add_mirror(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let name = matches.value_of("pool").unwrap().to_owned();
+    let disk = matches.value_of("disk").unwrap().to_owned();
+
+    ctx.v2(&format!("Adding mirror {} to pool {}", disk, name));
+    ctx.client
+        .add_pool_mirror(rpc::AddPoolMirrorRequest {
+            name: name.clone(),
+            disk: disk.clone(),
+        })
+        .await?;
+    ctx.v1(&format!("Added mirror {} to pool {}", disk, name));
+    Ok(())
+}
+
 async fn list(
     mut ctx: Context,
     _matches: &ArgMatches<'_>,
@@ -139,16 +187,21 @@ async fn list(
             let cap = Byte::from_bytes(p.capacity.into());
             let used = Byte::from_bytes(p.used.into());
             let state = pool_state_to_str(p.state);
+            let layout = pool_layout_to_str(p.layout);
             vec![
                 p.name.clone(),
                 state.to_string(),
+                layout.to_string(),
                 ctx.units(cap),
                 ctx.units(used),
                 p.disks.join(" "),
             ]
         })
         .collect();
-    ctx.print_list(vec!["NAME", "STATE", ">CAPACITY", ">USED", "DISKS"], table);
+    ctx.print_list(
+        vec!["NAME", "STATE", "LAYOUT", ">CAPACITY", ">USED", "DISKS"],
+        table,
+    );
 
     Ok(())
 }
@@ -161,3 +214,11 @@ fn pool_state_to_str(idx: i32) -> &'static str {
         rpc::PoolState::PoolFaulted => "faulted",
     }
 }
+
+fn pool_layout_to_str(idx: i32) -> &'static str {
+    match rpc::PoolLayout::from_i32(idx).unwrap() {
+        rpc::PoolLayout::PoolStripe => "stripe",
+        rpc::PoolLayout::PoolMirror => "mirror",
+        rpc::PoolLayout::PoolSingle => "single",
+    }
+}
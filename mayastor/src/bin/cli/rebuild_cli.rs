@@ -3,7 +3,9 @@
 
 use crate::context::Context;
 use ::rpc::mayastor as rpc;
+use byte_unit::Byte;
 use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::time::{SystemTime, UNIX_EPOCH};
 use tonic::Status;
 
 pub async fn handler(
@@ -17,6 +19,9 @@ pub async fn handler(
         ("resume", Some(args)) => resume(ctx, &args).await,
         ("state", Some(args)) => state(ctx, &args).await,
         ("progress", Some(args)) => progress(ctx, &args).await,
+        ("stats", Some(args)) => stats(ctx, &args).await,
+        ("history", Some(args)) => history(ctx, &args).await,
+        ("limits", Some(args)) => limits(ctx, &args).await,
         (cmd, _) => {
             Err(Status::not_found(format!("command {} does not exist", cmd)))
         }
@@ -37,7 +42,27 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("uri")
                 .required(true)
                 .index(2)
-                .help("uri of child to start rebuilding"),
+                .multiple(true)
+                .help("uri of child to start rebuilding, any further children are rebuilt along with it"),
+        )
+        .arg(
+            Arg::with_name("bandwidth")
+                .short("b")
+                .long("bandwidth")
+                .value_name("MIB_PER_SEC")
+                .help("maximum copy rate of the rebuild, 0 for no limit"),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .short("c")
+                .long("concurrency")
+                .value_name("SEGMENTS")
+                .help("number of segments copied concurrently"),
+        )
+        .arg(
+            Arg::with_name("verify")
+                .long("verify")
+                .help("compare the rebuilt children against the source once copied"),
         );
 
     let stop = SubCommand::with_name("stop")
@@ -115,6 +140,64 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .help("uri of child to get the rebuild progress from"),
         );
 
+    let stats = SubCommand::with_name("stats")
+        .about("shows the statistics of a rebuild")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of the nexus"),
+        )
+        .arg(
+            Arg::with_name("uri")
+                .required(true)
+                .index(2)
+                .help("uri of child to get the rebuild statistics from"),
+        );
+
+    let history = SubCommand::with_name("history")
+        .about("lists the rebuilds of a nexus which have come to an end")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("uuid of the nexus"),
+        )
+        .arg(
+            Arg::with_name("uri")
+                .index(2)
+                .help("uri of child to list the rebuilds of, all if omitted"),
+        );
+
+    let limits = SubCommand::with_name("limits")
+        .about("changes the limits of a rebuild or of all rebuilds together")
+        .arg(
+            Arg::with_name("bandwidth")
+                .short("b")
+                .long("bandwidth")
+                .value_name("MIB_PER_SEC")
+                .required(true)
+                .help("maximum copy rate, 0 for no limit"),
+        )
+        .arg(
+            Arg::with_name("concurrency")
+                .short("c")
+                .long("concurrency")
+                .value_name("SEGMENTS")
+                .help("number of segments copied concurrently"),
+        )
+        .arg(
+            Arg::with_name("uuid")
+                .index(1)
+                .requires("uri")
+                .help("uuid of the nexus, all rebuilds together if omitted"),
+        )
+        .arg(
+            Arg::with_name("uri")
+                .index(2)
+                .help("uri of child being rebuilt"),
+        );
+
     SubCommand::with_name("rebuild")
         .settings(&[
             AppSettings::SubcommandRequiredElseHelp,
@@ -128,6 +211,34 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         .subcommand(resume)
         .subcommand(state)
         .subcommand(progress)
+        .subcommand(stats)
+        .subcommand(history)
+        .subcommand(limits)
+}
+
+/// Get the rebuild limits from the command line arguments
+fn parse_limits(
+    matches: &ArgMatches<'_>,
+) -> Result<Option<rpc::RebuildLimits>, Status> {
+    if !matches.is_present("bandwidth") && !matches.is_present("concurrency") {
+        return Ok(None);
+    }
+    let bandwidth_mbps = match matches.value_of("bandwidth") {
+        Some(value) => value.parse().map_err(|_| {
+            Status::invalid_argument(format!("Invalid bandwidth {}", value))
+        })?,
+        None => 0,
+    };
+    let concurrency = match matches.value_of("concurrency") {
+        Some(value) => value.parse().map_err(|_| {
+            Status::invalid_argument(format!("Invalid concurrency {}", value))
+        })?,
+        None => 0,
+    };
+    Ok(Some(rpc::RebuildLimits {
+        bandwidth_mbps,
+        concurrency,
+    }))
 }
 
 async fn start(
@@ -135,17 +246,29 @@ async fn start(
     matches: &ArgMatches<'_>,
 ) -> Result<(), Status> {
     let uuid = matches.value_of("uuid").unwrap().to_string();
-    let uri = matches.value_of("uri").unwrap().to_string();
+    let mut uris = matches
+        .values_of("uri")
+        .unwrap()
+        .map(|u| u.to_string())
+        .collect::<Vec<_>>();
+    let uri = uris.remove(0);
+
+    let limits = parse_limits(matches)?;
+    let verify = matches.is_present("verify");
 
     ctx.client
         .start_rebuild(rpc::StartRebuildRequest {
             uuid: uuid.clone(),
             uri: uri.clone(),
+            limits,
+            more_uris: uris.clone(),
+            verify,
         })
         .await?;
     ctx.v1(&format!(
-        "Starting rebuild of child {} on nexus {}",
-        uri, uuid
+        "Starting rebuild of children {:?} on nexus {}",
+        std::iter::once(uri).chain(uris).collect::<Vec<_>>(),
+        uuid
     ));
     Ok(())
 }
@@ -253,5 +376,176 @@ async fn progress(
         .await?
         .into_inner();
     println!("{}% complete", response.progress);
+    if response.verifying || response.mismatches_found > 0 {
+        println!(
+            "{} mismatches found, {} fixed{}",
+            response.mismatches_found,
+            response.mismatches_fixed,
+            if response.verifying {
+                ", verifying"
+            } else {
+                ""
+            }
+        );
+    }
+    Ok(())
+}
+
+async fn stats(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let uri = matches.value_of("uri").unwrap().to_string();
+
+    ctx.v2(&format!(
+        "Getting the rebuild statistics of child {} on nexus {}",
+        uri, uuid
+    ));
+    let response = ctx
+        .client
+        .get_rebuild_stats(rpc::RebuildStatsRequest {
+            uuid: uuid.clone(),
+            uri: uri.clone(),
+        })
+        .await?
+        .into_inner();
+    let stats = response.stats.unwrap_or_default();
+
+    let table = vec![
+        vec!["state".to_string(), response.state],
+        vec!["source".to_string(), response.source],
+        vec!["progress".to_string(), format!("{}%", stats.progress)],
+        vec!["blocks_total".to_string(), stats.blocks_total.to_string()],
+        vec![
+            "blocks_recovered".to_string(),
+            stats.blocks_recovered.to_string(),
+        ],
+        vec![
+            "blocks_transferred".to_string(),
+            stats.blocks_transferred.to_string(),
+        ],
+        vec![
+            "segment_size".to_string(),
+            ctx.units(Byte::from_bytes(
+                (stats.segment_size_blks * stats.block_size).into(),
+            )),
+        ],
+        vec![
+            "tasks".to_string(),
+            format!("{}/{}", stats.tasks_active, stats.tasks_total),
+        ],
+        vec!["elapsed_s".to_string(), seconds(stats.elapsed_ms)],
+        vec![
+            "throughput_per_s".to_string(),
+            ctx.units(Byte::from_bytes(stats.throughput.into())),
+        ],
+        vec![
+            "remaining_s".to_string(),
+            if stats.remaining_ms == 0 && stats.progress < 100 {
+                "-".to_string()
+            } else {
+                seconds(stats.remaining_ms)
+            },
+        ],
+        vec![
+            "mismatches".to_string(),
+            format!(
+                "{} found, {} fixed",
+                stats.mismatches_found, stats.mismatches_fixed
+            ),
+        ],
+        vec![
+            "segments_retried".to_string(),
+            stats.segments_retried.to_string(),
+        ],
+    ];
+    ctx.print_list(vec!["NAME", "VALUE"], table);
+    Ok(())
+}
+
+async fn history(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let uri = matches.value_of("uri").unwrap_or_default().to_string();
+
+    ctx.v2(&format!("Getting the rebuild history of nexus {}", uuid));
+    let response = ctx
+        .client
+        .get_rebuild_history(rpc::RebuildHistoryRequest {
+            uuid: uuid.clone(),
+            uri: uri.clone(),
+        })
+        .await?
+        .into_inner();
+    if response.rebuilds.is_empty() {
+        ctx.v1("No rebuilds found");
+        return Ok(());
+    }
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64);
+    let table = response
+        .rebuilds
+        .into_iter()
+        .map(|r| {
+            let stats = r.stats.unwrap_or_default();
+            vec![
+                seconds(now.saturating_sub(r.end_time)),
+                r.uri,
+                r.source,
+                r.state,
+                stats.blocks_recovered.to_string(),
+                seconds(stats.elapsed_ms),
+                r.error,
+            ]
+        })
+        .collect();
+    ctx.print_list(
+        vec![
+            ">AGE_S",
+            "CHILD",
+            "SOURCE",
+            "STATE",
+            ">BLOCKS",
+            ">ELAPSED_S",
+            "ERROR",
+        ],
+        table,
+    );
+    Ok(())
+}
+
+/// Format a duration in milliseconds as seconds
+fn seconds(ms: u64) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+async fn limits(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap_or_default().to_string();
+    let uri = matches.value_of("uri").unwrap_or_default().to_string();
+    let limits = parse_limits(matches)?;
+
+    ctx.client
+        .set_rebuild_limits(rpc::SetRebuildLimitsRequest {
+            uuid: uuid.clone(),
+            uri: uri.clone(),
+            limits,
+        })
+        .await?;
+    if uuid.is_empty() {
+        ctx.v1("Changed the limits of all rebuilds");
+    } else {
+        ctx.v1(&format!(
+            "Changed the limits of the rebuild of child {} on nexus {}",
+            uri, uuid
+        ));
+    }
     Ok(())
 }
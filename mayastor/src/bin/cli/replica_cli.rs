@@ -42,6 +42,26 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .takes_value(false)
                 .help("Whether replica is thin provisioned (default false)"));
 
+    let clone = SubCommand::with_name("clone")
+        .about("Create thin provisioned replica from snapshot")
+        .arg(
+            Arg::with_name("snapshot")
+                .required(true)
+                .index(1)
+                .help("Snapshot name"))
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(2)
+                .help("Unique replica uuid"))
+        .arg(
+            Arg::with_name("protocol")
+                .short("p")
+                .long("protocol")
+                .takes_value(true)
+                .value_name("PROTOCOL")
+                .help("Name of a protocol (nvmf, iSCSI) used for sharing the replica (default none)"));
+
     let destroy = SubCommand::with_name("destroy")
         .about("Destroy replica")
         .arg(
@@ -51,6 +71,21 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
                 .help("Replica uuid"),
         );
 
+    let resize = SubCommand::with_name("resize")
+        .about("Grow replica to the given size")
+        .arg(
+            Arg::with_name("uuid")
+                .required(true)
+                .index(1)
+                .help("Replica uuid"),
+        )
+        .arg(
+            Arg::with_name("size")
+                .required(true)
+                .index(2)
+                .help("New size of the replica"),
+        );
+
     let share = SubCommand::with_name("share").about("Share or unshare replica")
         .arg(
             Arg::with_name("uuid")
@@ -71,7 +106,9 @@ pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
         ])
         .about("Replica management")
         .subcommand(create)
+        .subcommand(clone)
         .subcommand(destroy)
+        .subcommand(resize)
         .subcommand(share)
         .subcommand(SubCommand::with_name("list").about("List replicas"))
         .subcommand(
@@ -85,8 +122,10 @@ pub async fn handler(
 ) -> Result<(), Status> {
     match matches.subcommand() {
         ("create", Some(args)) => replica_create(ctx, &args).await,
+        ("clone", Some(args)) => replica_clone(ctx, &args).await,
         ("destroy", Some(args)) => replica_destroy(ctx, &args).await,
         ("list", Some(args)) => replica_list(ctx, &args).await,
+        ("resize", Some(args)) => replica_resize(ctx, &args).await,
         ("share", Some(args)) => replica_share(ctx, &args).await,
         ("stats", Some(args)) => replica_stat(ctx, &args).await,
         (cmd, _) => {
@@ -119,6 +158,25 @@ async fn replica_create(
     Ok(())
 }
 
+async fn replica_clone(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let snapshot = matches.value_of("snapshot").unwrap().to_owned();
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let share = parse_replica_protocol(matches.value_of("protocol"))?;
+
+    ctx.v2(&format!("Cloning snapshot {} to replica {}", snapshot, uuid));
+    let rq = rpc::CreateCloneRequest {
+        snapshot,
+        uuid,
+        share,
+    };
+    let resp = ctx.client.create_clone(rq).await?;
+    ctx.v1(&format!("Created {}", resp.get_ref().uri));
+    Ok(())
+}
+
 async fn replica_destroy(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
@@ -161,17 +219,42 @@ async fn replica_list(
                 proto.to_string(),
                 size,
                 r.uri.clone(),
+                r.origin.clone(),
             ]
         })
         .collect();
     ctx.print_list(
-        vec!["POOL", "NAME", ">THIN", ">SHARE", ">SIZE", "URI"],
+        vec!["POOL", "NAME", ">THIN", ">SHARE", ">SIZE", "URI", "ORIGIN"],
         table,
     );
 
     Ok(())
 }
 
+async fn replica_resize(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_owned();
+    let size = parse_size(matches.value_of("size").unwrap())
+        .map_err(|s| Status::invalid_argument(format!("Bad size '{}'", s)))?;
+
+    ctx.v2(&format!("Resizing replica {} to {}", uuid, ctx.units(size)));
+    let resp = ctx
+        .client
+        .resize_replica(rpc::ResizeReplicaRequest {
+            uuid,
+            size: size.get_bytes() as u64,
+        })
+        .await?;
+    ctx.v1(&format!(
+        "Replica {} resized to {}",
+        resp.get_ref().uuid,
+        ctx.units(Byte::from_bytes(resp.get_ref().size.into()))
+    ));
+    Ok(())
+}
+
 async fn replica_share(
     mut ctx: Context,
     matches: &ArgMatches<'_>,
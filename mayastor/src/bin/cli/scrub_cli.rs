@@ -0,0 +1,178 @@
+//!
+//! methods to interact with the scrub of a nexus
+
+use crate::context::Context;
+use ::rpc::mayastor as rpc;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use tonic::Status;
+
+pub async fn handler(
+    ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    match matches.subcommand() {
+        ("start", Some(args)) => start(ctx, &args).await,
+        ("stop", Some(args)) => stop(ctx, &args).await,
+        ("pause", Some(args)) => pause(ctx, &args).await,
+        ("resume", Some(args)) => resume(ctx, &args).await,
+        ("progress", Some(args)) => progress(ctx, &args).await,
+        (cmd, _) => {
+            Err(Status::not_found(format!("command {} does not exist", cmd)))
+        }
+    }
+}
+
+pub fn subcommands<'a, 'b>() -> App<'a, 'b> {
+    let uuid = Arg::with_name("uuid")
+        .required(true)
+        .index(1)
+        .help("uuid of the nexus");
+
+    let start = SubCommand::with_name("start")
+        .about("starts comparing the children of a nexus")
+        .arg(uuid.clone())
+        .arg(
+            Arg::with_name("authority")
+                .short("a")
+                .long("authority")
+                .value_name("URI")
+                .help("uri of child to repair the others from, only report the differences if omitted"),
+        );
+
+    let stop = SubCommand::with_name("stop")
+        .about("stops a scrub")
+        .arg(uuid.clone());
+
+    let pause = SubCommand::with_name("pause")
+        .about("pauses a scrub")
+        .arg(uuid.clone());
+
+    let resume = SubCommand::with_name("resume")
+        .about("resumes a scrub")
+        .arg(uuid.clone());
+
+    let progress = SubCommand::with_name("progress")
+        .about("shows the progress of a scrub and where the children differ")
+        .arg(uuid);
+
+    SubCommand::with_name("scrub")
+        .settings(&[
+            AppSettings::SubcommandRequiredElseHelp,
+            AppSettings::ColoredHelp,
+            AppSettings::ColorAlways,
+        ])
+        .about("Scrub management")
+        .subcommand(start)
+        .subcommand(stop)
+        .subcommand(pause)
+        .subcommand(resume)
+        .subcommand(progress)
+}
+
+async fn start(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+    let authority = matches.value_of("authority").unwrap_or_default();
+
+    ctx.client
+        .start_scrub(rpc::StartScrubRequest {
+            uuid: uuid.clone(),
+            authority: authority.to_string(),
+        })
+        .await?;
+    if authority.is_empty() {
+        ctx.v1(&format!("Starting scrub of nexus {}", uuid));
+    } else {
+        ctx.v1(&format!(
+            "Starting scrub of nexus {} repairing from child {}",
+            uuid, authority
+        ));
+    }
+    Ok(())
+}
+
+async fn stop(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    ctx.client
+        .stop_scrub(rpc::ScrubRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    ctx.v1(&format!("Stopping scrub of nexus {}", uuid));
+    Ok(())
+}
+
+async fn pause(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    ctx.client
+        .pause_scrub(rpc::ScrubRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    ctx.v1(&format!("Pausing scrub of nexus {}", uuid));
+    Ok(())
+}
+
+async fn resume(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    ctx.client
+        .resume_scrub(rpc::ScrubRequest {
+            uuid: uuid.clone(),
+        })
+        .await?;
+    ctx.v1(&format!("Resuming scrub of nexus {}", uuid));
+    Ok(())
+}
+
+async fn progress(
+    mut ctx: Context,
+    matches: &ArgMatches<'_>,
+) -> Result<(), Status> {
+    let uuid = matches.value_of("uuid").unwrap().to_string();
+
+    ctx.v2(&format!("Getting the scrub progress of nexus {}", uuid));
+    let response = ctx
+        .client
+        .get_scrub_progress(rpc::ScrubRequest {
+            uuid: uuid.clone(),
+        })
+        .await?
+        .into_inner();
+    println!("{} {}% complete", response.state, response.progress);
+    if !response.error.is_empty() {
+        println!("{}", response.error);
+    }
+    if response.mismatches.is_empty() {
+        ctx.v1("No differences found");
+        return Ok(());
+    }
+
+    let table = response
+        .mismatches
+        .into_iter()
+        .map(|m| {
+            vec![
+                m.offset.to_string(),
+                m.num_blocks.to_string(),
+                m.repaired.to_string(),
+                m.uris.join(","),
+            ]
+        })
+        .collect();
+    ctx.print_list(vec![">OFFSET", ">BLOCKS", "REPAIRED", "CHILDREN"], table);
+    Ok(())
+}
@@ -21,11 +21,16 @@ use spdk_sys::{
     spdk_bdev_get_num_blocks,
     spdk_bdev_get_product_name,
     spdk_bdev_get_uuid,
+    spdk_bdev_histogram_enable,
+    spdk_bdev_histogram_get,
     spdk_bdev_io_stat,
     spdk_bdev_io_type_supported,
     spdk_bdev_next,
     spdk_bdev_open,
+    spdk_get_ticks_hz,
+    spdk_histogram_data,
     spdk_uuid_generate,
+    SPDK_HISTOGRAM_BUCKET_SHIFT_DEFAULT,
 };
 
 use crate::{
@@ -48,6 +53,18 @@ pub struct Stat {
     pub num_write_ops: u64,
     pub bytes_read: u64,
     pub bytes_written: u64,
+    pub read_latency_ticks: u64,
+    pub write_latency_ticks: u64,
+    pub tick_rate: u64,
+}
+
+/// Latency histogram of all IOs of a bdev. The buckets are (end, count) pairs
+/// ordered by the end, which is the exclusive upper bound of the latency of
+/// the bucket in ticks. Empty buckets are left out.
+#[derive(Debug, Default)]
+pub struct Histogram {
+    pub buckets: Vec<(u64, u64)>,
+    pub tick_rate: u64,
 }
 
 /// Newtype structure that represents a block device. The soundness of the API
@@ -397,9 +414,118 @@ impl Bdev {
                 num_write_ops: stat.num_write_ops,
                 bytes_read: stat.bytes_read,
                 bytes_written: stat.bytes_written,
+                read_latency_ticks: stat.read_latency_ticks,
+                write_latency_ticks: stat.write_latency_ticks,
+                tick_rate: stat.ticks_rate,
             })
         }
     }
+
+    extern "C" fn histogram_cb(
+        sender_ptr: *mut c_void,
+        errno: i32,
+        _histogram: *mut spdk_histogram_data,
+    ) {
+        let sender =
+            unsafe { Box::from_raw(sender_ptr as *mut oneshot::Sender<i32>) };
+        sender.send(errno).expect("histogram_cb receiver is gone");
+    }
+
+    extern "C" fn histogram_enable_cb(sender_ptr: *mut c_void, errno: i32) {
+        let sender =
+            unsafe { Box::from_raw(sender_ptr as *mut oneshot::Sender<i32>) };
+        sender
+            .send(errno)
+            .expect("histogram_enable_cb receiver is gone");
+    }
+
+    /// Enable the latency histogram of the bdev, which from then on is
+    /// updated by every IO completed by the bdev, or return the errno value
+    /// in case of an error.
+    pub async fn enable_latency_histogram(&self) -> Result<(), i32> {
+        if self.latency_histogram_enabled() {
+            return Ok(());
+        }
+
+        let (sender, receiver) = oneshot::channel::<i32>();
+        unsafe {
+            spdk_bdev_histogram_enable(
+                self.0.as_ptr(),
+                Some(Self::histogram_enable_cb),
+                cb_arg(sender),
+                true,
+            );
+        }
+        let errno = receiver.await.expect("Cancellation is not supported");
+        if errno != 0 {
+            return Err(errno);
+        }
+        Ok(())
+    }
+
+    /// returns true if the latency histogram of the bdev is enabled
+    pub fn latency_histogram_enabled(&self) -> bool {
+        unsafe { self.0.as_ref().internal.histogram_enabled }
+    }
+
+    /// Get the latency histogram of the bdev or errno value in case of an
+    /// error. The histogram must have been enabled, it only counts the IOs
+    /// since then.
+    pub async fn latency_histogram(&self) -> Result<Histogram, i32> {
+        if !self.latency_histogram_enabled() {
+            return Err(Errno::ENOENT as i32);
+        }
+
+        // same layout as SPDK_HISTOGRAM_NUM_BUCKETS() of the default shift
+        let shift = SPDK_HISTOGRAM_BUCKET_SHIFT_DEFAULT;
+        let per_range = 1u64 << shift;
+        let mut buckets =
+            vec![0u64; ((64 - shift + 1) as u64 * per_range) as usize];
+        let mut histogram = spdk_histogram_data {
+            bucket_shift: shift,
+            bucket: buckets.as_mut_ptr(),
+        };
+        let (sender, receiver) = oneshot::channel::<i32>();
+
+        // this will iterate over io channels and call async cb when done
+        unsafe {
+            spdk_bdev_histogram_get(
+                self.0.as_ptr(),
+                &mut histogram as *mut _,
+                Some(Self::histogram_cb),
+                cb_arg(sender),
+            );
+        }
+
+        let errno = receiver.await.expect("Cancellation is not supported");
+        if errno != 0 {
+            return Err(errno);
+        }
+
+        // the end of a bucket is where the next one starts, see
+        // __spdk_histogram_data_get_bucket_start()
+        let end = |range: u64, index: u64| {
+            if range > 0 {
+                (1u64 << (range + shift as u64 - 1))
+                    .saturating_add((index + 1) << (range - 1))
+            } else {
+                index + 1
+            }
+        };
+        Ok(Histogram {
+            buckets: buckets
+                .iter()
+                .enumerate()
+                .filter(|(_, count)| **count > 0)
+                .map(|(i, count)| {
+                    let i = i as u64;
+                    (end(i / per_range, i % per_range), *count)
+                })
+                .collect(),
+            tick_rate: unsafe { spdk_get_ticks_hz() },
+        })
+    }
+
     /// returns the first bdev in the list
     pub fn bdev_first() -> Option<Bdev> {
         let bdev = unsafe { spdk_bdev_first() };
@@ -47,6 +47,7 @@ use crate::{
     },
     grpc,
     logger,
+    metrics,
     nats,
     subsys::Config,
     target::iscsi,
@@ -111,6 +112,11 @@ pub struct MayastorCliArgs {
     #[structopt(short = "n")]
     /// IP address and port of the NATS server
     pub nats_endpoint: Option<String>,
+    #[structopt(long = "metrics-endpoint")]
+    /// IP address and port for Prometheus metrics endpoint to listen on.
+    /// Enabling it makes every IO of the nexus and replica bdevs update a
+    /// latency histogram, which adds some overhead to each IO.
+    pub metrics_endpoint: Option<String>,
     /// The maximum amount of hugepage memory we are allowed to allocate in MiB
     /// (default: all)
     #[structopt(
@@ -142,6 +148,7 @@ impl Default for MayastorCliArgs {
         Self {
             grpc_endpoint: None,
             nats_endpoint: None,
+            metrics_endpoint: None,
             node_name: None,
             env_context: None,
             reactor_mask: "0x1".into(),
@@ -206,6 +213,7 @@ pub struct MayastorEnvironment {
     node_name: String,
     nats_endpoint: Option<String>,
     grpc_endpoint: Option<String>,
+    metrics_endpoint: Option<String>,
     mayastor_config: Option<String>,
     delay_subsystem_init: bool,
     enable_coredump: bool,
@@ -240,6 +248,7 @@ impl Default for MayastorEnvironment {
             node_name: "mayastor-node".into(),
             nats_endpoint: None,
             grpc_endpoint: None,
+            metrics_endpoint: None,
             mayastor_config: None,
             delay_subsystem_init: false,
             enable_coredump: true,
@@ -343,6 +352,7 @@ impl MayastorEnvironment {
         Self {
             grpc_endpoint: add_default_port(args.grpc_endpoint, 10124),
             nats_endpoint: add_default_port(args.nats_endpoint, 4222),
+            metrics_endpoint: add_default_port(args.metrics_endpoint, 9502),
             node_name: args.node_name.unwrap_or_else(|| "mayastor-node".into()),
             config: args.config,
             mayastor_config: args.mayastor_config,
@@ -723,6 +733,7 @@ impl MayastorEnvironment {
         type FutureResult = Result<(), ()>;
         let grpc_endpoint = self.grpc_endpoint.clone();
         let nats_endpoint = self.nats_endpoint.clone();
+        let metrics_endpoint = self.metrics_endpoint.clone();
         let node_name = self.node_name.clone();
         self.init();
 
@@ -751,6 +762,10 @@ impl MayastorEnvironment {
                             )));
                         }
                     };
+                    if let Some(metrics_ep) = metrics_endpoint.as_ref() {
+                        futures
+                            .push(Box::pin(metrics::metrics_run(metrics_ep)));
+                    }
                     futures.push(Box::pin(master));
                     let _out = future::try_join_all(futures).await;
                     info!("reactors stopped");
@@ -0,0 +1,301 @@
+//! Events describing state changes of the objects managed by mayastor.
+//!
+//! The events are published on a bus with any number of subscribers (i.e.
+//! gRPC clients watching the events) and those of interest to the control
+//! plane are forwarded to the NATS message bus as well. Publishing an event
+//! never blocks as it is done from the reactors, hence a subscriber which does
+//! not keep up with the events loses them. Every event carries a sequence
+//! number and the most recent events are remembered, so that a subscriber can
+//! resume after the last event it has seen when it reconnects. The sequence
+//! numbers start over when mayastor restarts, which is told by the random
+//! instance id the events carry along.
+
+use std::{collections::VecDeque, sync::Mutex};
+
+use once_cell::sync::Lazy;
+use rpc::mayastor as rpc;
+use snafu::Snafu;
+use tokio::sync::broadcast::{self, RecvError};
+
+use crate::{
+    bdev::{ActionType, ChildStatus, NexusStatus},
+    grpc::nexus_grpc::name_to_uuid,
+    nats::message_bus_publish,
+    rebuild::RebuildState,
+};
+
+/// The number of the most recent events which are remembered
+const EVENT_HISTORY: usize = 1024;
+
+/// The number of events a subscriber can lag behind before it loses them
+const EVENT_BACKLOG: usize = 256;
+
+/// The id of this mayastor instance, which the sequence numbers belong to
+static INSTANCE: Lazy<u64> = Lazy::new(|| loop {
+    // zero is what a subscriber which has not seen any event passes
+    let instance = rand::random::<u64>();
+    if instance != 0 {
+        break instance;
+    }
+});
+
+/// The event bus shared by the publishers and subscribers
+static EVENT_BUS: Lazy<Mutex<EventBus>> = Lazy::new(|| {
+    let (sender, _) = broadcast::channel(EVENT_BACKLOG);
+    Mutex::new(EventBus {
+        sequence: 0,
+        history: VecDeque::with_capacity(EVENT_HISTORY),
+        sender,
+    })
+});
+
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display(
+        "Events following the event {} are not available anymore",
+        sequence
+    ))]
+    EventsLost { sequence: u64 },
+}
+
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::EventsLost {
+                ..
+            } => Self::out_of_range(e.to_string()),
+        }
+    }
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Change of an object which has a life cycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lifecycle {
+    /// the object has been created (or imported)
+    Created,
+    /// the object has been destroyed
+    Destroyed,
+}
+
+/// State changes reported by the events
+#[derive(Debug, Clone, PartialEq)]
+pub enum EventKind {
+    /// status of the nexus has changed
+    NexusStatus { nexus: String, status: NexusStatus },
+    /// status of the child has changed, reason being the dynamic
+    /// reconfiguration of the nexus which changed it, if any
+    ChildStatus {
+        nexus: String,
+        child: String,
+        status: ChildStatus,
+        reason: Option<String>,
+    },
+    /// state of the job rebuilding the child has changed
+    RebuildState {
+        nexus: String,
+        child: String,
+        state: RebuildState,
+    },
+    /// errors of the child have exceeded a limit of the error store and
+    /// the action has been taken on it
+    ChildErrors {
+        nexus: String,
+        child: String,
+        action: ActionType,
+        reason: String,
+    },
+    /// the pool has been created or destroyed
+    Pool { name: String, change: Lifecycle },
+    /// the replica has been created or destroyed
+    Replica {
+        uuid: String,
+        pool: String,
+        change: Lifecycle,
+    },
+}
+
+/// Event published on the bus
+#[derive(Debug, Clone)]
+pub struct Event {
+    /// id of the mayastor instance which published the event
+    pub instance: u64,
+    /// sequence number of the event, starting from 1
+    pub sequence: u64,
+    /// what has changed
+    pub kind: EventKind,
+}
+
+struct EventBus {
+    /// sequence number of the last event
+    sequence: u64,
+    /// the most recent events
+    history: VecDeque<Event>,
+    /// the sending end of the subscriptions
+    sender: broadcast::Sender<Event>,
+}
+
+/// Publish an event to all subscribers.
+pub fn publish(kind: EventKind) {
+    message_bus_publish(&kind);
+
+    let mut bus = EVENT_BUS.lock().unwrap();
+    bus.sequence += 1;
+    let event = Event {
+        instance: *INSTANCE,
+        sequence: bus.sequence,
+        kind,
+    };
+    debug!("Publishing event {:?}", event);
+
+    if bus.history.len() == EVENT_HISTORY {
+        bus.history.pop_front();
+    }
+    bus.history.push_back(event.clone());
+    // it is not an error if nobody is listening
+    let _ = bus.sender.send(event);
+}
+
+/// The id of this mayastor instance, which every event carries
+pub fn instance() -> u64 {
+    *INSTANCE
+}
+
+/// Subscribe to the events which follow the event `since` published by the
+/// mayastor `instance`. The events which have been published already are
+/// replayed first if they are remembered, zero subscribes to the events
+/// published from now on. Once mayastor has restarted the events of the
+/// previous instance are lost.
+pub fn subscribe(instance: u64, since: u64) -> Result<Subscription> {
+    let bus = EVENT_BUS.lock().unwrap();
+    let oldest = bus.history.front().map_or(bus.sequence + 1, |e| e.sequence);
+    if since > 0
+        && (instance != *INSTANCE || since > bus.sequence || since + 1 < oldest)
+    {
+        return Err(Error::EventsLost {
+            sequence: since,
+        });
+    }
+
+    let since = if since == 0 { bus.sequence } else { since };
+    // nothing can be published in between as the bus is locked
+    Ok(Subscription {
+        replay: bus
+            .history
+            .iter()
+            .filter(|e| e.sequence > since)
+            .cloned()
+            .collect(),
+        receiver: bus.sender.subscribe(),
+        last: since,
+    })
+}
+
+/// Subscription to the events
+pub struct Subscription {
+    /// the events published before subscribing
+    replay: VecDeque<Event>,
+    /// the events published since subscribing
+    receiver: broadcast::Receiver<Event>,
+    /// sequence number of the last event returned
+    last: u64,
+}
+
+impl Subscription {
+    /// Wait for the next event. Once the subscriber has lagged behind and the
+    /// events have been lost, it has to subscribe again.
+    pub async fn next(&mut self) -> Result<Event> {
+        let event = match self.replay.pop_front() {
+            Some(event) => event,
+            None => match self.receiver.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(_)) => {
+                    return Err(Error::EventsLost {
+                        sequence: self.last,
+                    })
+                }
+                Err(RecvError::Closed) => {
+                    unreachable!("The event bus is never closed")
+                }
+            },
+        };
+        self.last = event.sequence;
+        Ok(event)
+    }
+}
+
+impl From<Lifecycle> for rpc::Lifecycle {
+    fn from(change: Lifecycle) -> Self {
+        match change {
+            Lifecycle::Created => rpc::Lifecycle::ObjectCreated,
+            Lifecycle::Destroyed => rpc::Lifecycle::ObjectDestroyed,
+        }
+    }
+}
+
+impl From<Event> for rpc::Event {
+    fn from(e: Event) -> Self {
+        let kind = match e.kind {
+            EventKind::NexusStatus {
+                nexus,
+                status,
+            } => rpc::event::Kind::Nexus(rpc::NexusStatusEvent {
+                uuid: name_to_uuid(&nexus).to_string(),
+                state: rpc::NexusState::from(status) as i32,
+            }),
+            EventKind::ChildStatus {
+                nexus,
+                child,
+                status,
+                reason,
+            } => rpc::event::Kind::Child(rpc::ChildStatusEvent {
+                nexus: name_to_uuid(&nexus).to_string(),
+                uri: child,
+                state: rpc::ChildState::from(status) as i32,
+                reason: reason.unwrap_or_default(),
+            }),
+            EventKind::RebuildState {
+                nexus,
+                child,
+                state,
+            } => rpc::event::Kind::Rebuild(rpc::RebuildStateEvent {
+                nexus: name_to_uuid(&nexus).to_string(),
+                uri: child,
+                state: state.to_string(),
+            }),
+            EventKind::ChildErrors {
+                nexus,
+                child,
+                action,
+                reason,
+            } => rpc::event::Kind::ChildErrors(rpc::ChildErrorsEvent {
+                nexus: name_to_uuid(&nexus).to_string(),
+                uri: child,
+                action: action.to_string(),
+                reason,
+            }),
+            EventKind::Pool {
+                name,
+                change,
+            } => rpc::event::Kind::Pool(rpc::PoolEvent {
+                name,
+                change: rpc::Lifecycle::from(change) as i32,
+            }),
+            EventKind::Replica {
+                uuid,
+                pool,
+                change,
+            } => rpc::event::Kind::Replica(rpc::ReplicaEvent {
+                uuid,
+                pool,
+                change: rpc::Lifecycle::from(change) as i32,
+            }),
+        };
+        rpc::Event {
+            instance: e.instance,
+            sequence: e.sequence,
+            kind: Some(kind),
+        }
+    }
+}
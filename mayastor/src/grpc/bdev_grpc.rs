@@ -13,10 +13,11 @@ use rpc::mayastor::{
     Bdevs,
     CreateReply,
     Null,
+    Stats,
 };
 
 use crate::{
-    core::{Bdev, Reactors, Share},
+    core::{Bdev, Reactors, Share, Stat},
     grpc::{sync_config, GrpcResult},
     nexus_uri::{bdev_create, bdev_destroy, NexusBdevError},
 };
@@ -53,6 +54,27 @@ impl From<Bdev> for RpcBdev {
     }
 }
 
+impl From<Stat> for Stats {
+    fn from(s: Stat) -> Self {
+        // latency is accounted in ticks of the timestamp counter
+        let to_us = |ticks: u64| {
+            if s.tick_rate == 0 {
+                0
+            } else {
+                (ticks as u128 * 1_000_000 / s.tick_rate as u128) as u64
+            }
+        };
+        Self {
+            num_read_ops: s.num_read_ops,
+            num_write_ops: s.num_write_ops,
+            bytes_read: s.bytes_read,
+            bytes_written: s.bytes_written,
+            read_latency_us: to_us(s.read_latency_ticks),
+            write_latency_us: to_us(s.write_latency_ticks),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct BdevSvc {}
 
@@ -1,5 +1,6 @@
 //! Mayastor grpc methods implementation.
 
+use tokio::sync::mpsc;
 use tonic::{Request, Response, Status};
 use tracing::instrument;
 
@@ -9,8 +10,10 @@ use crate::{
     bdev::{
         nexus::{instances, nexus_bdev},
         nexus_create,
+        NexusErrStore,
     },
     core::Cores,
+    events,
     grpc::{
         nexus_grpc::{
             nexus_add_child,
@@ -22,9 +25,14 @@ use crate::{
         GrpcResult,
     },
     pool,
+    rebuild::RebuildJob,
     replica,
+    snapshot,
 };
 
+/// The number of events buffered for a client watching the events
+const WATCH_EVENTS_BUFFER: usize = 64;
+
 #[derive(Debug)]
 struct UnixStream(tokio::net::UnixStream);
 
@@ -47,11 +55,12 @@ impl mayastor_server::Mayastor for MayastorSvc {
                 return Err(Status::invalid_argument("Missing devices"));
             }
             debug!(
-                "Creating pool {} on {} with block size {}, io_if {}...",
+                "Creating pool {} on {} with block size {}, io_if {}, layout {}...",
                 name,
                 args.disks.join(" "),
                 args.block_size,
                 args.io_if,
+                args.layout,
             );
 
             let pool = locally! { pool::create_pool(args) };
@@ -95,6 +104,23 @@ impl mayastor_server::Mayastor for MayastorSvc {
         Ok(Response::new(reply))
     }
 
+    #[instrument(level = "debug", err)]
+    async fn add_pool_mirror(
+        &self,
+        request: Request<AddPoolMirrorRequest>,
+    ) -> GrpcResult<Pool> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let name = args.name.clone();
+            debug!("Adding mirror {} to pool {} ...", args.disk, name);
+            let pool = locally! { pool::add_pool_mirror(args) };
+            info!("Added mirror to pool {}", name);
+            Ok(Response::new(pool))
+        })
+        .await
+    }
+
     #[instrument(level = "debug", err)]
     async fn create_replica(
         &self,
@@ -172,6 +198,81 @@ impl mayastor_server::Mayastor for MayastorSvc {
         .await
     }
 
+    #[instrument(level = "debug", err)]
+    async fn resize_replica(
+        &self,
+        request: Request<ResizeReplicaRequest>,
+    ) -> GrpcResult<Replica> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            debug!("Resizing replica {} to {} bytes ...", uuid, args.size);
+            let replica = locally! { replica::resize_replica(args) };
+            info!("Resized replica {}", uuid);
+            Ok(Response::new(replica))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn create_clone(
+        &self,
+        request: Request<CreateCloneRequest>,
+    ) -> GrpcResult<Replica> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            debug!("Creating clone {} of {} ...", uuid, args.snapshot);
+            let replica = locally! { replica::create_clone(args) };
+            info!("Created clone {} ...", uuid);
+            Ok(Response::new(replica))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn create_snapshot(
+        &self,
+        request: Request<CreateSnapshotRequest>,
+    ) -> GrpcResult<Snapshot> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let uuid = args.uuid.clone();
+        debug!("Creating snapshot of replica {} ...", uuid);
+        let snapshot = locally! { snapshot::create_snapshot(args) };
+        info!("Created snapshot {}", snapshot.name);
+        Ok(Response::new(snapshot))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn list_snapshots(
+        &self,
+        request: Request<Null>,
+    ) -> GrpcResult<ListSnapshotsReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        assert_eq!(Cores::current(), Cores::first());
+        let reply = snapshot::list_snapshots();
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn delete_snapshot(
+        &self,
+        request: Request<DeleteSnapshotRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let name = args.name.clone();
+        debug!("Deleting snapshot {} ...", name);
+        locally! { snapshot::delete_snapshot(args) };
+        info!("Deleted snapshot {}", name);
+        Ok(Response::new(Null {}))
+    }
+
     #[instrument(level = "debug", err)]
     async fn create_nexus(
         &self,
@@ -182,11 +283,31 @@ impl mayastor_server::Mayastor for MayastorSvc {
             trace!("{:?}", args);
             let uuid = args.uuid.clone();
             let name = uuid_to_name(&args.uuid)?;
+            let read_policy = match ReadPolicy::from_i32(args.read_policy) {
+                Some(policy) => nexus_bdev::ReadPolicy::from(policy),
+                None => {
+                    return Err(nexus_bdev::Error::InvalidReadPolicy {
+                        rp_value: args.read_policy,
+                    }
+                    .into())
+                }
+            };
+            let write_policy = match WritePolicy::from_i32(args.write_policy) {
+                Some(policy) => nexus_bdev::WritePolicy::from(policy),
+                None => {
+                    return Err(nexus_bdev::Error::InvalidWritePolicy {
+                        wp_value: args.write_policy,
+                    }
+                    .into())
+                }
+            };
             debug!("Creating nexus {} ...", uuid);
             locally! { async move {
                 nexus_create(&name, args.size, Some(&args.uuid), &args.children).await
             }};
             let nexus = nexus_lookup(&uuid)?;
+            nexus.set_read_policy(read_policy);
+            nexus.set_write_policy(write_policy);
             info!("Created nexus {}", uuid);
             Ok(Response::new(nexus.to_grpc()))
         }).await
@@ -263,6 +384,65 @@ impl mayastor_server::Mayastor for MayastorSvc {
         .await
     }
 
+    #[instrument(level = "debug", err)]
+    async fn resize_nexus(
+        &self,
+        request: Request<ResizeNexusRequest>,
+    ) -> GrpcResult<Nexus> {
+        sync_config(async {
+            let args = request.into_inner();
+            trace!("{:?}", args);
+            let uuid = args.uuid.clone();
+            debug!("Resizing nexus {} to {} bytes ...", uuid, args.size);
+            locally! { async move {
+                nexus_lookup(&args.uuid)?.resize(args.size).await
+            }};
+            let nexus = nexus_lookup(&uuid)?;
+            info!("Resized nexus {}", uuid);
+            Ok(Response::new(nexus.to_grpc()))
+        })
+        .await
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn stat_nexus(
+        &self,
+        request: Request<StatNexusRequest>,
+    ) -> GrpcResult<StatNexusReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let reply = locally! { async move {
+            nexus_lookup(&args.uuid)?.stats_to_grpc().await
+        }};
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn create_nexus_snapshot(
+        &self,
+        request: Request<CreateNexusSnapshotRequest>,
+    ) -> GrpcResult<CreateNexusSnapshotReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let uuid = args.uuid.clone();
+        debug!("Creating snapshot of nexus {} ...", uuid);
+        let reply = locally! { async move {
+            let snapshot = nexus_lookup(&args.uuid)?.create_snapshot().await?;
+            Ok::<_, nexus_bdev::Error>(CreateNexusSnapshotReply {
+                snapshots: snapshot
+                    .snapshots
+                    .into_iter()
+                    .map(|s| s.into())
+                    .collect(),
+                remote: snapshot.remote,
+            })
+        }};
+        info!("Created snapshot of nexus {}", uuid);
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
     #[instrument(level = "debug", err)]
     async fn publish_nexus(
         &self,
@@ -355,6 +535,38 @@ impl mayastor_server::Mayastor for MayastorSvc {
         .await
     }
 
+    #[instrument(level = "debug", err)]
+    async fn get_child_errors(
+        &self,
+        request: Request<ChildErrorsRequest>,
+    ) -> GrpcResult<ChildErrorsReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let io_op_flags = match args.io_op_flags {
+            0 => NexusErrStore::ALL_OP_FLAGS,
+            flags => flags,
+        };
+        let io_error_flags = match args.io_error_flags {
+            0 => NexusErrStore::IO_FAILED_FLAG,
+            flags => flags,
+        };
+        let age_ns = match args.age_ns {
+            0 => None,
+            age => Some(age),
+        };
+        let errors = nexus_lookup(&args.uuid)?.error_record_list(
+            &args.uri,
+            io_op_flags,
+            io_error_flags,
+            age_ns,
+        )?;
+        let reply = ChildErrorsReply {
+            errors: errors.into_iter().map(|r| r.into()).collect(),
+        };
+        trace!("{:?}", reply);
+        Ok(Response::new(reply))
+    }
+
     #[instrument(level = "debug", err)]
     async fn start_rebuild(
         &self,
@@ -362,8 +574,18 @@ impl mayastor_server::Mayastor for MayastorSvc {
     ) -> GrpcResult<Null> {
         let args = request.into_inner();
         trace!("{:?}", args);
+        let limits = args.limits.map(|l| l.into()).unwrap_or_default();
         locally! { async move {
-            nexus_lookup(&args.uuid)?.start_rebuild(&args.uri).await.map(|_|{})
+            let nexus = nexus_lookup(&args.uuid)?;
+            nexus
+                .start_rebuild_many(
+                    &args.uri,
+                    &args.more_uris,
+                    limits,
+                    args.verify,
+                )
+                .await
+                .map(|_| {})
         }};
 
         Ok(Response::new(Null {}))
@@ -432,4 +654,163 @@ impl mayastor_server::Mayastor for MayastorSvc {
             nexus_lookup(&args.uuid)?.get_rebuild_progress(&args.uri)
         }}))
     }
+
+    #[instrument(level = "debug", err)]
+    async fn get_rebuild_stats(
+        &self,
+        request: Request<RebuildStatsRequest>,
+    ) -> GrpcResult<RebuildStatsReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(locally! { async move {
+            nexus_lookup(&args.uuid)?.get_rebuild_stats(&args.uri)
+        }}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_rebuild_history(
+        &self,
+        request: Request<RebuildHistoryRequest>,
+    ) -> GrpcResult<RebuildHistoryReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(locally! { async move {
+            let uri = Some(args.uri.as_str()).filter(|uri| !uri.is_empty());
+            Ok::<_, nexus_bdev::Error>(
+                nexus_lookup(&args.uuid)?.get_rebuild_history(uri),
+            )
+        }}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn set_rebuild_limits(
+        &self,
+        request: Request<SetRebuildLimitsRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let limits = args.limits.unwrap_or_default();
+
+        if args.uuid.is_empty() {
+            if limits.concurrency != 0 {
+                return Err(Status::invalid_argument(
+                    "Concurrency can only be limited per rebuild",
+                ));
+            }
+            RebuildJob::set_global_bandwidth(limits.bandwidth_mbps);
+            info!(
+                "Limited the bandwidth of all rebuilds to {} MiB/s",
+                limits.bandwidth_mbps
+            );
+        } else {
+            let limits = limits.into();
+            locally! { async move {
+                nexus_lookup(&args.uuid)?
+                    .set_rebuild_limits(&args.uri, limits)
+                    .await
+            }};
+        }
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn start_scrub(
+        &self,
+        request: Request<StartScrubRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        locally! { async move {
+            let authority =
+                Some(args.authority.as_str()).filter(|a| !a.is_empty());
+            nexus_lookup(&args.uuid)?.start_scrub(authority).await
+        }};
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn stop_scrub(
+        &self,
+        request: Request<ScrubRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        locally! { async move {
+            nexus_lookup(&args.uuid)?.stop_scrub().await
+        }};
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn pause_scrub(
+        &self,
+        request: Request<ScrubRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        locally! { async move {
+            nexus_lookup(&args.uuid)?.pause_scrub().await
+        }};
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn resume_scrub(
+        &self,
+        request: Request<ScrubRequest>,
+    ) -> GrpcResult<Null> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        locally! { async move {
+            nexus_lookup(&args.uuid)?.resume_scrub().await
+        }};
+
+        Ok(Response::new(Null {}))
+    }
+
+    #[instrument(level = "debug", err)]
+    async fn get_scrub_progress(
+        &self,
+        request: Request<ScrubRequest>,
+    ) -> GrpcResult<ScrubProgressReply> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        Ok(Response::new(locally! { async move {
+            nexus_lookup(&args.uuid)?.get_scrub_progress()
+        }}))
+    }
+
+    type WatchEventsStream = mpsc::Receiver<Result<Event, Status>>;
+
+    #[instrument(level = "debug", err)]
+    async fn watch_events(
+        &self,
+        request: Request<WatchEventsRequest>,
+    ) -> GrpcResult<Self::WatchEventsStream> {
+        let args = request.into_inner();
+        trace!("{:?}", args);
+        let mut subscription = events::subscribe(args.instance, args.since)?;
+
+        let (mut sender, receiver) = mpsc::channel(WATCH_EVENTS_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let event = subscription
+                    .next()
+                    .await
+                    .map(Event::from)
+                    .map_err(Status::from);
+                // the stream ends with the error, the client can resume
+                // from the last event it has received
+                let lost = event.is_err();
+                if sender.send(event).await.is_err() || lost {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(receiver))
+    }
 }
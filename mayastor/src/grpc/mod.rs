@@ -30,7 +30,7 @@ macro_rules! locally {
 
 mod bdev_grpc;
 mod mayastor_grpc;
-mod nexus_grpc;
+pub(crate) mod nexus_grpc;
 mod server;
 
 use crate::subsys::Config;
@@ -1,14 +1,24 @@
 //! Helpers related to nexus grpc methods.
 
 use rpc::mayastor as rpc;
-use std::convert::From;
+use std::{
+    convert::From,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
 use uuid::Uuid;
 
+use nix::errno::Errno;
+
 use crate::{
     bdev::nexus::{
         instances,
-        nexus_bdev::{Error, Nexus, NexusStatus},
-        nexus_child::{ChildStatus, NexusChild},
+        nexus_bdev::{Error, Nexus, NexusStatus, ReadPolicy, WritePolicy},
+        nexus_child::{ChildState, ChildStatus, NexusChild},
+        nexus_child_error_store::{
+            NexusChildErrorRecord,
+            NexusErrStore,
+            QueryType,
+        },
     },
     rebuild::RebuildJob,
 };
@@ -32,6 +42,45 @@ impl From<NexusStatus> for rpc::NexusState {
     }
 }
 
+impl From<NexusChildErrorRecord> for rpc::ChildErrorRecord {
+    fn from(record: NexusChildErrorRecord) -> Self {
+        // the record has a monotonic timestamp, which has to be converted
+        let time = SystemTime::now() - (Instant::now() - record.timestamp);
+        Self {
+            io_op: rpc::IoType::from_i32(record.io_op as i32)
+                .unwrap_or(rpc::IoType::Other) as i32,
+            io_error: record.io_error,
+            io_offset: record.io_offset,
+            io_num_blocks: record.io_num_blocks,
+            timestamp: time
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+        }
+    }
+}
+
+impl From<rpc::ReadPolicy> for ReadPolicy {
+    fn from(policy: rpc::ReadPolicy) -> Self {
+        match policy {
+            rpc::ReadPolicy::ReadRoundRobin => ReadPolicy::RoundRobin,
+            rpc::ReadPolicy::ReadLeastOutstanding => {
+                ReadPolicy::LeastOutstanding
+            }
+            rpc::ReadPolicy::ReadLowestLatency => ReadPolicy::LowestLatency,
+            rpc::ReadPolicy::ReadPreferLocal => ReadPolicy::PreferLocal,
+        }
+    }
+}
+
+impl From<rpc::WritePolicy> for WritePolicy {
+    fn from(policy: rpc::WritePolicy) -> Self {
+        match policy {
+            rpc::WritePolicy::WriteAll => WritePolicy::All,
+            rpc::WritePolicy::WriteQuorum => WritePolicy::Quorum,
+        }
+    }
+}
+
 impl NexusChild {
     /// Convert nexus child object to grpc representation.
     ///
@@ -44,6 +93,42 @@ impl NexusChild {
             rebuild_progress: self.get_rebuild_progress(),
         }
     }
+
+    /// Get IO statistics of the child in grpc representation. There are no
+    /// counters if the child is not open.
+    pub async fn stats_to_grpc(&self) -> rpc::ChildStats {
+        let stats = match self.bdev.as_ref() {
+            Some(bdev) if self.state == ChildState::Open => {
+                match bdev.stats().await {
+                    Ok(stat) => Some(stat.into()),
+                    Err(errno) => {
+                        warn!(
+                            "{}: failed to get stats of child {} (errno={})",
+                            self.parent, self.name, errno
+                        );
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+        let errors = |io_op_flags| {
+            self.err_store.as_ref().map_or(0, |store| {
+                store.query(
+                    io_op_flags,
+                    NexusErrStore::IO_FAILED_FLAG,
+                    None,
+                    QueryType::Total,
+                )
+            })
+        };
+        rpc::ChildStats {
+            uri: self.name.clone(),
+            stats,
+            read_errors: errors(NexusErrStore::READ_FLAG),
+            write_errors: errors(NexusErrStore::WRITE_FLAG),
+        }
+    }
 }
 
 impl Nexus {
@@ -65,6 +150,25 @@ impl Nexus {
             rebuilds: RebuildJob::count() as u32,
         }
     }
+
+    /// Get IO statistics of the nexus and of each of its children in grpc
+    /// representation.
+    pub async fn stats_to_grpc(&self) -> Result<rpc::StatNexusReply, Error> {
+        let stats =
+            self.bdev.stats().await.map_err(|errno| Error::NexusStats {
+                source: Errno::from_i32(errno.abs()),
+                name: self.name.clone(),
+            })?;
+        let mut children = Vec::new();
+        for child in &self.children {
+            children.push(child.stats_to_grpc().await);
+        }
+        Ok(rpc::StatNexusReply {
+            uuid: name_to_uuid(&self.name).to_string(),
+            stats: Some(stats.into()),
+            children,
+        })
+    }
 }
 
 /// Convert nexus name to uuid.
@@ -72,7 +176,7 @@ impl Nexus {
 /// This function never fails which means that if there is a nexus with
 /// unconventional name that likely means it was not created using nexus
 /// rpc api, we return the whole name without modifications as it is.
-fn name_to_uuid(name: &str) -> &str {
+pub(crate) fn name_to_uuid(name: &str) -> &str {
     if name.starts_with("nexus-") {
         &name[6 ..]
     } else {
@@ -12,15 +12,19 @@ extern crate spdk_sys;
 pub mod bdev;
 pub mod core;
 pub mod delay;
+pub mod events;
 pub mod ffihelper;
 pub mod grpc;
 pub mod jsonrpc;
 pub mod logger;
+pub mod metrics;
 pub mod nats;
 pub mod nexus_uri;
 pub mod pool;
 pub mod rebuild;
 pub mod replica;
+pub mod scrub;
+pub mod snapshot;
 pub mod subsys;
 pub mod target;
 
@@ -0,0 +1,478 @@
+//! HTTP endpoint serving the statistics of mayastor in Prometheus text format.
+//!
+//! The endpoint is optional and is enabled by specifying the address to listen
+//! on. The statistics are collected on the management core when the metrics
+//! are scraped, nothing is gathered in between the scrapes. The exception are
+//! the latency histograms which SPDK updates on every IO of the bdevs they
+//! are enabled for. Those are enabled for all nexus and replica bdevs when the
+//! endpoint starts, and for the ones created afterwards.
+
+use std::{
+    fmt::Display,
+    net::SocketAddr,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body,
+    Method,
+    Request,
+    Response,
+    Server,
+    StatusCode,
+};
+
+use crate::{
+    bdev::{nexus::instances, nexus_lookup, NexusErrStore, QueryType},
+    core::{Bdev, Histogram, Reactors, Stat},
+    grpc::nexus_grpc::name_to_uuid,
+    pool::PoolsIter,
+    replica::ReplicaIter,
+};
+
+/// The path which the metrics are served on
+const METRICS_PATH: &str = "/metrics";
+
+/// Content type of the Prometheus text format
+const TEXT_FORMAT: &str = "text/plain; version=0.0.4";
+
+/// Labels of a sample as (name, value) pairs
+type Labels = Vec<(&'static str, String)>;
+
+/// Set once the endpoint has been started
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Upper bounds of the latency histogram buckets in seconds
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.1, 0.5, 1.0,
+    10.0,
+];
+
+/// IO statistics of a nexus or a replica
+struct IoSample {
+    labels: Labels,
+    stat: Stat,
+    latency: Option<Histogram>,
+}
+
+/// Samples of all metric families taken at the time of a scrape
+#[derive(Default)]
+struct Samples {
+    nexus_io: Vec<IoSample>,
+    nexus_size: Vec<(Labels, u64)>,
+    nexus_state: Vec<(Labels, u64)>,
+    child_state: Vec<(Labels, u64)>,
+    child_errors: Vec<(Labels, u32)>,
+    rebuild_progress: Vec<(Labels, i32)>,
+    replica_io: Vec<IoSample>,
+    replica_size: Vec<(Labels, u64)>,
+    pool_capacity: Vec<(Labels, u64)>,
+    pool_used: Vec<(Labels, u64)>,
+    reactor_state: Vec<(Labels, u64)>,
+}
+
+/// Get IO statistics of the bdev, the failure is logged and skipped as the
+/// bdev may be going away while it is being scraped.
+async fn bdev_stats(bdev: Bdev) -> Option<Stat> {
+    match bdev.stats().await {
+        Ok(stat) => Some(stat),
+        Err(errno) => {
+            warn!("Failed to get stats for {} (errno={})", bdev.name(), errno);
+            None
+        }
+    }
+}
+
+/// Enable the latency histogram of a nexus or replica bdev if the metrics
+/// endpoint has been started. Must be run on the management core.
+pub async fn enable_latency_histogram(bdev: Bdev) {
+    if !ENABLED.load(Ordering::SeqCst) {
+        return;
+    }
+    if let Err(errno) = bdev.enable_latency_histogram().await {
+        warn!(
+            "Failed to enable latency histogram for {} (errno={})",
+            bdev.name(),
+            errno
+        );
+    }
+}
+
+/// Enable the latency histograms of all nexus and replica bdevs.
+async fn enable_latency_histograms() {
+    let mut bdevs = instances()
+        .iter()
+        .map(|n| n.bdev.clone())
+        .collect::<Vec<_>>();
+    for replica in ReplicaIter::new() {
+        bdevs.push(unsafe { (*replica.as_ptr()).bdev.into() });
+    }
+    for bdev in bdevs {
+        enable_latency_histogram(bdev).await;
+    }
+}
+
+/// Get the latency histogram of the bdev, failing the same way as the stats.
+/// Bdevs which have come about in a way which did not enable the histogram
+/// have none.
+async fn bdev_latency(bdev: Bdev) -> Option<Histogram> {
+    if !bdev.latency_histogram_enabled() {
+        return None;
+    }
+    match bdev.latency_histogram().await {
+        Ok(histogram) => Some(histogram),
+        Err(errno) => {
+            warn!(
+                "Failed to get latency histogram for {} (errno={})",
+                bdev.name(),
+                errno
+            );
+            None
+        }
+    }
+}
+
+impl Samples {
+    /// Collect the samples. Must be run on the management core.
+    async fn collect() -> Self {
+        let mut samples = Self::default();
+
+        // the nexus may be destroyed while waiting for its stats so it is
+        // looked up again afterwards
+        let names = instances()
+            .iter()
+            .map(|n| n.name.clone())
+            .collect::<Vec<_>>();
+        for name in names {
+            let bdev = match nexus_lookup(&name) {
+                Some(nexus) => nexus.bdev.clone(),
+                None => continue,
+            };
+            let stat = bdev_stats(bdev.clone()).await;
+            let latency = bdev_latency(bdev).await;
+            let nexus = match nexus_lookup(&name) {
+                Some(nexus) => nexus,
+                None => continue,
+            };
+            let uuid = name_to_uuid(&name).to_string();
+            let labels = vec![("uuid", uuid.clone())];
+
+            if let Some(stat) = stat {
+                samples.nexus_io.push(IoSample {
+                    labels: labels.clone(),
+                    stat,
+                    latency,
+                });
+            }
+            samples.nexus_size.push((labels.clone(), nexus.size));
+            for state in &["online", "degraded", "faulted"] {
+                let mut labels = labels.clone();
+                labels.push(("state", state.to_string()));
+                let value = (nexus.status().to_string() == *state) as u64;
+                samples.nexus_state.push((labels, value));
+            }
+
+            for child in &nexus.children {
+                let labels =
+                    vec![("nexus", uuid.clone()), ("uri", child.name.clone())];
+                for state in &["online", "degraded", "faulted"] {
+                    let mut labels = labels.clone();
+                    labels.push(("state", state.to_string()));
+                    let value = (child.status().to_string() == *state) as u64;
+                    samples.child_state.push((labels, value));
+                }
+                if let Some(store) = child.err_store.as_ref() {
+                    for (op, flag) in &[
+                        ("read", NexusErrStore::READ_FLAG),
+                        ("write", NexusErrStore::WRITE_FLAG),
+                    ] {
+                        let mut labels = labels.clone();
+                        labels.push(("op", op.to_string()));
+                        let count = store.query(
+                            *flag,
+                            NexusErrStore::IO_FAILED_FLAG,
+                            None,
+                            QueryType::Total,
+                        );
+                        samples.child_errors.push((labels, count));
+                    }
+                }
+                let progress = child.get_rebuild_progress();
+                if progress >= 0 {
+                    samples.rebuild_progress.push((labels, progress));
+                }
+            }
+        }
+
+        for replica in ReplicaIter::new().collect::<Vec<_>>() {
+            let labels = vec![
+                ("uuid", replica.get_uuid().to_string()),
+                ("pool", replica.get_pool_name().to_string()),
+            ];
+            samples
+                .replica_size
+                .push((labels.clone(), replica.get_size()));
+            let bdev: Bdev = unsafe { (*replica.as_ptr()).bdev.into() };
+            if let Some(stat) = bdev_stats(bdev.clone()).await {
+                samples.replica_io.push(IoSample {
+                    labels,
+                    stat,
+                    latency: bdev_latency(bdev).await,
+                });
+            }
+        }
+
+        for pool in PoolsIter::new() {
+            let labels = vec![("name", pool.get_name().to_string())];
+            let capacity = pool.get_capacity();
+            samples.pool_capacity.push((labels.clone(), capacity));
+            samples
+                .pool_used
+                .push((labels, capacity.saturating_sub(pool.get_free())));
+        }
+
+        for reactor in Reactors::iter() {
+            let labels = vec![
+                ("core", reactor.core().to_string()),
+                ("state", reactor.get_state().to_string().to_lowercase()),
+            ];
+            samples.reactor_state.push((labels, 1));
+        }
+
+        samples
+    }
+
+    /// Render the samples in Prometheus text format.
+    fn render(&self) -> String {
+        let mut out = Output::default();
+
+        out.io("nexus", &self.nexus_io);
+        out.family(
+            "mayastor_nexus_size_bytes",
+            "Size of the nexus",
+            "gauge",
+            &self.nexus_size,
+        );
+        out.family(
+            "mayastor_nexus_state",
+            "Current state of the nexus",
+            "gauge",
+            &self.nexus_state,
+        );
+        out.family(
+            "mayastor_nexus_child_state",
+            "Current state of the child of the nexus",
+            "gauge",
+            &self.child_state,
+        );
+        out.family(
+            "mayastor_nexus_child_errors",
+            "Number of failed IOs remembered in the error store of the child",
+            "gauge",
+            &self.child_errors,
+        );
+        out.family(
+            "mayastor_nexus_child_rebuild_progress_percent",
+            "Progress of the rebuild of the child",
+            "gauge",
+            &self.rebuild_progress,
+        );
+        out.io("replica", &self.replica_io);
+        out.family(
+            "mayastor_replica_size_bytes",
+            "Size of the replica",
+            "gauge",
+            &self.replica_size,
+        );
+        out.family(
+            "mayastor_pool_capacity_bytes",
+            "Capacity of the pool",
+            "gauge",
+            &self.pool_capacity,
+        );
+        out.family(
+            "mayastor_pool_used_bytes",
+            "Used space of the pool",
+            "gauge",
+            &self.pool_used,
+        );
+        out.family(
+            "mayastor_reactor_state",
+            "Current state of the reactor",
+            "gauge",
+            &self.reactor_state,
+        );
+
+        out.0
+    }
+}
+
+/// Text of the metrics being rendered
+#[derive(Default)]
+struct Output(String);
+
+impl Output {
+    /// Write the help and type of the metric family.
+    fn header(&mut self, name: &str, help: &str, kind: &str) {
+        self.0.push_str(&format!("# HELP {} {}\n", name, help));
+        self.0.push_str(&format!("# TYPE {} {}\n", name, kind));
+    }
+
+    /// Write a single sample.
+    fn sample(
+        &mut self,
+        name: &str,
+        labels: &[(&str, String)],
+        value: impl Display,
+    ) {
+        let labels = labels
+            .iter()
+            .map(|(k, v)| {
+                format!(
+                    "{}=\"{}\"",
+                    k,
+                    v.replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                        .replace('\n', "\\n")
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+        self.0
+            .push_str(&format!("{}{{{}}} {}\n", name, labels, value));
+    }
+
+    /// Write the metric family with all its samples.
+    fn family<T: Display>(
+        &mut self,
+        name: &str,
+        help: &str,
+        kind: &str,
+        samples: &[(Labels, T)],
+    ) {
+        self.header(name, help, kind);
+        for (labels, value) in samples {
+            self.sample(name, labels, value);
+        }
+    }
+
+    /// Write the IO metric families of the nexus or replica objects. The
+    /// latency histogram covers all IOs since the endpoint has been started,
+    /// or since the object has been created if that was later,
+    /// its sum is estimated from the ends of the buckets kept by SPDK which
+    /// are less than 1% wide.
+    fn io(&mut self, object: &str, samples: &[IoSample]) {
+        let counters: [(&str, &str, fn(&Stat) -> u64); 4] = [
+            ("reads_total", "Number of read operations", |s| {
+                s.num_read_ops
+            }),
+            ("writes_total", "Number of write operations", |s| {
+                s.num_write_ops
+            }),
+            ("read_bytes_total", "Number of bytes read", |s| s.bytes_read),
+            ("written_bytes_total", "Number of bytes written", |s| {
+                s.bytes_written
+            }),
+        ];
+        for (suffix, help, value) in counters.iter() {
+            let name = format!("mayastor_{}_{}", object, suffix);
+            self.header(
+                &name,
+                &format!("{} of the {}", help, object),
+                "counter",
+            );
+            for s in samples {
+                self.sample(&name, &s.labels, value(&s.stat));
+            }
+        }
+
+        let name = format!("mayastor_{}_latency_seconds", object);
+        self.header(
+            &name,
+            &format!("Latency of IO operations of the {}", object),
+            "histogram",
+        );
+        for s in samples {
+            let histogram = match s.latency.as_ref() {
+                Some(histogram) if histogram.tick_rate > 0 => histogram,
+                _ => continue,
+            };
+            let seconds =
+                |ticks: u64| ticks as f64 / histogram.tick_rate as f64;
+            let count: u64 = histogram.buckets.iter().map(|(_, c)| c).sum();
+            for le in LATENCY_BUCKETS.iter() {
+                let mut labels = s.labels.clone();
+                labels.push(("le", le.to_string()));
+                // a bucket of SPDK ends before its end tick
+                let value: u64 = histogram
+                    .buckets
+                    .iter()
+                    .filter(|(end, _)| seconds(end - 1) <= *le)
+                    .map(|(_, c)| c)
+                    .sum();
+                self.sample(&format!("{}_bucket", name), &labels, value);
+            }
+            let mut labels = s.labels.clone();
+            labels.push(("le", "+Inf".to_string()));
+            self.sample(&format!("{}_bucket", name), &labels, count);
+
+            let sum: f64 = histogram
+                .buckets
+                .iter()
+                .map(|(end, c)| seconds(*end) * *c as f64)
+                .sum();
+            self.sample(&format!("{}_sum", name), &s.labels, sum);
+            self.sample(&format!("{}_count", name), &s.labels, count);
+        }
+    }
+}
+
+/// Serve the metrics or complain about anything else.
+async fn handle(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
+    if req.method() != Method::GET || req.uri().path() != METRICS_PATH {
+        let mut not_found = Response::new(Body::empty());
+        *not_found.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(not_found);
+    }
+
+    let hdl = Reactors::current()
+        .spawn_local(async { Samples::collect().await.render() });
+    let text = hdl.await.unwrap();
+
+    let mut response = Response::new(Body::from(text));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, TEXT_FORMAT.parse().unwrap());
+    Ok(response)
+}
+
+/// Serve the metrics on the given address until the server fails.
+pub async fn metrics_run(endpoint: &str) -> Result<(), ()> {
+    info!("Metrics endpoint configured at address {}", endpoint);
+    let addr: SocketAddr = match endpoint.parse() {
+        Ok(addr) => addr,
+        Err(e) => {
+            error!("Invalid metrics endpoint {}: {}", endpoint, e);
+            return Err(());
+        }
+    };
+
+    ENABLED.store(true, Ordering::SeqCst);
+    Reactors::current()
+        .spawn_local(enable_latency_histograms())
+        .await
+        .unwrap();
+
+    let make_svc = make_service_fn(|_conn| async {
+        Ok::<_, hyper::Error>(service_fn(handle))
+    });
+    match Server::bind(&addr).serve(make_svc).await {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            error!("Metrics endpoint failed with error: {}", e);
+            Err(())
+        }
+    }
+}
@@ -1,8 +1,10 @@
 //! NATS message bus connecting mayastor to control plane (moac).
 //!
-//! It is designed to make sending events to control plane easy in the future.
-//! That's the reason for global sender protected by the mutex, that normally
-//! would not be needed and currently is used only to terminate the message bus.
+//! Besides the periodic registration, state changes which the control plane
+//! should react to are published as events on well-known subjects. They are
+//! passed to the message bus through a bounded queue behind the global sender,
+//! so that the reactors never block on it. If the queue is full the event is
+//! dropped and the control plane learns about the change when it polls next.
 
 use std::{
     env,
@@ -25,11 +27,32 @@ use tokio_nats::{
     NatsConfigBuilder,
 };
 
+use crate::{
+    bdev::{ActionType, ChildStatus, NexusStatus},
+    events::EventKind,
+    grpc::nexus_grpc::name_to_uuid,
+    pool::mirrored_pool,
+    rebuild::RebuildState,
+};
+
 /// Mayastor sends registration messages in this interval (kind of heart-beat)
 const HB_INTERVAL: u64 = 10;
 
-/// The end of channel used to send messages to or terminate the NATS client.
-static SENDER: Lazy<Mutex<Option<mpsc::Sender<()>>>> =
+/// The number of events which can be queued before they are dropped
+const EVENT_QUEUE: usize = 256;
+
+/// Subjects of the events published on the message bus
+const CHILD_FAULTED: &str = "child.faulted";
+const CHILD_ERRORS: &str = "child.errors";
+const REBUILD_STARTED: &str = "rebuild.started";
+const REBUILD_COMPLETED: &str = "rebuild.completed";
+const REBUILD_FAILED: &str = "rebuild.failed";
+const NEXUS_DEGRADED: &str = "nexus.degraded";
+const NEXUS_ONLINE: &str = "nexus.online";
+const POOL_DEGRADED: &str = "pool.degraded";
+
+/// The end of channel used to send events to or terminate the NATS client.
+static SENDER: Lazy<Mutex<Option<mpsc::Sender<Event>>>> =
     Lazy::new(|| Mutex::new(None));
 
 /// Errors for pool operations.
@@ -71,6 +94,11 @@ enum Error {
     QueueRegister { cause: TokioNatsError },
     #[snafu(display("Failed to queue deregister request: {:?}", cause))]
     QueueDeregister { cause: TokioNatsError },
+    #[snafu(display("Failed to queue event {}: {:?}", subject, cause))]
+    QueueEvent {
+        cause: TokioNatsError,
+        subject: String,
+    },
 }
 
 /// Register message payload
@@ -87,6 +115,128 @@ struct DeregisterArgs {
     id: String,
 }
 
+/// The object an event is about
+#[derive(Serialize, Debug)]
+#[serde(untagged)]
+enum EventObject {
+    Nexus {
+        uuid: String,
+    },
+    Child {
+        nexus: String,
+        uri: String,
+        reason: String,
+    },
+    Pool {
+        name: String,
+    },
+}
+
+/// Event message payload
+#[derive(Serialize, Debug)]
+struct EventArgs {
+    id: String,
+    #[serde(flatten)]
+    object: EventObject,
+}
+
+/// Event queued for the message bus
+#[derive(Debug)]
+struct Event {
+    subject: &'static str,
+    object: EventObject,
+}
+
+impl Event {
+    /// Translate the state change to the event for the control plane if it
+    /// is interested in it. The nexus mirroring the disks of a pool is not
+    /// known to the control plane, so changes of its status are reported as
+    /// changes of the pool, the pool being looked up by `pool_on_nexus`.
+    fn from_kind(
+        kind: &EventKind,
+        pool_on_nexus: impl Fn(&str) -> Option<String>,
+    ) -> Option<Self> {
+        let (subject, object) = match kind {
+            EventKind::ChildStatus {
+                nexus,
+                child,
+                status: ChildStatus::Faulted,
+                reason,
+            } if pool_on_nexus(nexus).is_none() => (
+                CHILD_FAULTED,
+                EventObject::Child {
+                    nexus: name_to_uuid(nexus).to_string(),
+                    uri: child.clone(),
+                    reason: reason.clone().unwrap_or_default(),
+                },
+            ),
+            // the other actions are reported by the state changes they cause
+            EventKind::ChildErrors {
+                nexus,
+                child,
+                action: ActionType::Warn,
+                reason,
+            } if pool_on_nexus(nexus).is_none() => (
+                CHILD_ERRORS,
+                EventObject::Child {
+                    nexus: name_to_uuid(nexus).to_string(),
+                    uri: child.clone(),
+                    reason: reason.clone(),
+                },
+            ),
+            EventKind::RebuildState {
+                nexus,
+                child,
+                state,
+            } if pool_on_nexus(nexus).is_none() => {
+                let subject = match state {
+                    RebuildState::Running => REBUILD_STARTED,
+                    RebuildState::Completed => REBUILD_COMPLETED,
+                    RebuildState::Failed => REBUILD_FAILED,
+                    _ => return None,
+                };
+                (
+                    subject,
+                    EventObject::Child {
+                        nexus: name_to_uuid(nexus).to_string(),
+                        uri: child.clone(),
+                        reason: String::new(),
+                    },
+                )
+            }
+            EventKind::NexusStatus {
+                nexus,
+                status,
+            } => match (pool_on_nexus(nexus), status) {
+                (Some(name), NexusStatus::Degraded) => (
+                    POOL_DEGRADED,
+                    EventObject::Pool {
+                        name,
+                    },
+                ),
+                (None, NexusStatus::Degraded) => (
+                    NEXUS_DEGRADED,
+                    EventObject::Nexus {
+                        uuid: name_to_uuid(nexus).to_string(),
+                    },
+                ),
+                (None, NexusStatus::Online) => (
+                    NEXUS_ONLINE,
+                    EventObject::Nexus {
+                        uuid: name_to_uuid(nexus).to_string(),
+                    },
+                ),
+                _ => return None,
+            },
+            _ => return None,
+        };
+        Some(Self {
+            subject,
+            object,
+        })
+    }
+}
+
 /// Resolve a hostname or return an error.
 async fn resolve(name: &str) -> Result<String, Error> {
     let mut ips = lookup_host(name).await.context(ResolveServer {
@@ -134,11 +284,12 @@ impl MessageBus {
         }
     }
 
-    /// Connect to the server and start emitting periodic register messages.
-    /// Runs until the sender side of mpsc channel is closed.
+    /// Connect to the server and start emitting periodic register messages
+    /// and the queued events. Runs until the sender side of mpsc channel is
+    /// closed.
     pub async fn run(
         &mut self,
-        mut receiver: mpsc::Receiver<()>,
+        mut receiver: mpsc::Receiver<Event>,
     ) -> Result<(), Error> {
         assert!(self.client.is_none());
 
@@ -160,22 +311,29 @@ impl MessageBus {
             "Registering '{}' and grpc server {} ...",
             self.node, self.grpc_endpoint
         );
-        loop {
+        'outer: loop {
             if let Err(err) = self.register().await {
                 error!("Registration failed: {:?}", err);
             };
-            let _res = select! {
-                () = delay_for(self.hb_interval).fuse() => (),
-                msg = receiver.next() => {
-                    match msg {
-                        Some(_) => warn!("Messages have not been implemented yet"),
-                        None => {
-                            info!("Terminating the NATS client");
-                            break;
+            let mut heartbeat = delay_for(self.hb_interval).fuse();
+            loop {
+                select! {
+                    () = heartbeat => break,
+                    msg = receiver.next() => {
+                        match msg {
+                            Some(event) => {
+                                if let Err(err) = self.publish(event).await {
+                                    error!("{}", err);
+                                }
+                            }
+                            None => {
+                                info!("Terminating the NATS client");
+                                break 'outer;
+                            }
                         }
                     }
-                }
-            };
+                };
+            }
         }
 
         if let Err(err) = self.deregister().await {
@@ -233,6 +391,26 @@ impl MessageBus {
         Ok(())
     }
 
+    /// Send an event to the NATS server.
+    async fn publish(&mut self, event: Event) -> Result<(), Error> {
+        let payload = EventArgs {
+            id: self.node.clone(),
+            object: event.object,
+        };
+        match &mut self.client {
+            Some(client) => client
+                .publish(event.subject, serde_json::to_vec(&payload).unwrap())
+                .await
+                .map_err(|cause| Error::QueueEvent {
+                    cause,
+                    subject: event.subject.to_owned(),
+                })?,
+            None => return Err(Error::NotStarted {}),
+        }
+        debug!("Published event {} {:?}", event.subject, payload.object);
+        Ok(())
+    }
+
     /// Send a deregister message to the NATS server.
     async fn deregister(&mut self) -> Result<(), Error> {
         let payload = DeregisterArgs {
@@ -262,7 +440,7 @@ pub async fn message_bus_run(
     node: &str,
     grpc_endpoint: &str,
 ) -> Result<(), ()> {
-    let (sender, receiver) = mpsc::channel::<()>(1);
+    let (sender, receiver) = mpsc::channel::<Event>(EVENT_QUEUE);
     {
         let mut sender_maybe = SENDER.lock().unwrap();
         if sender_maybe.is_some() {
@@ -285,3 +463,169 @@ pub fn message_bus_stop() {
     // this will free the sender and unblock the receiver waiting for a message
     let _sender_maybe = SENDER.lock().unwrap().take();
 }
+
+/// Queue the event for the control plane if it is interested in it and the
+/// message bus has been started. This never blocks, the event is dropped if
+/// the queue is full.
+pub fn message_bus_publish(kind: &EventKind) {
+    let mut sender_maybe = SENDER.lock().unwrap();
+    let sender = match sender_maybe.as_mut() {
+        Some(sender) => sender,
+        None => return,
+    };
+    if let Some(event) = Event::from_kind(kind, mirrored_pool) {
+        if let Err(err) = sender.try_send(event) {
+            if err.is_full() {
+                warn!("Event queue of the message bus is full, dropping event");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEXUS: &str = "nexus-a6a6ab5e-c6f9-4dd5-a3a6-e8f9fc1b7c1a";
+    const UUID: &str = "a6a6ab5e-c6f9-4dd5-a3a6-e8f9fc1b7c1a";
+    const POOL_NEXUS: &str = "pool-mirror";
+
+    fn pool_on_nexus(nexus: &str) -> Option<String> {
+        if nexus == POOL_NEXUS {
+            Some("pool".to_owned())
+        } else {
+            None
+        }
+    }
+
+    // subject and JSON payload of the event published for the state change
+    fn publish(kind: EventKind) -> Option<(&'static str, serde_json::Value)> {
+        Event::from_kind(&kind, pool_on_nexus).map(|e| {
+            let payload = EventArgs {
+                id: "node".to_owned(),
+                object: e.object,
+            };
+            (e.subject, serde_json::to_value(&payload).unwrap())
+        })
+    }
+
+    fn child_status(nexus: &str, status: ChildStatus) -> EventKind {
+        EventKind::ChildStatus {
+            nexus: nexus.to_owned(),
+            child: "aio:///disk".to_owned(),
+            status,
+            reason: Some("io error".to_owned()),
+        }
+    }
+
+    fn rebuild_state(state: RebuildState) -> EventKind {
+        EventKind::RebuildState {
+            nexus: NEXUS.to_owned(),
+            child: "aio:///disk".to_owned(),
+            state,
+        }
+    }
+
+    fn nexus_status(nexus: &str, status: NexusStatus) -> EventKind {
+        EventKind::NexusStatus {
+            nexus: nexus.to_owned(),
+            status,
+        }
+    }
+
+    #[test]
+    fn child_events() {
+        assert_eq!(
+            publish(child_status(NEXUS, ChildStatus::Faulted)),
+            Some((
+                CHILD_FAULTED,
+                serde_json::json!({
+                    "id": "node",
+                    "nexus": UUID,
+                    "uri": "aio:///disk",
+                    "reason": "io error",
+                })
+            ))
+        );
+        assert_eq!(publish(child_status(NEXUS, ChildStatus::Degraded)), None);
+
+        let errors = |action| EventKind::ChildErrors {
+            nexus: NEXUS.to_owned(),
+            child: "aio:///disk".to_owned(),
+            action,
+            reason: "too many read errors".to_owned(),
+        };
+        assert_eq!(
+            publish(errors(ActionType::Warn)),
+            Some((
+                CHILD_ERRORS,
+                serde_json::json!({
+                    "id": "node",
+                    "nexus": UUID,
+                    "uri": "aio:///disk",
+                    "reason": "too many read errors",
+                })
+            ))
+        );
+        assert_eq!(publish(errors(ActionType::Fault)), None);
+    }
+
+    #[test]
+    fn rebuild_events() {
+        let subject = |state| publish(rebuild_state(state)).map(|e| e.0);
+        assert_eq!(subject(RebuildState::Running), Some(REBUILD_STARTED));
+        assert_eq!(subject(RebuildState::Completed), Some(REBUILD_COMPLETED));
+        assert_eq!(subject(RebuildState::Failed), Some(REBUILD_FAILED));
+        assert_eq!(subject(RebuildState::Paused), None);
+        assert_eq!(subject(RebuildState::Stopped), None);
+
+        assert_eq!(
+            publish(rebuild_state(RebuildState::Completed)).unwrap().1,
+            serde_json::json!({
+                "id": "node",
+                "nexus": UUID,
+                "uri": "aio:///disk",
+                "reason": "",
+            })
+        );
+    }
+
+    #[test]
+    fn nexus_events() {
+        assert_eq!(
+            publish(nexus_status(NEXUS, NexusStatus::Degraded)),
+            Some((
+                NEXUS_DEGRADED,
+                serde_json::json!({ "id": "node", "uuid": UUID })
+            ))
+        );
+        assert_eq!(
+            publish(nexus_status(NEXUS, NexusStatus::Online)),
+            Some((
+                NEXUS_ONLINE,
+                serde_json::json!({ "id": "node", "uuid": UUID })
+            ))
+        );
+        assert_eq!(publish(nexus_status(NEXUS, NexusStatus::Faulted)), None);
+    }
+
+    #[test]
+    // the nexus of a mirrored pool is reported as the pool
+    fn pool_events() {
+        assert_eq!(
+            publish(nexus_status(POOL_NEXUS, NexusStatus::Degraded)),
+            Some((
+                POOL_DEGRADED,
+                serde_json::json!({ "id": "node", "name": "pool" })
+            ))
+        );
+        assert_eq!(
+            publish(nexus_status(POOL_NEXUS, NexusStatus::Online)),
+            None
+        );
+        assert_eq!(
+            publish(child_status(POOL_NEXUS, ChildStatus::Faulted)),
+            None
+        );
+    }
+}
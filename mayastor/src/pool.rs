@@ -2,10 +2,20 @@
 //!
 //! They provide abstraction on top of aio and uring bdev, lvol store, etc
 //! and export simple-to-use json-rpc methods for managing pools.
+//!
+//! A pool spanning several disks has one of two layouts. The disks of a
+//! striped pool are the base bdevs of the SPDK RAID-0 bdev, which spreads
+//! the data over all of them in strips of 64KiB. This is not a linear
+//! concatenation: the capacity is the smallest disk times their number and
+//! the pool is lost together with any of its disks. The disks
+//! of a mirrored pool are the children of a nexus, which keeps a full copy
+//! of the lvol store on each of them, rebuilds a disk which has been replaced
+//! and keeps the pool going in degraded state as long as one disk is left.
 
 use std::{
     ffi::{c_void, CStr, CString},
     os::raw::c_char,
+    ptr::null_mut,
 };
 
 use futures::channel::oneshot;
@@ -18,6 +28,14 @@ use spdk_sys::{
     bdev_aio_delete,
     create_aio_bdev as create_uring_bdev,
     lvol_store_bdev,
+    raid_bdev_add_base_devices,
+    raid_bdev_config,
+    raid_bdev_config_add,
+    raid_bdev_config_add_base_bdev,
+    raid_bdev_config_cleanup,
+    raid_bdev_config_find_by_name,
+    raid_bdev_create,
+    raid_bdev_remove_base_devices,
     spdk_bs_free_cluster_count,
     spdk_bs_get_cluster_size,
     spdk_bs_total_data_cluster_count,
@@ -30,12 +48,22 @@ use spdk_sys::{
     vbdev_lvs_destruct,
     vbdev_lvs_examine,
     LVS_CLEAR_WITH_NONE,
+    RAID0,
 };
 
 use crate::{
-    bdev::{util::uring, Uri},
+    bdev::{
+        nexus_create,
+        nexus_lookup,
+        util::uring,
+        ChildStatus,
+        NexusStatus,
+        Uri,
+    },
     core::{Bdev, Share},
+    events::{self, EventKind, Lifecycle},
     ffihelper::{cb_arg, done_cb},
+    metrics,
     nexus_uri::{bdev_destroy, NexusBdevError},
     replica::ReplicaIter,
 };
@@ -45,10 +73,13 @@ use crate::{
 #[snafu(visibility = "pub(crate)")]
 pub enum Error {
     #[snafu(display(
-        "Invalid number of disks specified: should be 1, got {}",
+        "Invalid number of disks specified: should be 1 to {}, got {}",
+        MAX_POOL_DISKS,
         num
     ))]
     BadNumDisks { num: usize },
+    #[snafu(display("Invalid pool layout: {}", layout))]
+    InvalidLayout { layout: i32 },
     #[snafu(display(
         "{} bdev {} already exists or parameters are invalid",
         bdev_if,
@@ -77,6 +108,36 @@ pub enum Error {
     DeviceAlreadyUsed { name: String },
     #[snafu(display("Failed to import the pool {} (errno={})", name, errno))]
     FailedImport { name: String, errno: i32 },
+    #[snafu(display(
+        "Failed to stripe the disks of the pool {} (errno={})",
+        name,
+        errno
+    ))]
+    FailedStripe { name: String, errno: i32 },
+    #[snafu(display(
+        "The disks of the pool {} differ in size, which a stripe does not support",
+        name
+    ))]
+    UnequalDisks { name: String },
+    #[snafu(display(
+        "Failed to mirror the disks of the pool {}: {}",
+        name,
+        msg
+    ))]
+    FailedMirror { name: String, msg: String },
+    #[snafu(display("The pool {} is not mirrored", name))]
+    NotMirrored { name: String },
+    #[snafu(display(
+        "Failed to add mirror {} to the pool {}: {}",
+        disk,
+        name,
+        msg
+    ))]
+    FailedAddMirror {
+        name: String,
+        disk: String,
+        msg: String,
+    },
     #[snafu(display("Failed to unshare replica: {}", msg))]
     FailedUnshareReplica { msg: String },
     #[snafu(display("Failed to destroy pool {} (errno={})", name, errno))]
@@ -102,6 +163,9 @@ impl From<Error> for tonic::Status {
             Error::BadNumDisks {
                 ..
             } => Self::invalid_argument(e.to_string()),
+            Error::InvalidLayout {
+                ..
+            } => Self::invalid_argument(e.to_string()),
             Error::BadBdev {
                 ..
             } => Self::invalid_argument(e.to_string()),
@@ -138,6 +202,21 @@ impl From<Error> for tonic::Status {
             Error::FailedImport {
                 ..
             } => Self::internal(e.to_string()),
+            Error::FailedStripe {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::UnequalDisks {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+            Error::FailedMirror {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::NotMirrored {
+                ..
+            } => Self::failed_precondition(e.to_string()),
+            Error::FailedAddMirror {
+                ..
+            } => Self::internal(e.to_string()),
             Error::FailedUnshareReplica {
                 ..
             } => Self::internal(e.to_string()),
@@ -153,6 +232,13 @@ impl From<Error> for tonic::Status {
 
 type Result<T> = std::result::Result<T, Error>;
 
+/// The maximum number of disks of a pool as the RAID-0 bdev counts its base
+/// bdevs in a byte.
+const MAX_POOL_DISKS: usize = u8::MAX as usize;
+
+/// Strip size of the RAID-0 bdev of a striped pool in KiB.
+const STRIP_SIZE_KB: u32 = 64;
+
 /// Wrapper for create aio or uring bdev C function
 pub fn create_base_bdev(
     file: &str,
@@ -204,6 +290,264 @@ extern "C" fn pool_done_cb(
     sender.send(errno).expect("Receiver is gone");
 }
 
+/// Create the RAID-0 bdev which stripes the disks of the pool and
+/// return its name. The bdev is registered as soon as all disks are claimed.
+/// The RAID-0 bdev only uses as much of each disk as the smallest one holds,
+/// and as SPDK does not concatenate bdevs, disks which differ in size are
+/// refused rather than wasted.
+async fn create_stripe_bdev(name: &str, disks: &[String]) -> Result<String> {
+    let sizes = disks
+        .iter()
+        .map(|d| Bdev::lookup_by_name(d).map(|b| b.size_in_bytes()))
+        .collect::<Vec<_>>();
+    if sizes.iter().any(|s| *s != sizes[0]) {
+        return Err(Error::UnequalDisks {
+            name: String::from(name),
+        });
+    }
+
+    let raid_name = format!("{}-stripe", name);
+    let c_name = CString::new(raid_name.clone()).unwrap();
+    let mut cfg: *mut raid_bdev_config = null_mut();
+
+    debug!(
+        "Creating RAID-0 bdev {} of {} disks",
+        raid_name,
+        disks.len()
+    );
+    let rc = unsafe {
+        raid_bdev_config_add(
+            c_name.as_ptr(),
+            STRIP_SIZE_KB,
+            disks.len() as u8,
+            RAID0,
+            &mut cfg,
+        )
+    };
+    if rc != 0 {
+        return Err(Error::FailedStripe {
+            name: String::from(name),
+            errno: rc,
+        });
+    }
+
+    let mut rc = 0;
+    for (slot, disk) in disks.iter().enumerate() {
+        let c_disk = CString::new(disk.as_str()).unwrap();
+        rc = unsafe {
+            raid_bdev_config_add_base_bdev(cfg, c_disk.as_ptr(), slot as u8)
+        };
+        if rc != 0 {
+            break;
+        }
+    }
+    if rc == 0 {
+        rc = unsafe { raid_bdev_create(cfg) };
+    }
+    if rc != 0 {
+        unsafe { raid_bdev_config_cleanup(cfg) };
+        return Err(Error::FailedStripe {
+            name: String::from(name),
+            errno: rc,
+        });
+    }
+
+    let rc = unsafe { raid_bdev_add_base_devices(cfg) };
+    if rc != 0 || Bdev::lookup_by_name(&raid_name).is_none() {
+        destroy_stripe_bdev(&raid_name, name).await?;
+        return Err(Error::FailedStripe {
+            name: String::from(name),
+            errno: rc,
+        });
+    }
+
+    info!("RAID-0 bdev {} was created", raid_name);
+    Ok(raid_name)
+}
+
+/// Return the name of the pool whose disks are mirrored by the nexus, if
+/// any. Only the nexus named after a pool is looked at, so that this is
+/// cheap enough for the IO path.
+pub(crate) fn mirrored_pool(nexus: &str) -> Option<String> {
+    if !nexus.ends_with(MIRROR_SUFFIX) {
+        return None;
+    }
+    Pool::lookup(&nexus[.. nexus.len() - MIRROR_SUFFIX.len()])
+        .filter(|p| p.get_base_bdev().name() == nexus)
+        .map(|p| p.get_name().to_owned())
+}
+
+/// Return names of the disks striped by the RAID-0 bdev or None if
+/// the bdev is not a RAID-0 bdev.
+fn stripe_disks(raid_name: &str) -> Option<Vec<String>> {
+    let c_name = CString::new(raid_name).unwrap();
+    unsafe {
+        let cfg = raid_bdev_config_find_by_name(c_name.as_ptr());
+        if cfg.is_null() {
+            return None;
+        }
+        Some(
+            (0 .. (*cfg).num_base_bdevs as usize)
+                .map(|i| {
+                    CStr::from_ptr((*(*cfg).base_bdev.add(i)).name)
+                        .to_string_lossy()
+                        .into_owned()
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Destroy the RAID-0 bdev of the pool, which releases (but does not
+/// destroy) its disks.
+async fn destroy_stripe_bdev(raid_name: &str, name: &str) -> Result<()> {
+    let c_name = CString::new(raid_name).unwrap();
+    let cfg = unsafe { raid_bdev_config_find_by_name(c_name.as_ptr()) };
+    if cfg.is_null() {
+        return Ok(());
+    }
+
+    let (sender, receiver) = oneshot::channel::<i32>();
+    unsafe {
+        raid_bdev_remove_base_devices(cfg, Some(done_cb), cb_arg(sender));
+    }
+    let errno = receiver.await.expect("Cancellation is not supported");
+    if errno != 0 {
+        return Err(Error::FailedDestroyBdev {
+            bdev: String::from(raid_name),
+            bdev_type: "raid".to_string(),
+            name: String::from(name),
+            errno,
+        });
+    }
+    unsafe { raid_bdev_config_cleanup(cfg) };
+
+    info!("RAID-0 bdev {} was destroyed", raid_name);
+    Ok(())
+}
+
+/// Suffix of the name of the nexus which mirrors the disks of a pool, after
+/// the name of the pool.
+const MIRROR_SUFFIX: &str = "-mirror";
+
+/// Create the nexus which mirrors the disks of the pool and return its name.
+/// The nexus is created without a size so that it spans the disks.
+async fn create_mirror_bdev(name: &str, disks: &[String]) -> Result<String> {
+    let nexus_name = format!("{}{}", name, MIRROR_SUFFIX);
+
+    debug!(
+        "Creating nexus {} mirroring {} disks",
+        nexus_name,
+        disks.len()
+    );
+    nexus_create(&nexus_name, 0, None, disks)
+        .await
+        .map_err(|e| Error::FailedMirror {
+            name: String::from(name),
+            msg: e.to_string(),
+        })?;
+
+    info!("nexus {} was created", nexus_name);
+    Ok(nexus_name)
+}
+
+/// Destroy a base bdev of the pool.
+async fn destroy_base_bdev(base_bdev_name: &str, name: &str) -> Result<()> {
+    let base_bdev = match Bdev::lookup_by_name(base_bdev_name) {
+        Some(bdev) => bdev,
+        None => {
+            // it's not an error if the base bdev disappeared but it is
+            // weird
+            warn!(
+                "Base bdev {} disappeared while destroying the pool {}",
+                base_bdev_name, name
+            );
+            return Ok(());
+        }
+    };
+    if let Some(uri) = base_bdev.bdev_uri() {
+        debug!("destroying bdev {}", uri);
+        bdev_destroy(&uri)
+            .await
+            .map_err(|_e| Error::FailedDestroyBdev {
+                bdev: base_bdev.name(),
+                bdev_type: base_bdev.driver(),
+                name: String::from(name),
+                errno: -1,
+            })
+            .map(|_| Ok(()))?
+    } else {
+        let base_bdev_type = base_bdev.driver();
+        debug!(
+            "Destroying bdev {} type {}",
+            base_bdev.name(),
+            base_bdev_type
+        );
+
+        let (sender, receiver) = oneshot::channel::<i32>();
+        if base_bdev_type == "aio" {
+            unsafe {
+                bdev_aio_delete(
+                    base_bdev.as_ptr(),
+                    Some(done_cb),
+                    cb_arg(sender),
+                );
+            }
+        } else {
+            unsafe {
+                delete_uring_bdev(
+                    base_bdev.as_ptr(),
+                    Some(done_cb),
+                    cb_arg(sender),
+                );
+            }
+        }
+        let bdev_errno = receiver.await.expect("Cancellation is not supported");
+        if bdev_errno != 0 {
+            Err(Error::FailedDestroyBdev {
+                bdev: String::from(base_bdev_name),
+                bdev_type: base_bdev_type,
+                name: String::from(name),
+                errno: bdev_errno,
+            })
+        } else {
+            info!(
+                "Base bdev {} type {} of the pool {} has been destroyed",
+                base_bdev_name, base_bdev_type, name
+            );
+            Ok(())
+        }
+    }
+}
+
+/// Disk claimed by a pool.
+pub struct PoolDisk {
+    /// name of the bdev of the disk
+    pub name: String,
+    /// the disk as it is reported to the user
+    pub uri: String,
+    /// health of the disk
+    pub state: rpc::PoolState,
+}
+
+impl PoolDisk {
+    /// The disk is faulted if its bdev has disappeared.
+    fn from_bdev(name: String) -> Self {
+        match Bdev::lookup_by_name(&name) {
+            Some(bdev) => Self {
+                uri: bdev.driver() + "://" + &name,
+                name,
+                state: rpc::PoolState::PoolOnline,
+            },
+            None => Self {
+                uri: name.clone(),
+                name,
+                state: rpc::PoolState::PoolFaulted,
+            },
+        }
+    }
+}
+
 /// Structure representing a pool which comprises lvol store and
 /// underlying bdev.
 ///
@@ -257,6 +601,69 @@ impl Pool {
         base_bdev_ptr.into()
     }
 
+    /// Get layout of the pool.
+    pub fn get_layout(&self) -> rpc::PoolLayout {
+        let base_bdev_name = self.get_base_bdev().name();
+        if nexus_lookup(&base_bdev_name).is_some() {
+            rpc::PoolLayout::PoolMirror
+        } else if stripe_disks(&base_bdev_name).is_some() {
+            rpc::PoolLayout::PoolStripe
+        } else {
+            rpc::PoolLayout::PoolSingle
+        }
+    }
+
+    /// Get disks of the pool along with their health.
+    pub fn get_disks(&self) -> Vec<PoolDisk> {
+        let base_bdev_name = self.get_base_bdev().name();
+
+        if let Some(nexus) = nexus_lookup(&base_bdev_name) {
+            return nexus
+                .children
+                .iter()
+                .map(|child| PoolDisk {
+                    name: Uri::parse(&child.name)
+                        .map_or(child.name.clone(), |uri| uri.get_name()),
+                    uri: child.name.clone(),
+                    state: match child.status() {
+                        ChildStatus::Online => rpc::PoolState::PoolOnline,
+                        ChildStatus::Degraded => rpc::PoolState::PoolDegraded,
+                        ChildStatus::Faulted => rpc::PoolState::PoolFaulted,
+                    },
+                })
+                .collect();
+        }
+
+        stripe_disks(&base_bdev_name)
+            .unwrap_or_else(|| vec![base_bdev_name])
+            .into_iter()
+            .map(PoolDisk::from_bdev)
+            .collect()
+    }
+
+    /// Get state of the pool. A mirrored pool is degraded as long as some of
+    /// its disks are online, while a striped pool needs all of them.
+    pub fn get_state(&self) -> rpc::PoolState {
+        match nexus_lookup(&self.get_base_bdev().name()) {
+            Some(nexus) => match nexus.status() {
+                NexusStatus::Online => rpc::PoolState::PoolOnline,
+                NexusStatus::Degraded => rpc::PoolState::PoolDegraded,
+                NexusStatus::Faulted => rpc::PoolState::PoolFaulted,
+            },
+            None => {
+                if self
+                    .get_disks()
+                    .iter()
+                    .all(|d| d.state == rpc::PoolState::PoolOnline)
+                {
+                    rpc::PoolState::PoolOnline
+                } else {
+                    rpc::PoolState::PoolFaulted
+                }
+            }
+        }
+    }
+
     /// Get capacity of the pool in bytes.
     pub fn get_capacity(&self) -> u64 {
         unsafe {
@@ -276,6 +683,46 @@ impl Pool {
             spdk_bs_free_cluster_count(lvs.blobstore) * cluster_size
         }
     }
+
+    /// Add another mirror to a mirrored pool, which is rebuilt from the other
+    /// mirrors in the background. This adds redundancy, not capacity: the
+    /// blobstore of SPDK has no means of growing an lvol store in place and
+    /// the RAID-0 bdev cannot take more disks once it has been created, so
+    /// neither kind of pool can grow. Growing a pool still takes moving its
+    /// replicas to a bigger one.
+    pub async fn add_mirror(&self, disk: &str) -> Result<()> {
+        let base_bdev = self.get_base_bdev();
+        let nexus = match nexus_lookup(&base_bdev.name()) {
+            Some(nexus) => nexus,
+            None => {
+                return Err(Error::NotMirrored {
+                    name: self.get_name().to_owned(),
+                })
+            }
+        };
+
+        // the nexus creates its children from URIs
+        let uri = if is_uri_scheme(&[disk.to_owned()]) {
+            disk.to_owned()
+        } else {
+            format!("aio://{}?blk_size={}", disk, base_bdev.block_len())
+        };
+        nexus.add_child(&uri, false).await.map_err(|e| {
+            Error::FailedAddMirror {
+                name: self.get_name().to_owned(),
+                disk: disk.to_owned(),
+                msg: e.to_string(),
+            }
+        })?;
+
+        info!(
+            "Disk {} has been added as a mirror to the pool {}",
+            disk,
+            self.get_name()
+        );
+        Ok(())
+    }
+
     /// Return raw pointer to spdk lvol store structure
     pub fn as_ptr(&self) -> *mut spdk_lvol_store {
         self.lvs_ptr
@@ -325,6 +772,10 @@ impl Pool {
         match Pool::lookup(&name) {
             Some(pool) => {
                 info!("The pool {} has been created", name);
+                events::publish(EventKind::Pool {
+                    name: String::from(name),
+                    change: Lifecycle::Created,
+                });
                 Ok(pool)
             }
             None => Err(Error::PoolGone {
@@ -361,6 +812,19 @@ impl Pool {
             match Pool::lookup(&name) {
                 Some(pool) => {
                     info!("The pool {} has been imported", name);
+                    for replica in ReplicaIter::new()
+                        .filter(|r| r.get_pool_name() == name)
+                        .collect::<Vec<_>>()
+                    {
+                        metrics::enable_latency_histogram(unsafe {
+                            (*replica.as_ptr()).bdev.into()
+                        })
+                        .await;
+                    }
+                    events::publish(EventKind::Pool {
+                        name: String::from(name),
+                        change: Lifecycle::Created,
+                    });
                     Ok(pool)
                 }
                 None => Err(Error::DeviceAlreadyUsed {
@@ -375,10 +839,11 @@ impl Pool {
         }
     }
 
-    /// Destroy the pool
+    /// Destroy the pool and the disks it has claimed.
     pub async fn destroy(self) -> Result<()> {
         let name = self.get_name().to_string();
         let base_bdev_name = self.get_base_bdev().name();
+        let disks = self.get_disks();
 
         debug!("Destroying the pool {}", name);
 
@@ -407,73 +872,33 @@ impl Pool {
             });
         }
 
-        // we will destroy base bdev now
-        let base_bdev = match Bdev::lookup_by_name(&base_bdev_name) {
-            Some(bdev) => bdev,
-            None => {
-                // it's not an error if the base bdev disappeared but it is
-                // weird
-                warn!(
-                    "Base bdev {} disappeared while destroying the pool {}",
-                    base_bdev_name, name
-                );
-                return Ok(());
-            }
-        };
-        if let Some(uri) = base_bdev.bdev_uri() {
-            debug!("destroying bdev {}", uri);
-            bdev_destroy(&uri)
+        // we will destroy base bdev(s) now
+        if let Some(nexus) = nexus_lookup(&base_bdev_name) {
+            // the nexus destroys the disks being its children
+            nexus
+                .destroy()
                 .await
                 .map_err(|_e| Error::FailedDestroyBdev {
-                    bdev: base_bdev.name(),
-                    bdev_type: base_bdev.driver(),
-                    name,
+                    bdev: base_bdev_name.clone(),
+                    bdev_type: "nexus".to_string(),
+                    name: name.clone(),
                     errno: -1,
-                })
-                .map(|_| Ok(()))?
-        } else {
-            let base_bdev_type = base_bdev.driver();
-            debug!(
-                "Destroying bdev {} type {}",
-                base_bdev.name(),
-                base_bdev_type
-            );
-
-            let (sender, receiver) = oneshot::channel::<i32>();
-            if base_bdev_type == "aio" {
-                unsafe {
-                    bdev_aio_delete(
-                        base_bdev.as_ptr(),
-                        Some(done_cb),
-                        cb_arg(sender),
-                    );
-                }
-            } else {
-                unsafe {
-                    delete_uring_bdev(
-                        base_bdev.as_ptr(),
-                        Some(done_cb),
-                        cb_arg(sender),
-                    );
-                }
-            }
-            let bdev_errno =
-                receiver.await.expect("Cancellation is not supported");
-            if bdev_errno != 0 {
-                Err(Error::FailedDestroyBdev {
-                    bdev: base_bdev_name,
-                    bdev_type: base_bdev_type,
-                    name,
-                    errno: bdev_errno,
-                })
-            } else {
-                info!(
-                    "The pool {} and base bdev {} type {} have been destroyed",
-                    name, base_bdev_name, base_bdev_type
-                );
-                Ok(())
+                })?;
+        } else if stripe_disks(&base_bdev_name).is_some() {
+            destroy_stripe_bdev(&base_bdev_name, &name).await?;
+            for disk in disks {
+                destroy_base_bdev(&disk.name, &name).await?;
             }
+        } else {
+            destroy_base_bdev(&base_bdev_name, &name).await?;
         }
+
+        info!("The pool {} has been destroyed", name);
+        events::publish(EventKind::Pool {
+            name,
+            change: Lifecycle::Destroyed,
+        });
+        Ok(())
     }
 }
 
@@ -514,46 +939,137 @@ impl Iterator for PoolsIter {
 
 impl From<Pool> for rpc::Pool {
     fn from(pool: Pool) -> Self {
+        let disks = pool.get_disks();
         rpc::Pool {
             name: pool.get_name().to_owned(),
-            disks: vec![
-                pool.get_base_bdev().driver()
-                    + "://"
-                    + &pool.get_base_bdev().name(),
-            ],
-            // TODO: figure out how to detect state of pool
-            state: rpc::PoolState::PoolOnline as i32,
+            disks: disks.iter().map(|d| d.uri.clone()).collect(),
+            state: pool.get_state() as i32,
             capacity: pool.get_capacity(),
             used: pool.get_capacity() - pool.get_free(),
+            layout: pool.get_layout() as i32,
+            disk_states: disks
+                .into_iter()
+                .map(|d| rpc::PoolDisk {
+                    uri: d.uri,
+                    state: d.state as i32,
+                })
+                .collect(),
         }
     }
 }
 
-async fn create_pool_legacy(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
-    // TODO: support RAID-0 devices
-    if args.disks.len() != 1 {
-        return Err(Error::BadNumDisks {
-            num: args.disks.len(),
-        });
+/// Return the pool if it has been created on the same disks, which makes
+/// the pool creation idempotent.
+fn existing_pool(pool: Pool, disks: &[String]) -> Result<rpc::Pool> {
+    let names = pool
+        .get_disks()
+        .into_iter()
+        .map(|d| d.name)
+        .collect::<Vec<_>>();
+    if names == disks {
+        Ok(pool.into())
+    } else {
+        Err(Error::AlreadyExists {
+            name: pool.get_name().to_owned(),
+        })
     }
+}
 
+/// Create the bdev which the lvol store of the pool is going to be created
+/// on (or imported from). A single disk is claimed directly whatever the
+/// layout.
+async fn create_pool_bdev(
+    name: &str,
+    disks: &[String],
+    layout: rpc::PoolLayout,
+) -> Result<String> {
+    if disks.len() == 1 {
+        return Ok(disks[0].clone());
+    }
+    match layout {
+        rpc::PoolLayout::PoolStripe => create_stripe_bdev(name, disks).await,
+        rpc::PoolLayout::PoolMirror => create_mirror_bdev(name, disks).await,
+        rpc::PoolLayout::PoolSingle => Err(Error::InvalidLayout {
+            layout: layout as i32,
+        }),
+    }
+}
+
+/// Destroy what has been created for a pool which could not be created in
+/// the end: the bdev striping or mirroring the disks, if any, and the bdevs
+/// of the disks which have been created for it. This is best effort, the
+/// error which got us here is the one to report.
+async fn destroy_pool_bdevs(name: &str, bdev: Option<&str>, disks: &[String]) {
+    if let Some(bdev) = bdev {
+        if let Some(nexus) = nexus_lookup(bdev) {
+            // the nexus destroys the disks being its children
+            if let Err(e) = nexus.destroy().await {
+                error!("Failed to destroy nexus {}: {}", bdev, e);
+            }
+            return;
+        }
+        if let Err(e) = destroy_stripe_bdev(bdev, name).await {
+            error!("{}", e);
+        }
+    }
+    for disk in disks {
+        if let Err(e) = destroy_base_bdev(disk, name).await {
+            error!("{}", e);
+        }
+    }
+}
+
+/// Create the pool on the bdev made of its disks, or import it from there,
+/// destroying the bdevs if neither succeeds. `created` are the bdevs of the
+/// disks which have been created for the pool.
+async fn create_pool_on(
+    name: &str,
+    disks: &[String],
+    created: &[String],
+    layout: rpc::PoolLayout,
+) -> Result<rpc::Pool> {
+    let bdev = match create_pool_bdev(name, disks, layout).await {
+        Ok(bdev) => bdev,
+        Err(e) => {
+            destroy_pool_bdevs(name, None, created).await;
+            return Err(e);
+        }
+    };
+
+    if let Ok(pool) = Pool::import(name, &bdev).await {
+        return Ok(pool.into());
+    }
+    match Pool::create(name, &bdev).await {
+        Ok(pool) => Ok(pool.into()),
+        Err(e) => {
+            // a single disk is the bdev of the pool itself
+            let stacked = if disks.len() > 1 {
+                Some(bdev.as_str())
+            } else {
+                None
+            };
+            destroy_pool_bdevs(name, stacked, created).await;
+            Err(e)
+        }
+    }
+}
+
+async fn create_pool_legacy(
+    args: rpc::CreatePoolRequest,
+    layout: rpc::PoolLayout,
+) -> Result<rpc::Pool> {
     if let Some(pool) = Pool::lookup(&args.name) {
-        return if pool.get_base_bdev().name() == args.disks[0] {
-            Ok(pool.into())
-        } else {
-            Err(Error::AlreadyExists {
-                name: args.name,
-            })
-        };
+        return existing_pool(pool, &args.disks);
     }
 
     // TODO: We would like to check if the disk is in use, but there
     // is no easy way how to get this info using available api.
-    let disk = &args.disks[0];
-    if Bdev::lookup_by_name(disk).is_some() {
-        return Err(Error::AlreadyBdev {
-            name: disk.clone(),
-        });
+    for disk in &args.disks {
+        if Bdev::lookup_by_name(disk).is_some() {
+            return Err(Error::AlreadyBdev {
+                name: disk.clone(),
+            });
+        }
     }
     // The block size may be missing or explicitly set to zero. In
     // both cases we want to provide our own default value instead
@@ -574,69 +1090,129 @@ async fn create_pool_legacy(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
             });
         }
     };
-    create_base_bdev(disk, block_size, io_if)?;
 
-    if let Ok(pool) = Pool::import(&args.name, disk).await {
-        return Ok(pool.into());
-    };
-    let pool = Pool::create(&args.name, disk).await?;
-    Ok(pool.into())
+    if layout == rpc::PoolLayout::PoolMirror && args.disks.len() > 1 {
+        // the nexus creates its children from URIs
+        let scheme = match io_if {
+            rpc::PoolIoIf::PoolIoUring => "uring",
+            _ => "aio",
+        };
+        let disks = args
+            .disks
+            .iter()
+            .map(|d| format!("{}://{}?blk_size={}", scheme, d, block_size))
+            .collect::<Vec<_>>();
+        return create_pool_on(&args.name, &disks, &[], layout).await;
+    }
+
+    let mut created = Vec::new();
+    for disk in &args.disks {
+        if let Err(e) = create_base_bdev(disk, block_size, io_if) {
+            destroy_pool_bdevs(&args.name, None, &created).await;
+            return Err(e);
+        }
+        created.push(disk.clone());
+    }
+    create_pool_on(&args.name, &args.disks, &created, layout).await
 }
 
 fn is_uri_scheme(disks: &[String]) -> bool {
     !disks.iter().any(|d| Url::parse(d).is_err())
 }
 
-async fn create_pool_uri(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
-    if args.disks.len() != 1 {
-        return Err(Error::BadNumDisks {
-            num: args.disks.len(),
-        });
+async fn create_pool_uri(
+    args: rpc::CreatePoolRequest,
+    layout: rpc::PoolLayout,
+) -> Result<rpc::Pool> {
+    let mut parsed = Vec::new();
+    for disk in &args.disks {
+        parsed.push(Uri::parse(disk).map_err(|e| Error::BadBdev {
+            bdev_if: e.to_string(),
+            name: disk.clone(),
+        })?);
     }
 
-    let parsed = Uri::parse(&args.disks[0]).map_err(|e| Error::BadBdev {
-        bdev_if: e.to_string(),
-        name: args.disks[0].clone(),
-    })?;
-
     if let Some(pool) = Pool::lookup(&args.name) {
-        return if pool.get_base_bdev().name() == parsed.get_name() {
-            Ok(pool.into())
-        } else {
-            Err(Error::AlreadyExists {
-                name: args.name,
-            })
-        };
+        let names = parsed.iter().map(|p| p.get_name()).collect::<Vec<_>>();
+        return existing_pool(pool, &names);
     }
 
-    let bdev = match parsed.create().await {
-        Err(e) => match e {
-            NexusBdevError::BdevExists {
-                ..
-            } => Ok(parsed.get_name()),
-            _ => Err(Error::BadBdev {
-                bdev_if: "".to_string(),
-                name: parsed.get_name(),
-            }),
-        },
-        Ok(name) => Ok(name),
-    }?;
-
-    if let Ok(pool) = Pool::import(&args.name, &bdev).await {
-        return Ok(pool.into());
+    if layout == rpc::PoolLayout::PoolMirror && args.disks.len() > 1 {
+        return create_pool_on(&args.name, &args.disks, &[], layout).await;
     }
 
-    let pool = Pool::create(&args.name, &bdev).await?;
-    Ok(pool.into())
+    // the bdevs which exist already are left alone if the pool fails
+    let mut bdevs = Vec::new();
+    let mut created = Vec::new();
+    for parsed in parsed {
+        match parsed.create().await {
+            Ok(name) => {
+                created.push(name.clone());
+                bdevs.push(name);
+            }
+            Err(NexusBdevError::BdevExists {
+                ..
+            }) => bdevs.push(parsed.get_name()),
+            Err(_) => {
+                destroy_pool_bdevs(&args.name, None, &created).await;
+                return Err(Error::BadBdev {
+                    bdev_if: "".to_string(),
+                    name: parsed.get_name(),
+                });
+            }
+        }
+    }
+    create_pool_on(&args.name, &bdevs, &created, layout).await
 }
 
 pub async fn create_pool(args: rpc::CreatePoolRequest) -> Result<rpc::Pool> {
+    if args.disks.is_empty() || args.disks.len() > MAX_POOL_DISKS {
+        return Err(Error::BadNumDisks {
+            num: args.disks.len(),
+        });
+    }
+    let layout = match rpc::PoolLayout::from_i32(args.layout) {
+        Some(val) => val,
+        None => {
+            return Err(Error::InvalidLayout {
+                layout: args.layout,
+            });
+        }
+    };
+    if layout == rpc::PoolLayout::PoolSingle && args.disks.len() > 1 {
+        return Err(Error::InvalidLayout {
+            layout: args.layout,
+        });
+    }
+
     if is_uri_scheme(&args.disks) {
         debug!("pool creation with URI scheme");
-        create_pool_uri(args).await
+        create_pool_uri(args, layout).await
     } else {
         debug!("pool creation with legacy scheme");
-        create_pool_legacy(args).await
+        create_pool_legacy(args, layout).await
+    }
+}
+
+pub(crate) async fn add_pool_mirror(
+    args: rpc::AddPoolMirrorRequest,
+) -> Result<rpc::Pool> {
+    let pool = match Pool::lookup(&args.name) {
+        Some(pool) => pool,
+        None => {
+            return Err(Error::UnknownPool {
+                name: args.name,
+            })
+        }
+    };
+    pool.add_mirror(&args.disk).await?;
+
+    // the pool could have gone while the disk was being added
+    match Pool::lookup(&args.name) {
+        Some(pool) => Ok(pool.into()),
+        None => Err(Error::PoolGone {
+            name: args.name,
+        }),
     }
 }
 
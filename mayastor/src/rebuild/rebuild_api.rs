@@ -1,15 +1,21 @@
 #![warn(missing_docs)]
 
-use std::fmt;
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
 
 use crossbeam::channel::{Receiver, Sender};
 use futures::channel::oneshot;
 use snafu::Snafu;
 
 use crate::{
-    bdev::VerboseError,
+    bdev::{DirtyLog, VerboseError},
     core::{BdevHandle, CoreError, Descriptor, DmaError},
     nexus_uri::NexusBdevError,
+    subsys::config::opts::RebuildOpts,
 };
 
 use super::rebuild_impl::*;
@@ -72,6 +78,22 @@ pub enum RebuildError {
     },
     #[snafu(display("Failed to get bdev name from URI {}", uri))]
     BdevInvalidURI { source: NexusBdevError, uri: String },
+    #[snafu(display(
+        "Invalid rebuild concurrency {}, must be between 1 and {}",
+        concurrency,
+        max,
+    ))]
+    InvalidConcurrency { concurrency: usize, max: usize },
+    #[snafu(display(
+        "Failed to rebuild the segments at blocks {:?} after {} retries",
+        blocks,
+        retries,
+    ))]
+    SegmentsFailed {
+        blocks: Vec<u64>,
+        retries: u32,
+        source: Box<RebuildError>,
+    },
 }
 
 #[derive(Debug, PartialEq, Copy, Clone)]
@@ -86,8 +108,8 @@ pub enum RebuildState {
     Stopped,
     /// Paused when the job is paused as requested through pause
     Paused,
-    /// Failed when an IO (R/W) operation was failed
-    /// there are no retries as it currently stands
+    /// Failed when an IO (R/W) operation kept on failing for a segment
+    /// until it ran out of retries
     Failed,
     /// Completed when the rebuild was successfully completed
     Completed,
@@ -106,6 +128,25 @@ impl fmt::Display for RebuildState {
     }
 }
 
+/// Limits on the resources used by a rebuild job, they can be changed while
+/// the job is running and take effect from the next segment copy on
+#[derive(Debug, PartialEq, Copy, Clone)]
+pub struct RebuildLimits {
+    /// maximum rate of the copy in MiB/s, 0 for no limit
+    pub bandwidth_mbps: u64,
+    /// number of segments copied concurrently
+    pub concurrency: usize,
+}
+
+impl Default for RebuildLimits {
+    fn default() -> Self {
+        Self {
+            bandwidth_mbps: 0,
+            concurrency: SEGMENT_TASKS,
+        }
+    }
+}
+
 /// A rebuild job is responsible for managing a rebuild (copy) which reads
 /// from source_hdl and writes into destination_hdl from specified start to end
 #[derive(Debug)]
@@ -117,14 +158,51 @@ pub struct RebuildJob {
     /// source URI of the healthy child to rebuild from
     pub source: String,
     pub(super) source_hdl: BdevHandle,
+    /// sources given up on after read errors, their handles are kept open
+    /// for the copies still in flight
+    pub(super) old_sources: Vec<(String, BdevHandle)>,
     /// target URI of the out of sync child in need of a rebuild
     pub destination: String,
     pub(super) destination_hdl: BdevHandle,
+    /// further out of sync children rebuilt from the same reads as the
+    /// destination, with their handles
+    pub(super) more_destinations: Vec<(String, BdevHandle)>,
+    /// destinations given up on after failing to be written to while the
+    /// others were not, along with the error. They are left out of the
+    /// copies from then on, their handles are kept open for the copies
+    /// still in flight.
+    pub(super) dropped_destinations: Vec<(String, RebuildError)>,
     pub(super) block_size: u64,
     pub(super) range: std::ops::Range<u64>,
     pub(super) next: u64,
     pub(super) segment_size_blks: u64,
+    /// when set, only the dirty regions of the range are copied
+    pub(super) dirty_log: Option<Arc<DirtyLog>>,
     pub(super) task_pool: RebuildTasks,
+    /// limits on the bandwidth and concurrency of the copy
+    pub(super) limits: RebuildLimits,
+    /// spreads the segment copies out to keep within the bandwidth limit
+    pub(super) pacer: Pacer,
+    /// when the nexus was last told to record a checkpoint
+    pub(super) last_checkpoint: Instant,
+    /// retry policy of the segment copies
+    pub(super) opts: RebuildOpts,
+    /// number of retries of the segments which have failed to copy so far
+    pub(super) retries: HashMap<u64, u32>,
+    /// segments waiting to be retried, in order
+    pub(super) retry_queue: VecDeque<u64>,
+    /// number of segment copies retried so far
+    pub(super) segments_retried: u64,
+    /// segments which could not be copied within the retries
+    pub failed_segments: Vec<u64>,
+    /// comparison of the rebuilt segments against the source
+    pub(super) verification: Verification,
+    /// number of blocks written to the destinations so far
+    pub(super) blocks_transferred: u64,
+    /// wall clock and monotonic time of the start of the job
+    pub(super) start_time: Option<(SystemTime, Instant)>,
+    /// monotonic time of the end of the job
+    pub(super) end_time: Option<Instant>,
     pub(super) notify_fn: fn(String, String) -> (),
     /// channel used to signal rebuild update
     pub notify_chan: (Sender<RebuildState>, Receiver<RebuildState>),
@@ -137,6 +215,7 @@ pub struct RebuildJob {
 }
 
 /// rebuild statistics
+#[derive(Debug, Clone)]
 pub struct RebuildStats {
     /// total number of blocks to recover
     pub blocks_total: u64,
@@ -148,6 +227,49 @@ pub struct RebuildStats {
     pub segment_size_blks: u64,
     /// size in bytes of each block
     pub block_size: u64,
+    /// the copy is done and the destinations are being compared against
+    /// the source
+    pub verifying: bool,
+    /// number of segments compared against the source
+    pub segments_verified: u64,
+    /// number of segments found to differ from the source
+    pub mismatches_found: u64,
+    /// number of differing segments which were copied again
+    pub mismatches_fixed: u64,
+    /// number of segment copies retried after failing
+    pub segments_retried: u64,
+    /// number of blocks written to the destinations, including the segments
+    /// copied again after a failure or a mismatch
+    pub blocks_transferred: u64,
+    /// number of copy tasks of the job
+    pub tasks_total: usize,
+    /// number of copy tasks in progress
+    pub tasks_active: usize,
+    /// when the job was started, if it has been
+    pub start_time: Option<SystemTime>,
+    /// time since the job was started, up to its end
+    pub elapsed: Duration,
+    /// average transfer rate in bytes per second
+    pub throughput: u64,
+    /// estimated time until all the blocks are recovered, extrapolated from
+    /// the progress so far
+    pub remaining: Option<Duration>,
+}
+
+/// Comparison of the destinations against the source, segment by segment,
+/// after all of them have been copied
+#[derive(Debug, Default)]
+pub(super) struct Verification {
+    /// whether to verify at all
+    pub(super) enabled: bool,
+    /// the copy is done and the segments are being verified
+    pub(super) active: bool,
+    /// number of segments verified so far
+    pub(super) segments: u64,
+    /// segments which differed from the source
+    pub(super) mismatched: HashSet<u64>,
+    /// number of differing segments copied again
+    pub(super) fixed: u64,
 }
 
 /// Public facing operations on a Rebuild Job
@@ -167,6 +289,10 @@ pub trait ClientOperations {
     /// this could be used to mitigate excess load on the source bdev, eg
     /// too much contention with frontend IO
     fn resume(&mut self) -> Result<(), RebuildError>;
+    /// Changes the bandwidth and concurrency limits of the job, also while
+    /// it is running
+    fn set_limits(&mut self, limits: RebuildLimits)
+        -> Result<(), RebuildError>;
 
     /// Forcefully terminates the job, overriding any pending client operation
     /// returns an async channel which can be used to await for termination
@@ -175,24 +301,45 @@ pub trait ClientOperations {
 
 impl RebuildJob {
     /// Creates a new RebuildJob which rebuilds from source URI to target URI
-    /// from start to end (of the data partition); notify_fn callback is called
-    /// when the rebuild state is updated - with the nexus and destination
-    /// URI as arguments
+    /// from start to end (of the data partition); if a dirty log is given only
+    /// the regions marked as dirty are rebuilt; the copy is kept within the
+    /// given limits; with verify, or if the rebuild options ask for it, the
+    /// destinations are compared against the source once copied and the
+    /// segments which differ are copied again; notify_fn callback is called
+    /// when the rebuild state is updated and every so often while running -
+    /// with the nexus and destination URI as arguments
+    #[allow(clippy::too_many_arguments)]
     pub fn create<'a>(
         nexus: &str,
         source: &str,
         destination: &'a str,
         range: std::ops::Range<u64>,
+        dirty_log: Option<Arc<DirtyLog>>,
+        limits: RebuildLimits,
+        verify: bool,
         notify_fn: fn(String, String) -> (),
     ) -> Result<&'a mut Self, RebuildError> {
-        Self::new(nexus, source, destination, range, notify_fn)?.store()?;
+        Self::new(
+            nexus,
+            source,
+            destination,
+            range,
+            dirty_log,
+            limits,
+            verify,
+            notify_fn,
+        )?
+        .store()?;
 
         Ok(Self::lookup(destination)?)
     }
 
-    /// Lookup a rebuild job by its destination uri and return it
+    /// Lookup a rebuild job by any of its destination uris and return it
     pub fn lookup(name: &str) -> Result<&mut Self, RebuildError> {
-        if let Some(job) = Self::get_instances().get_mut(name) {
+        if let Some(job) = Self::get_instances().values_mut().find(|j| {
+            j.destination == name
+                || j.more_destinations.iter().any(|(d, _)| d == name)
+        }) {
             Ok(job)
         } else {
             Err(RebuildError::JobNotFound {
@@ -201,6 +348,21 @@ impl RebuildJob {
         }
     }
 
+    /// All the children rebuilt by the job, the destination first, apart
+    /// from the ones it has given up on
+    pub fn destinations(&self) -> Vec<String> {
+        std::iter::once(self.destination.clone())
+            .chain(self.more_destinations.iter().map(|(d, _)| d.clone()))
+            .filter(|d| !self.dropped_destinations.iter().any(|(n, _)| n == d))
+            .collect()
+    }
+
+    /// The children the job has given up on after they failed to be written
+    /// to, with the error, while it carries on rebuilding the others
+    pub fn dropped_destinations(&self) -> &[(String, RebuildError)] {
+        &self.dropped_destinations
+    }
+
     /// Lookup all rebuilds jobs with name as its source
     pub fn lookup_src(name: &str) -> Vec<&mut Self> {
         Self::get_instances()
@@ -230,6 +392,28 @@ impl RebuildJob {
         self.states.current
     }
 
+    /// Current limits of the rebuild job
+    pub fn limits(&self) -> RebuildLimits {
+        self.limits
+    }
+
+    /// Whether the destinations are compared against the source once all
+    /// the segments are copied
+    pub fn verify(&self) -> bool {
+        self.verification.enabled
+    }
+
+    /// Limit the rate of all rebuild copies together to `bandwidth_mbps`
+    /// MiB/s, 0 removes the limit
+    pub fn set_global_bandwidth(bandwidth_mbps: u64) {
+        global_limit().bandwidth_mbps = bandwidth_mbps;
+    }
+
+    /// Current rate limit of all rebuild copies together in MiB/s
+    pub fn global_bandwidth() -> u64 {
+        global_limit().bandwidth_mbps
+    }
+
     /// Error description
     pub fn error_desc(&self) -> String {
         match self.error.as_ref() {
@@ -1,21 +1,38 @@
 #![warn(missing_docs)]
 
-use std::{cell::UnsafeCell, collections::HashMap};
+use std::{
+    cell::UnsafeCell,
+    collections::{HashMap, VecDeque},
+    os::raw::c_void,
+    ptr,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
 
+use crc::crc32;
 use crossbeam::channel::unbounded;
 use futures::{
     channel::{mpsc, oneshot},
+    future::join_all,
     StreamExt,
 };
-use once_cell::sync::OnceCell;
+use once_cell::sync::{Lazy, OnceCell};
 use snafu::ResultExt;
 
-use spdk_sys::{spdk_get_thread, SPDK_BDEV_LARGE_BUF_MAX_SIZE};
+use spdk_sys::{
+    spdk_get_thread,
+    spdk_poller,
+    spdk_poller_register,
+    spdk_poller_unregister,
+    SPDK_BDEV_LARGE_BUF_MAX_SIZE,
+};
 
 use crate::{
-    bdev::VerboseError,
+    bdev::{nexus_lookup, ChildStatus, DirtyLog, VerboseError},
     core::{Bdev, BdevHandle, DmaBuf, RangeContext, Reactors},
+    events::{self, EventKind},
     nexus_uri::bdev_get_name,
+    subsys::Config,
 };
 
 use super::rebuild_api::*;
@@ -41,18 +58,27 @@ struct TaskResult {
     error: Option<RebuildError>,
 }
 
-/// Number of concurrent copy tasks per rebuild job
-const SEGMENT_TASKS: usize = 16;
+/// Default number of concurrent copy tasks per rebuild job
+pub(super) const SEGMENT_TASKS: usize = 16;
+/// Maximum number of concurrent copy tasks per rebuild job
+pub(super) const MAX_SEGMENT_TASKS: usize = 64;
 /// Size of each segment used by the copy task
 pub const SEGMENT_SIZE: u64 = SPDK_BDEV_LARGE_BUF_MAX_SIZE as u64;
+/// How often a running job lets the nexus record its checkpoint
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(10);
 
 /// Each rebuild task needs a unique buffer to read/write from source to target
 /// A mpsc channel is used to communicate with the management task
 #[derive(Debug)]
 struct RebuildTask {
     buffer: DmaBuf,
+    /// buffer the destinations are read into to compare them against the
+    /// source in the other buffer, when verifying
+    verify_buffer: Option<DmaBuf>,
     sender: mpsc::Sender<TaskResult>,
     error: Option<TaskResult>,
+    /// block being copied by the task, if any
+    blk: Option<u64>,
 }
 
 /// Pool of rebuild tasks and progress tracking
@@ -64,9 +90,98 @@ pub(super) struct RebuildTasks {
 
     channel: (mpsc::Sender<TaskResult>, mpsc::Receiver<TaskResult>),
     active: usize,
-    total: usize,
+    /// ids of the tasks which are not copying anything
+    idle: Vec<usize>,
+
+    /// blocks copied to the destination so far
+    blocks_recovered: u64,
+}
 
-    segments_done: u64,
+/// Spreads the segment copies out over time so that they stay within a
+/// bandwidth limit. Every copy reserves the time it takes at the limited rate
+/// and has to wait until the copies reserved before it have had theirs.
+#[derive(Debug, Default)]
+pub(super) struct Pacer {
+    /// time from which the next copy may start
+    next: Option<Instant>,
+}
+
+impl Pacer {
+    /// Reserve the time to copy `bytes` at `bandwidth_mbps` MiB/s and return
+    /// how long the copy has to wait for its turn
+    fn reserve(
+        &mut self,
+        bytes: u64,
+        bandwidth_mbps: u64,
+        now: Instant,
+    ) -> Duration {
+        if bandwidth_mbps == 0 {
+            self.next = None;
+            return Duration::default();
+        }
+        let start = match self.next {
+            Some(next) if next > now => next,
+            _ => now,
+        };
+        self.next = Some(
+            start
+                + Duration::from_nanos(
+                    bytes * 1_000_000_000 / (bandwidth_mbps * 1024 * 1024),
+                ),
+        );
+        start - now
+    }
+}
+
+/// Bandwidth limit shared by all the rebuild jobs
+pub(super) struct GlobalLimit {
+    /// maximum rate of all the copies together in MiB/s, 0 for no limit
+    pub(super) bandwidth_mbps: u64,
+    pacer: Pacer,
+}
+
+static GLOBAL_LIMIT: Lazy<Mutex<GlobalLimit>> = Lazy::new(|| {
+    Mutex::new(GlobalLimit {
+        bandwidth_mbps: 0,
+        pacer: Pacer::default(),
+    })
+});
+
+/// Get the bandwidth limit shared by all the rebuild jobs
+pub(super) fn global_limit() -> MutexGuard<'static, GlobalLimit> {
+    GLOBAL_LIMIT.lock().unwrap()
+}
+
+/// Poller which wakes up a sleeping copy task once and goes away
+struct Sleeper {
+    poller: *mut spdk_poller,
+    sender: Option<oneshot::Sender<()>>,
+}
+
+extern "C" fn sleeper_wake_up(ctx: *mut c_void) -> i32 {
+    let mut sleeper = unsafe { Box::from_raw(ctx as *mut Sleeper) };
+    unsafe { spdk_poller_unregister(&mut sleeper.poller) };
+    if let Some(sender) = sleeper.sender.take() {
+        let _ = sender.send(());
+    }
+    0
+}
+
+/// Wait for the given time without blocking the reactor
+async fn sleep(duration: Duration) {
+    let (sender, receiver) = oneshot::channel();
+    let sleeper = Box::into_raw(Box::new(Sleeper {
+        poller: ptr::null_mut(),
+        sender: Some(sender),
+    }));
+    unsafe {
+        (*sleeper).poller = spdk_poller_register(
+            Some(sleeper_wake_up),
+            sleeper as *mut c_void,
+            duration.as_micros() as u64,
+        );
+    }
+    let _ = receiver.await;
 }
 
 /// Checks whether a range is contained within another range
@@ -90,7 +205,7 @@ impl RebuildJob {
     pub(super) fn store(self: Self) -> Result<(), RebuildError> {
         let rebuild_list = Self::get_instances();
 
-        if rebuild_list.contains_key(&self.destination) {
+        if Self::lookup(&self.destination).is_ok() {
             Err(RebuildError::JobAlreadyExists {
                 job: self.destination,
             })
@@ -107,8 +222,15 @@ impl RebuildJob {
         source: &str,
         destination: &str,
         range: std::ops::Range<u64>,
+        dirty_log: Option<Arc<DirtyLog>>,
+        limits: RebuildLimits,
+        verify: bool,
         notify_fn: fn(String, String) -> (),
     ) -> Result<Self, RebuildError> {
+        Self::validate_limits(&limits)?;
+        let opts = Config::get().rebuild_opts.clone();
+        let verify = verify || opts.verify;
+
         let source_hdl = BdevHandle::open(
             &bdev_get_name(source).context(BdevInvalidURI {
                 uri: source.to_string(),
@@ -148,20 +270,15 @@ impl RebuildJob {
             // the extra buffer
             channel: mpsc::channel(0),
             active: 0,
-            total: SEGMENT_TASKS,
-            segments_done: 0,
+            idle: Vec::new(),
+            blocks_recovered: 0,
         };
-
-        for _ in 0 .. tasks.total {
-            let copy_buffer = destination_hdl
-                .dma_malloc((segment_size_blks * block_size) as usize)
-                .context(NoCopyBuffer {})?;
-            tasks.tasks.push(RebuildTask {
-                buffer: copy_buffer,
-                sender: tasks.channel.0.clone(),
-                error: None,
-            });
-        }
+        tasks.grow(
+            limits.concurrency,
+            &destination_hdl,
+            segment_size_blks * block_size,
+            verify,
+        )?;
 
         let (source, destination, nexus) = (
             source.to_string(),
@@ -179,13 +296,32 @@ impl RebuildJob {
             nexus_descriptor,
             source,
             source_hdl,
+            old_sources: Vec::new(),
             destination,
             destination_hdl,
+            more_destinations: Vec::new(),
+            dropped_destinations: Vec::new(),
             next: range.start,
             range,
             block_size,
             segment_size_blks,
+            dirty_log,
             task_pool: tasks,
+            limits,
+            pacer: Pacer::default(),
+            last_checkpoint: Instant::now(),
+            retries: HashMap::new(),
+            retry_queue: VecDeque::new(),
+            segments_retried: 0,
+            failed_segments: Vec::new(),
+            verification: Verification {
+                enabled: verify,
+                ..Default::default()
+            },
+            opts,
+            blocks_transferred: 0,
+            start_time: None,
+            end_time: None,
             notify_fn,
             notify_chan: unbounded::<RebuildState>(),
             states: Default::default(),
@@ -198,14 +334,34 @@ impl RebuildJob {
     // awaits each completion. When any task completes it kicks off another
     // until the bdev is fully rebuilt
     async fn run(&mut self) {
-        self.start_all_tasks();
+        if self.start_time.is_none() {
+            self.start_time = Some((SystemTime::now(), Instant::now()));
+        }
+        if self.error.is_some() {
+            // a segment ran out of retries while the job was being paused
+            self.fail();
+        } else {
+            self.start_all_tasks();
+            if self.task_pool.active == 0 {
+                // nothing (left) to rebuild, eg: the dirty log is clean
+                self.all_segments_done();
+            }
+        }
         while self.task_pool.active > 0 {
             match self.await_one_task().await {
                 Some(r) => match r.error {
-                    None => {
+                    Some(e) if self.error.is_some() => {
+                        error!("Failed to rebuild segment id {} block {} with error: {}", r.id, r.blk, e);
+                        self.fail();
+                        self.await_all_tasks().await;
+                        break;
+                    }
+                    // either copied or queued for a retry
+                    _ => {
                         match self.states.pending {
                             None | Some(RebuildState::Running) => {
                                 self.start_task_by_id(r.id);
+                                self.checkpoint_if_due();
                             }
                             _ => {
                                 // await all active tasks as we might still have
@@ -215,13 +371,6 @@ impl RebuildJob {
                             }
                         }
                     }
-                    Some(e) => {
-                        error!("Failed to rebuild segment id {} block {} with error: {}", r.id, r.blk, e);
-                        self.fail();
-                        self.await_all_tasks().await;
-                        self.error = Some(e);
-                        break;
-                    }
                 },
                 None => {
                     // all senders have disconnected, out of place termination?
@@ -243,9 +392,10 @@ impl RebuildJob {
         self.segment_size_blks
     }
 
-    /// Copies one segment worth of data from source into destination. During
-    /// this time the LBA range being copied is locked so that there cannot be
-    /// front end I/O to the same LBA range.
+    /// Copies one segment worth of data from source into destination, or
+    /// verifies it once the copy is done. During this time the LBA range
+    /// being copied is locked so that there cannot be front end I/O to the
+    /// same LBA range.
     ///
     /// # Safety
     ///
@@ -284,7 +434,11 @@ impl RebuildJob {
             })?;
 
         // Perform the copy
-        let result = self.copy_one(id, blk).await;
+        let result = if self.verification.active {
+            self.verify_one(id, blk).await
+        } else {
+            self.copy_one(id, blk).await
+        };
 
         // Wait for the LBA range to be unlocked.
         // This allows others I/Os to be issued to this LBA range once again.
@@ -334,12 +488,109 @@ impl RebuildJob {
                 bdev: &self.source,
             })?;
 
-        self.destination_hdl
-            .write_at(blk * self.block_size, copy_buffer)
+        // the segment is read once and written to all the destinations
+        let offset = blk * self.block_size;
+        let copy_buffer: &DmaBuf = copy_buffer;
+        let dropped = &self.dropped_destinations;
+        let results = join_all(
+            std::iter::once((&self.destination, &self.destination_hdl))
+                .chain(self.more_destinations.iter().map(|(d, h)| (d, h)))
+                .filter(|(d, _)| !dropped.iter().any(|(n, _)| n == *d))
+                .map(|(bdev, hdl)| async move {
+                    hdl.write_at(offset, copy_buffer).await.context(
+                        WriteIoError {
+                            bdev,
+                        },
+                    )
+                }),
+        )
+        .await;
+
+        // a destination which fails the write is given up on as long as
+        // another one has taken it, otherwise the segment is retried
+        let written = results.iter().filter(|r| r.is_ok()).count();
+        let mut errors = results.into_iter().filter_map(Result::err);
+        if written == 0 {
+            if let Some(error) = errors.next() {
+                return Err(error);
+            }
+        }
+        errors.for_each(|error| self.drop_destination(error));
+
+        self.blocks_transferred += self.get_segment_size_blks(blk);
+        Ok(())
+    }
+
+    /// Compares one segment worth of data of the destinations against the
+    /// source by their checksums and copies it again to the destinations
+    /// which differ. The source is read into the copy buffer of the task and
+    /// the destinations into its verify buffer.
+    async fn verify_one(
+        &mut self,
+        id: usize,
+        blk: u64,
+    ) -> Result<(), RebuildError> {
+        let offset = blk * self.block_size;
+        let len = self.get_segment_size_blks(blk);
+        let mut last_buffers: (DmaBuf, DmaBuf);
+
+        let (source_buffer, buffer) = if len == self.segment_size_blks {
+            let task = &mut self.task_pool.tasks[id];
+            if task.verify_buffer.is_none() {
+                task.verify_buffer = Some(
+                    self.destination_hdl
+                        .dma_malloc(task.buffer.len())
+                        .context(NoCopyBuffer {})?,
+                );
+            }
+            (&mut task.buffer, task.verify_buffer.as_mut().unwrap())
+        } else {
+            // the last segment is shorter than the buffers of the tasks
+            let size = (len * self.block_size) as usize;
+            last_buffers = (
+                self.destination_hdl
+                    .dma_malloc(size)
+                    .context(NoCopyBuffer {})?,
+                self.destination_hdl
+                    .dma_malloc(size)
+                    .context(NoCopyBuffer {})?,
+            );
+            (&mut last_buffers.0, &mut last_buffers.1)
+        };
+
+        self.source_hdl
+            .read_at(offset, source_buffer)
             .await
-            .context(WriteIoError {
-                bdev: &self.destination,
+            .context(ReadIoError {
+                bdev: &self.source,
+            })?;
+        let checksum = crc32::checksum_ieee(source_buffer.as_slice());
+
+        let dropped = &self.dropped_destinations;
+        for (bdev, hdl) in
+            std::iter::once((&self.destination, &self.destination_hdl))
+                .chain(self.more_destinations.iter().map(|(d, h)| (d, h)))
+                .filter(|(d, _)| !dropped.iter().any(|(n, _)| n == *d))
+        {
+            hdl.read_at(offset, buffer).await.context(ReadIoError {
+                bdev,
             })?;
+            if crc32::checksum_ieee(buffer.as_slice()) == checksum {
+                continue;
+            }
+
+            warn!(
+                "Rebuild job {}: segment at block {} of {} differs from the source {}",
+                self.destination, blk, bdev, self.source
+            );
+            self.verification.mismatched.insert(blk);
+            hdl.write_at(offset, source_buffer).await.context(
+                WriteIoError {
+                    bdev,
+                },
+            )?;
+            self.blocks_transferred += len;
+        }
 
         Ok(())
     }
@@ -382,6 +633,16 @@ impl RebuildJob {
                 "Rebuild job {}: changing state from {:?} to {:?}",
                 self.destination, old, new
             );
+            if new.done() {
+                self.end_time = Some(Instant::now());
+            }
+            for child in self.destinations() {
+                events::publish(EventKind::RebuildState {
+                    nexus: self.nexus.clone(),
+                    child,
+                    state: new,
+                });
+            }
             self.notify();
         }
     }
@@ -467,19 +728,47 @@ impl std::fmt::Display for RebuildOperation {
 
 impl ClientOperations for RebuildJob {
     fn stats(&self) -> RebuildStats {
-        let blocks_total = self.range.end - self.range.start;
+        let blocks_recovered = self.task_pool.blocks_recovered;
+        // the clean regions are skipped, so what is left to copy is what the
+        // dirty log holds from the lowest segment still outstanding onwards
+        let blocks_total = match &self.dirty_log {
+            _ if self.verification.active => blocks_recovered,
+            Some(log) => {
+                blocks_recovered + log.dirty_blocks_from(self.checkpoint())
+            }
+            None => self.range.end - self.range.start,
+        };
 
-        // segment size may not be aligned to the total size
-        let blocks_recovered = std::cmp::min(
-            self.task_pool.segments_done * self.segment_size_blks,
-            blocks_total,
-        );
+        let progress = if blocks_total == 0 {
+            100
+        } else {
+            (blocks_recovered * 100) / blocks_total
+        };
 
-        let progress = (blocks_recovered * 100) / blocks_total;
+        let elapsed = match self.start_time {
+            Some((_, start)) => {
+                self.end_time.unwrap_or_else(Instant::now) - start
+            }
+            None => Duration::default(),
+        };
+        let throughput = match elapsed.as_millis() as u64 {
+            0 => 0,
+            ms => self.blocks_transferred * self.block_size * 1000 / ms,
+        };
+        let remaining = match blocks_recovered {
+            _ if blocks_recovered == blocks_total => Some(Duration::default()),
+            0 => None,
+            recovered => {
+                Some(elapsed.mul_f64(
+                    (blocks_total - recovered) as f64 / recovered as f64,
+                ))
+            }
+        };
 
         info!(
             "State: {}, Src: {}, Dst: {}, range: {:?}, next: {}, \
-             block_size: {}, segment_sz: {}, recovered_blks: {}, progress: {}%",
+             block_size: {}, segment_sz: {}, recovered_blks: {}, progress: {}%, \
+             verifying: {}, verified_segments: {}, mismatches: {}, fixed: {}",
             self.state(),
             self.source,
             self.destination,
@@ -489,6 +778,10 @@ impl ClientOperations for RebuildJob {
             self.segment_size_blks,
             blocks_recovered,
             progress,
+            self.verification.active,
+            self.verification.segments,
+            self.verification.mismatched.len(),
+            self.verification.fixed,
         );
 
         RebuildStats {
@@ -497,6 +790,18 @@ impl ClientOperations for RebuildJob {
             progress,
             segment_size_blks: self.segment_size_blks,
             block_size: self.block_size,
+            verifying: self.verification.active,
+            segments_verified: self.verification.segments,
+            mismatches_found: self.verification.mismatched.len() as u64,
+            mismatches_fixed: self.verification.fixed,
+            segments_retried: self.segments_retried,
+            blocks_transferred: self.blocks_transferred,
+            tasks_total: self.task_pool.tasks.len(),
+            tasks_active: self.task_pool.active,
+            start_time: self.start_time.map(|(time, _)| time),
+            elapsed,
+            throughput,
+            remaining,
         }
     }
 
@@ -521,6 +826,26 @@ impl ClientOperations for RebuildJob {
         self.exec_client_op(RebuildOperation::Resume)
     }
 
+    fn set_limits(
+        &mut self,
+        limits: RebuildLimits,
+    ) -> Result<(), RebuildError> {
+        Self::validate_limits(&limits)?;
+        // more tasks are started as soon as one of the running ones is done
+        self.task_pool.grow(
+            limits.concurrency,
+            &self.destination_hdl,
+            self.segment_size_blks * self.block_size,
+            self.verification.enabled,
+        )?;
+        info!(
+            "Rebuild job {}: changing limits from {:?} to {:?}",
+            self.destination, self.limits, limits
+        );
+        self.limits = limits;
+        Ok(())
+    }
+
     fn terminate(&mut self) -> oneshot::Receiver<RebuildState> {
         self.exec_internal_op(RebuildOperation::Stop).ok();
         let end_channel = oneshot::channel();
@@ -547,47 +872,143 @@ impl InternalOperations for RebuildJob {
     }
 }
 
+impl RebuildTasks {
+    /// Add tasks, each with its own copy buffer and another one to verify
+    /// with if asked to, until there are `total`
+    fn grow(
+        &mut self,
+        total: usize,
+        hdl: &BdevHandle,
+        buffer_size: u64,
+        verify: bool,
+    ) -> Result<(), RebuildError> {
+        while self.tasks.len() < total {
+            let copy_buffer = hdl
+                .dma_malloc(buffer_size as usize)
+                .context(NoCopyBuffer {})?;
+            let verify_buffer = if verify {
+                Some(
+                    hdl.dma_malloc(buffer_size as usize)
+                        .context(NoCopyBuffer {})?,
+                )
+            } else {
+                None
+            };
+            // the idle tasks are started from the end
+            self.idle.insert(0, self.tasks.len());
+            self.tasks.push(RebuildTask {
+                buffer: copy_buffer,
+                verify_buffer,
+                sender: self.channel.0.clone(),
+                error: None,
+                blk: None,
+            });
+        }
+        Ok(())
+    }
+}
+
 impl RebuildJob {
+    /// Check the limits are within the supported range
+    fn validate_limits(limits: &RebuildLimits) -> Result<(), RebuildError> {
+        if limits.concurrency == 0 || limits.concurrency > MAX_SEGMENT_TASKS {
+            return Err(RebuildError::InvalidConcurrency {
+                concurrency: limits.concurrency,
+                max: MAX_SEGMENT_TASKS,
+            });
+        }
+        Ok(())
+    }
+
     fn start_all_tasks(&mut self) {
         assert_eq!(
             self.task_pool.active, 0,
             "{} active tasks",
             self.task_pool.active
         );
+        self.start_idle_tasks();
+    }
 
-        for n in 0 .. self.task_pool.total {
-            self.next = match self.send_segment_task(n) {
+    /// Start idle tasks until as many are active as the concurrency limit
+    /// allows or there is nothing left to rebuild
+    fn start_idle_tasks(&mut self) {
+        while self.task_pool.active < self.limits.concurrency {
+            let id = match self.task_pool.idle.pop() {
+                Some(id) => id,
+                None => break,
+            };
+            match self.send_segment_task(id) {
                 Some(next) => {
                     self.task_pool.active += 1;
-                    next
+                    self.next = next;
                 }
-                None => break, /* we've already got enough tasks to rebuild
-                                * the bdev */
-            };
+                None => {
+                    // we've already got enough tasks to rebuild the bdev
+                    self.task_pool.idle.push(id);
+                    break;
+                }
+            }
         }
     }
 
+    /// Start more tasks now that the task `id` is done
     fn start_task_by_id(&mut self, id: usize) {
-        match self.send_segment_task(id) {
-            Some(next) => {
-                self.task_pool.active += 1;
-                self.next = next;
-            }
-            None => {
-                if self.task_pool.active == 0 {
-                    self.complete();
-                }
+        debug_assert!(self.task_pool.idle.contains(&id));
+        self.start_idle_tasks();
+        if self.task_pool.active == 0 {
+            self.all_segments_done();
+        }
+    }
+
+    /// All the segments are copied, so verify them now if asked to, or
+    /// they are verified as well and the job is complete
+    fn all_segments_done(&mut self) {
+        if self.verification.enabled && !self.verification.active {
+            info!(
+                "Rebuild job {}: verifying the rebuilt segments against {}",
+                self.destination, self.source
+            );
+            self.verification.active = true;
+            self.next = self.range.start;
+            self.start_idle_tasks();
+            if self.task_pool.active > 0 {
+                return;
             }
-        };
+        }
+        if self.verification.active {
+            info!(
+                "Rebuild job {}: verified {} segments, {} differed from the source and {} were copied again",
+                self.destination,
+                self.verification.segments,
+                self.verification.mismatched.len(),
+                self.verification.fixed
+            );
+        }
+        self.complete();
     }
 
     async fn await_one_task(&mut self) -> Option<TaskResult> {
         self.task_pool.channel.1.next().await.map(|f| {
             self.task_pool.active -= 1;
-            if f.error.is_none() {
-                self.task_pool.segments_done += 1;
-            } else {
-                self.task_pool.tasks[f.id].error = Some(f.clone());
+            self.task_pool.idle.push(f.id);
+            self.task_pool.tasks[f.id].blk = None;
+            match &f.error {
+                None if self.verification.active => {
+                    self.verification.segments += 1;
+                    if self.verification.mismatched.contains(&f.blk) {
+                        self.verification.fixed += 1;
+                    }
+                    self.retries.remove(&f.blk);
+                }
+                None => {
+                    self.task_pool.blocks_recovered +=
+                        self.get_segment_size_blks(f.blk);
+                    self.retries.remove(&f.blk);
+                }
+                Some(e) => {
+                    self.task_pool.tasks[f.id].error = Some(f.clone());
+                    self.retry_segment(f.blk, e);
+                }
             }
             f
         })
@@ -602,34 +1023,252 @@ impl RebuildJob {
     }
 
     /// Sends one segment worth of data in a reactor future and notifies the
-    /// management channel. Returns the next segment offset to rebuild, if any
-    fn send_segment_task(&self, id: usize) -> Option<u64> {
-        if self.next >= self.range.end {
-            None
-        } else {
-            let blk = self.next;
-            let next = std::cmp::min(
-                self.next + self.segment_size_blks,
-                self.range.end,
-            );
-            let name = self.destination.clone();
+    /// management channel. The copy waits for its turn if the job or all the
+    /// jobs together are limited in bandwidth. Returns the next segment
+    /// offset to rebuild, if any
+    fn send_segment_task(&mut self, id: usize) -> Option<u64> {
+        // the segments to retry go first, after backing off for a while
+        let (blk, next, backoff) = match self.retry_queue.pop_front() {
+            Some(blk) => (blk, self.next, self.retry_backoff(blk)),
+            None => {
+                let blk = self.next_segment()?;
+                let next =
+                    std::cmp::min(blk + self.segment_size_blks, self.range.end);
+                (blk, next, Duration::default())
+            }
+        };
+        let name = self.destination.clone();
+        self.task_pool.tasks[id].blk = Some(blk);
+
+        let bytes = self.get_segment_size_blks(blk) * self.block_size;
+        let now = Instant::now();
+        let delay = {
+            let mut global = global_limit();
+            let bandwidth_mbps = global.bandwidth_mbps;
+            global.pacer.reserve(bytes, bandwidth_mbps, now)
+        }
+        .max(self.pacer.reserve(bytes, self.limits.bandwidth_mbps, now))
+        .max(backoff);
+
+        Reactors::current().send_future(async move {
+            if delay > Duration::default() {
+                sleep(delay).await;
+            }
+            let job = Self::lookup(&name).unwrap();
+
+            let r = TaskResult {
+                blk,
+                id,
+                error: job.locked_copy_one(id, blk).await.err(),
+            };
 
-            Reactors::current().send_future(async move {
-                let job = Self::lookup(&name).unwrap();
+            let task = &mut job.task_pool.tasks[id];
+            if let Err(e) = task.sender.start_send(r) {
+                error!("Failed to notify job of segment id: {} blk: {} completion, err: {}", id, blk, e.verbose());
+            }
+        });
 
-                let r = TaskResult {
-                    blk,
-                    id,
-                    error: job.locked_copy_one(id, blk).await.err(),
-                };
+        Some(next)
+    }
 
-                let task = &mut job.task_pool.tasks[id];
-                if let Err(e) = task.sender.start_send(r) {
-                    error!("Failed to notify job of segment id: {} blk: {} completion, err: {}", id, blk, e.verbose());
-                }
+    /// Returns the block, relative to the start of the range, before which
+    /// everything has been rebuilt, so that a job resumed from there misses
+    /// nothing
+    pub fn checkpoint(&self) -> u64 {
+        self.task_pool
+            .tasks
+            .iter()
+            .filter_map(|t| t.blk)
+            .chain(self.retry_queue.iter().cloned())
+            .fold(self.next, std::cmp::min)
+            - self.range.start
+    }
+
+    /// Skip the first `blk` blocks of the range, which a previous job has
+    /// already rebuilt. Only possible before the job is started.
+    pub fn resume_from(&mut self, blk: u64) -> Result<(), RebuildError> {
+        if self.state() != RebuildState::Init
+            || self.range.start + blk > self.range.end
+        {
+            return Err(RebuildError::InvalidParameters {});
+        }
+        self.next = self.range.start + blk;
+        if self.dirty_log.is_none() {
+            self.task_pool.blocks_recovered = blk;
+        }
+        Ok(())
+    }
+
+    /// Queue the segment at `blk` which has failed to copy for another
+    /// attempt, unless it has used up its retries already in which case it
+    /// is recorded as failed, as is the job. A read error from the source
+    /// switches over to another source for all the segments still to copy.
+    fn retry_segment(&mut self, blk: u64, error: &RebuildError) {
+        let retries = self.retries.entry(blk).or_insert(0);
+        if *retries >= self.opts.segment_retries {
+            self.failed_segments.push(blk);
+            self.error = Some(RebuildError::SegmentsFailed {
+                blocks: self.failed_segments.clone(),
+                retries: self.opts.segment_retries,
+                source: Box::new(error.clone()),
             });
+            return;
+        }
+        *retries += 1;
+        self.segments_retried += 1;
+        warn!(
+            "Rebuild job {}: retrying segment at block {} ({}/{}) after: {}",
+            self.destination,
+            blk,
+            retries,
+            self.opts.segment_retries,
+            error.verbose()
+        );
+        self.retry_queue.push_back(blk);
+
+        match error {
+            RebuildError::ReadIoError {
+                bdev, ..
+            } if self.opts.switch_source && *bdev == self.source => {
+                self.switch_source()
+            }
+            _ => {}
+        }
+    }
+
+    /// Time to wait before retrying the segment at `blk`, doubled with
+    /// every retry
+    fn retry_backoff(&self, blk: u64) -> Duration {
+        let retries = self.retries.get(&blk).cloned().unwrap_or(1);
+        Duration::from_millis(
+            self.opts.retry_backoff_ms << std::cmp::min(retries - 1, 16),
+        )
+    }
+
+    /// Carry on reading from another healthy child of the nexus, if any,
+    /// which has not been given up on already
+    fn switch_source(&mut self) {
+        let candidates = match nexus_lookup(&self.nexus) {
+            Some(nexus) => nexus
+                .children
+                .iter()
+                .filter(|c| {
+                    c.status() == ChildStatus::Online
+                        && c.name != self.source
+                        && c.name != self.destination
+                        && !self.old_sources.iter().any(|(n, _)| *n == c.name)
+                })
+                .map(|c| c.name.clone())
+                .collect::<Vec<_>>(),
+            None => return,
+        };
+
+        for source in candidates {
+            let hdl = match bdev_get_name(&source)
+                .ok()
+                .and_then(|name| BdevHandle::open(&name, false, false).ok())
+            {
+                Some(hdl) => hdl,
+                None => continue,
+            };
+            info!(
+                "Rebuild job {}: switching source from {} to {}",
+                self.destination, self.source, source
+            );
+            let old = std::mem::replace(&mut self.source_hdl, hdl);
+            let old_name = std::mem::replace(&mut self.source, source);
+            self.old_sources.push((old_name, old));
+            return;
+        }
+    }
+
+    /// Rebuild the child `destination` as well, from the same reads as the
+    /// other destinations. The whole range is copied then, as a dirty log
+    /// only covers a single child. Only possible before the job is started.
+    pub fn add_destination(
+        &mut self,
+        destination: &str,
+    ) -> Result<(), RebuildError> {
+        if self.state() != RebuildState::Init || destination == self.source {
+            return Err(RebuildError::InvalidParameters {});
+        }
+        if Self::lookup(destination).is_ok() {
+            return Err(RebuildError::JobAlreadyExists {
+                job: destination.to_string(),
+            });
+        }
 
-            Some(next)
+        let hdl = BdevHandle::open(
+            &bdev_get_name(destination).context(BdevInvalidURI {
+                uri: destination.to_string(),
+            })?,
+            true,
+            false,
+        )
+        .context(NoBdevHandle {
+            bdev: destination,
+        })?;
+        if !Self::validate(
+            &self.source_hdl.get_bdev(),
+            &hdl.get_bdev(),
+            &self.range,
+        ) {
+            return Err(RebuildError::InvalidParameters {});
+        }
+
+        self.more_destinations.push((destination.to_string(), hdl));
+        self.dirty_log = None;
+        Ok(())
+    }
+
+    /// Give up on the destination which has failed the write of a segment
+    /// that the others took, and let the nexus know so that it can fault the
+    /// child. The job carries on with the other destinations.
+    fn drop_destination(&mut self, error: RebuildError) {
+        let bdev = match &error {
+            RebuildError::WriteIoError {
+                bdev, ..
+            } => bdev.clone(),
+            _ => return,
+        };
+        // the copies in flight may fail on it as well
+        if self.dropped_destinations.iter().any(|(d, _)| *d == bdev) {
+            return;
+        }
+        error!(
+            "Rebuild job {}: giving up on destination {} after: {}",
+            self.destination,
+            bdev,
+            error.verbose()
+        );
+        self.dropped_destinations.push((bdev, error));
+        (self.notify_fn)(self.nexus.clone(), self.destination.clone());
+    }
+
+    /// Call the notify fn every so often while running so that the nexus
+    /// can record how far the job got
+    fn checkpoint_if_due(&mut self) {
+        if self.last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+            self.last_checkpoint = Instant::now();
+            (self.notify_fn)(self.nexus.clone(), self.destination.clone());
+        }
+    }
+
+    /// Returns the offset of the next segment to rebuild, if any, skipping
+    /// over the clean regions when the job is driven by a dirty log. The
+    /// verification covers the whole range, whatever the dirty log says.
+    fn next_segment(&self) -> Option<u64> {
+        if self.next >= self.range.end {
+            return None;
+        }
+        match &self.dirty_log {
+            _ if self.verification.active => Some(self.next),
+            None => Some(self.next),
+            // the dirty log is relative to the start of the data partition
+            Some(log) => log
+                .next_dirty(self.next - self.range.start)
+                .map(|blk| blk + self.range.start)
+                .filter(|blk| *blk < self.range.end),
         }
     }
 }
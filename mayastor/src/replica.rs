@@ -11,10 +11,14 @@ use rpc::mayastor as rpc;
 use snafu::{ResultExt, Snafu};
 
 use spdk_sys::{
+    spdk_blob_get_parent_snapshot,
+    spdk_blob_is_clone,
     spdk_lvol,
     vbdev_lvol_create,
+    vbdev_lvol_create_clone,
     vbdev_lvol_destroy,
     vbdev_lvol_get_from_bdev,
+    vbdev_lvol_resize,
     LVOL_CLEAR_WITH_UNMAP,
     LVOL_CLEAR_WITH_WRITE_ZEROES,
     SPDK_BDEV_IO_TYPE_UNMAP,
@@ -22,8 +26,11 @@ use spdk_sys::{
 
 use crate::{
     core::Bdev,
+    events::{self, EventKind, Lifecycle},
     ffihelper::{cb_arg, done_errno_cb, errno_result_from_i32, ErrnoResult},
+    metrics,
     pool::Pool,
+    snapshot::{lvol_is_snapshot, Snapshot, SnapshotIter},
     subsys::NvmfSubsystem,
     target,
 };
@@ -37,6 +44,14 @@ pub enum RpcError {
     DestroyReplica { source: Error, uuid: String },
     #[snafu(display("Failed to (un)share replica {}", uuid))]
     ShareReplica { source: Error, uuid: String },
+    #[snafu(display("Failed to resize replica {}", uuid))]
+    ResizeReplica { source: Error, uuid: String },
+    #[snafu(display("Failed to clone snapshot {} to {}", snapshot, uuid))]
+    CreateClone {
+        source: Error,
+        snapshot: String,
+        uuid: String,
+    },
 }
 
 impl From<RpcError> for tonic::Status {
@@ -51,6 +66,12 @@ impl From<RpcError> for tonic::Status {
             RpcError::ShareReplica {
                 source, ..
             } => Self::from(source),
+            RpcError::ResizeReplica {
+                source, ..
+            } => Self::from(source),
+            RpcError::CreateClone {
+                source, ..
+            } => Self::from(source),
         }
     }
 }
@@ -82,6 +103,29 @@ pub enum Error {
     InvalidProtocol { protocol: i32 },
     #[snafu(display("Replica does not exist"))]
     ReplicaNotFound {},
+    #[snafu(display(
+        "Replica cannot shrink from {} to {} bytes",
+        current,
+        requested
+    ))]
+    InvalidResize { current: u64, requested: u64 },
+    #[snafu(display(
+        "Not enough space in pool \"{}\": {} bytes needed, {} bytes free",
+        pool,
+        needed,
+        free
+    ))]
+    PoolFull {
+        pool: String,
+        needed: u64,
+        free: u64,
+    },
+    #[snafu(display("Failed to resize lvol"))]
+    ResizeLvol { source: Errno },
+    #[snafu(display("Snapshot does not exist"))]
+    SnapshotNotFound {},
+    #[snafu(display("Failed to create clone lvol"))]
+    CreateCloneLvol { source: Errno },
 }
 
 impl From<Error> for tonic::Status {
@@ -123,12 +167,31 @@ impl From<Error> for tonic::Status {
             Error::ReplicaNotFound {
                 ..
             } => Self::not_found(e.to_string()),
+            Error::InvalidResize {
+                ..
+            } => Self::invalid_argument(e.to_string()),
+            Error::PoolFull {
+                ..
+            } => Self::resource_exhausted(e.to_string()),
+            Error::ResizeLvol {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::SnapshotNotFound {
+                ..
+            } => Self::not_found(e.to_string()),
+            Error::CreateCloneLvol {
+                ..
+            } => Self::internal(e.to_string()),
         }
     }
 }
 
 type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// Blob id returned by the blobstore when a blob has no parent snapshot
+/// (SPDK_BLOBID_INVALID is not exported by bindgen).
+const SPDK_BLOBID_INVALID: u64 = u64::MAX;
+
 /// Structure representing a replica which is basically SPDK lvol.
 ///
 /// Note about safety: The structure wraps raw C pointer from SPDK.
@@ -217,6 +280,55 @@ impl Replica {
             .context(CreateLvol {})?;
 
         info!("Created replica {} on pool {}", uuid, pool.get_name());
+        metrics::enable_latency_histogram(unsafe { (*lvol_ptr).bdev.into() })
+            .await;
+        events::publish(EventKind::Replica {
+            uuid: uuid.to_owned(),
+            pool: pool.get_name().to_owned(),
+            change: Lifecycle::Created,
+        });
+        Ok(Self {
+            lvol_ptr,
+        })
+    }
+
+    /// Create a thin provisioned replica on the pool of the snapshot which
+    /// shares the data of the snapshot until it is overwritten. Any number of
+    /// clones can be created from the same snapshot.
+    pub async fn create_clone(uuid: &str, snapshot: &Snapshot) -> Result<Self> {
+        if Self::lookup(uuid).is_some() {
+            return Err(Error::ReplicaExists {});
+        }
+        let c_uuid = CString::new(uuid).unwrap();
+        let (sender, receiver) =
+            oneshot::channel::<ErrnoResult<*mut spdk_lvol>>();
+        unsafe {
+            vbdev_lvol_create_clone(
+                snapshot.as_ptr(),
+                c_uuid.as_ptr(),
+                Some(Self::replica_done_cb),
+                cb_arg(sender),
+            );
+        }
+
+        let lvol_ptr = receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(CreateCloneLvol {})?;
+
+        info!(
+            "Created replica {} from snapshot {} on pool {}",
+            uuid,
+            snapshot.get_name(),
+            snapshot.get_pool_name()
+        );
+        metrics::enable_latency_histogram(unsafe { (*lvol_ptr).bdev.into() })
+            .await;
+        events::publish(EventKind::Replica {
+            uuid: uuid.to_owned(),
+            pool: snapshot.get_pool_name().to_owned(),
+            change: Lifecycle::Created,
+        });
         Ok(Self {
             lvol_ptr,
         })
@@ -227,7 +339,7 @@ impl Replica {
         match Bdev::lookup_by_name(uuid) {
             Some(bdev) => {
                 let lvol = unsafe { vbdev_lvol_get_from_bdev(bdev.as_ptr()) };
-                if lvol.is_null() {
+                if lvol.is_null() || lvol_is_snapshot(lvol) {
                     None
                 } else {
                     Some(Self {
@@ -248,7 +360,8 @@ impl Replica {
     pub async fn destroy(self) -> Result<()> {
         self.unshare().await?;
 
-        let uuid = self.get_uuid();
+        let uuid = self.get_uuid().to_owned();
+        let pool = self.get_pool_name().to_owned();
         let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
         unsafe {
             vbdev_lvol_destroy(
@@ -264,6 +377,72 @@ impl Replica {
             .context(DestroyLvol {})?;
 
         info!("Destroyed replica {}", uuid);
+        events::publish(EventKind::Replica {
+            uuid,
+            pool,
+            change: Lifecycle::Destroyed,
+        });
+        Ok(())
+    }
+
+    /// Grow the replica to `size` bytes. A thick provisioned replica needs the
+    /// additional space to be free in the pool. The bdev layer is notified of
+    /// the new size, from which both the nvmf namespace and the iscsi LUN
+    /// take their capacity, so a shared replica stays shared and the hosts
+    /// see the new size (nvmf raises a namespace attribute changed event).
+    pub async fn resize(&self, size: u64) -> Result<()> {
+        let current = self.get_size();
+        if size < current {
+            return Err(Error::InvalidResize {
+                current,
+                requested: size,
+            });
+        }
+        if size == current {
+            return Ok(());
+        }
+
+        if !self.is_thin() {
+            let pool = match Pool::lookup(self.get_pool_name()) {
+                Some(p) => p,
+                None => {
+                    return Err(Error::PoolNotFound {
+                        pool: self.get_pool_name().to_owned(),
+                    })
+                }
+            };
+            let needed = size - current;
+            let free = pool.get_free();
+            if needed > free {
+                return Err(Error::PoolFull {
+                    pool: pool.get_name().to_owned(),
+                    needed,
+                    free,
+                });
+            }
+        }
+
+        let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+        unsafe {
+            vbdev_lvol_resize(
+                self.lvol_ptr,
+                size,
+                Some(done_errno_cb),
+                cb_arg(sender),
+            );
+        }
+
+        receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(ResizeLvol {})?;
+
+        info!(
+            "Resized replica {} from {} to {} bytes",
+            self.get_uuid(),
+            current,
+            self.get_size()
+        );
         Ok(())
     }
 
@@ -349,6 +528,33 @@ impl Replica {
         unsafe { (*self.lvol_ptr).thin_provision }
     }
 
+    /// Return name of the snapshot the replica has been cloned from, if it
+    /// is a clone.
+    pub fn get_origin(&self) -> Option<String> {
+        if !unsafe { spdk_blob_is_clone((*self.lvol_ptr).blob) } {
+            return None;
+        }
+        let (lvs, parent) = unsafe {
+            let lvs = (*self.lvol_ptr).lvol_store;
+            (
+                lvs,
+                spdk_blob_get_parent_snapshot(
+                    (*lvs).blobstore,
+                    (*self.lvol_ptr).blob_id,
+                ),
+            )
+        };
+        if parent == SPDK_BLOBID_INVALID {
+            return None;
+        }
+        SnapshotIter::new()
+            .find(|s| unsafe {
+                (*s.as_ptr()).lvol_store == lvs
+                    && (*s.as_ptr()).blob_id == parent
+            })
+            .map(|s| s.get_name().to_owned())
+    }
+
     /// Return raw pointer to lvol (C struct spdk_lvol).
     pub fn as_ptr(&self) -> *mut spdk_lvol {
         self.lvol_ptr
@@ -411,9 +617,10 @@ impl Iterator for ReplicaIter {
                 None => return None,
             };
 
-            // Skip all other bdevs which are not lvols (i.e. aio)
+            // Skip all other bdevs which are not lvols (i.e. aio) and the
+            // lvols which are snapshots
             let lvol = unsafe { vbdev_lvol_get_from_bdev(bdev.as_ptr()) };
-            if !lvol.is_null() {
+            if !lvol.is_null() && !lvol_is_snapshot(lvol) {
                 let mut aliases = bdev.aliases();
                 // each lvol has a first alias of form "pool/lvol-name"
                 if !aliases.is_empty() {
@@ -453,6 +660,7 @@ impl From<Replica> for rpc::Replica {
                 None => rpc::ShareProtocolReplica::ReplicaNone,
             } as i32,
             uri: r.get_share_uri(),
+            origin: r.get_origin().unwrap_or_default(),
         }
     }
 }
@@ -536,12 +744,7 @@ pub(crate) async fn stat_replicas() -> Result<rpc::StatReplicasReply, RpcError>
                 stats.push(rpc::ReplicaStats {
                     uuid,
                     pool,
-                    stats: Some(rpc::Stats {
-                        num_read_ops: st.num_read_ops,
-                        num_write_ops: st.num_write_ops,
-                        bytes_read: st.bytes_read,
-                        bytes_written: st.bytes_written,
-                    }),
+                    stats: Some(st.into()),
                 });
             }
             Err(errno) => {
@@ -615,3 +818,70 @@ pub(crate) async fn share_replica(
         uri: replica.get_share_uri(),
     })
 }
+
+pub(crate) async fn resize_replica(
+    args: rpc::ResizeReplicaRequest,
+) -> Result<rpc::Replica, RpcError> {
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(ResizeReplica {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    replica.resize(args.size).await.context(ResizeReplica {
+        uuid: args.uuid.clone(),
+    })?;
+    Ok(replica.into())
+}
+
+pub(crate) async fn create_clone(
+    args: rpc::CreateCloneRequest,
+) -> Result<rpc::Replica, RpcError> {
+    let want_share = match rpc::ShareProtocolReplica::from_i32(args.share) {
+        Some(val) => val,
+        None => Err(Error::InvalidProtocol {
+            protocol: args.share,
+        })
+        .context(CreateClone {
+            snapshot: args.snapshot.clone(),
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(r) => r,
+        None => {
+            let snapshot = match Snapshot::lookup(&args.snapshot) {
+                Some(s) => s,
+                None => {
+                    Err(Error::SnapshotNotFound {}).context(CreateClone {
+                        snapshot: args.snapshot.clone(),
+                        uuid: args.uuid.clone(),
+                    })?
+                }
+            };
+            Replica::create_clone(&args.uuid, &snapshot).await.context(
+                CreateClone {
+                    snapshot: args.snapshot.clone(),
+                    uuid: args.uuid.clone(),
+                },
+            )?
+        }
+    };
+
+    match want_share {
+        rpc::ShareProtocolReplica::ReplicaNvmf => {
+            replica.share(ShareType::Nvmf).await.context(CreateClone {
+                snapshot: args.snapshot.clone(),
+                uuid: args.uuid.clone(),
+            })?
+        }
+        rpc::ShareProtocolReplica::ReplicaIscsi => {
+            replica.share(ShareType::Iscsi).await.context(CreateClone {
+                snapshot: args.snapshot.clone(),
+                uuid: args.uuid.clone(),
+            })?
+        }
+        rpc::ShareProtocolReplica::ReplicaNone => (),
+    }
+    Ok(replica.into())
+}
@@ -0,0 +1,6 @@
+/// Scrub api module
+mod scrub_api;
+/// Scrub implementation module
+mod scrub_impl;
+
+pub use scrub_api::*;
@@ -0,0 +1,232 @@
+#![warn(missing_docs)]
+
+use std::fmt;
+
+use futures::channel::oneshot;
+use snafu::Snafu;
+
+use crate::{
+    core::{BdevHandle, CoreError, Descriptor, DmaError},
+    nexus_uri::NexusBdevError,
+};
+
+#[derive(Debug, Snafu, Clone)]
+#[snafu(visibility = "pub(crate)")]
+#[allow(missing_docs)]
+/// Various scrub errors when interacting with a scrub job or encountered
+/// while comparing the children
+pub enum ScrubError {
+    #[snafu(display("Failed to allocate buffer for the scrub"))]
+    NoScrubBuffer { source: DmaError },
+    #[snafu(display("Failed to get a handle for bdev {}", bdev))]
+    NoBdevHandle { source: CoreError, bdev: String },
+    #[snafu(display("Bdev {} not found", bdev))]
+    BdevNotFound { source: CoreError, bdev: String },
+    #[snafu(display("Failed to get bdev name from URI {}", uri))]
+    BdevInvalidURI { source: NexusBdevError, uri: String },
+    #[snafu(display("Read IO failed for bdev {}", bdev))]
+    ReadIoError { source: CoreError, bdev: String },
+    #[snafu(display("Write IO failed for bdev {}", bdev))]
+    WriteIoError { source: CoreError, bdev: String },
+    #[snafu(display("Failed to find the scrub job of nexus {}", nexus))]
+    JobNotFound { nexus: String },
+    #[snafu(display("A scrub job of nexus {} is in progress already", nexus))]
+    JobAlreadyExists { nexus: String },
+    #[snafu(display(
+        "At least 2 children are needed for a scrub, nexus {} has {}",
+        nexus,
+        children,
+    ))]
+    NotEnoughChildren { nexus: String, children: usize },
+    #[snafu(display(
+        "Child {} to repair from is not one of the scrubbed children",
+        child
+    ))]
+    InvalidAuthority { child: String },
+    #[snafu(display(
+        "{} operation failed because current scrub state is {}.",
+        operation,
+        state,
+    ))]
+    OpError { operation: String, state: String },
+    #[snafu(display(
+        "Failed to lock LBA range for blk {}, len {}, with error: {}",
+        blk,
+        len,
+        source,
+    ))]
+    RangeLockError {
+        blk: u64,
+        len: u64,
+        source: nix::errno::Errno,
+    },
+    #[snafu(display(
+        "Failed to unlock LBA range for blk {}, len {}, with error: {}",
+        blk,
+        len,
+        source,
+    ))]
+    RangeUnLockError {
+        blk: u64,
+        len: u64,
+        source: nix::errno::Errno,
+    },
+}
+
+#[derive(Debug, PartialEq, Copy, Clone)]
+/// allowed states for a scrub job
+pub enum ScrubState {
+    /// Init when the job is newly created
+    Init,
+    /// Running when the job is comparing the children
+    Running,
+    /// Paused when the job is paused as requested through pause
+    Paused,
+    /// Stopped when the job is halted as requested through stop
+    Stopped,
+    /// Failed when an IO (R/W) operation failed
+    Failed,
+    /// Completed when all of the children have been compared
+    Completed,
+}
+
+impl fmt::Display for ScrubState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScrubState::Init => write!(f, "init"),
+            ScrubState::Running => write!(f, "running"),
+            ScrubState::Paused => write!(f, "paused"),
+            ScrubState::Stopped => write!(f, "stopped"),
+            ScrubState::Failed => write!(f, "failed"),
+            ScrubState::Completed => write!(f, "completed"),
+        }
+    }
+}
+
+impl ScrubState {
+    /// Final state of a scrub job
+    pub fn done(self) -> bool {
+        matches!(self, Self::Stopped | Self::Failed | Self::Completed)
+    }
+}
+
+/// A range of the data partition where the children do not hold the same
+/// data
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScrubMismatch {
+    /// first block of the range, relative to the start of the data partition
+    pub offset: u64,
+    /// length of the range in blocks
+    pub num_blocks: u64,
+    /// children which differ from the authoritative child, or from the
+    /// first child without one
+    pub children: Vec<String>,
+    /// whether the range of these children has been repaired
+    pub repaired: bool,
+}
+
+/// scrub statistics
+#[derive(Debug, Clone)]
+pub struct ScrubStats {
+    /// total number of blocks to compare
+    pub blocks_total: u64,
+    /// number of blocks compared
+    pub blocks_scrubbed: u64,
+    /// scrub progress in % (0-100)
+    pub progress: u64,
+    /// ranges where the children differ, in order
+    pub mismatches: Vec<ScrubMismatch>,
+}
+
+/// A scrub job reads the same segments from all the given children of a
+/// nexus and compares them. The ranges in which they differ are recorded
+/// and, when one of the children is trusted to hold the right data, the
+/// others are repaired from it.
+#[derive(Debug)]
+pub struct ScrubJob {
+    /// name of the nexus of which the children are scrubbed
+    pub nexus: String,
+    /// descriptor for the nexus
+    pub(super) nexus_descriptor: Descriptor,
+    /// URIs of the scrubbed children
+    pub children: Vec<String>,
+    /// handles of the children, closed once the job is done
+    pub(super) handles: Vec<BdevHandle>,
+    /// URI of the child the others are repaired from, if any
+    pub authority: Option<String>,
+    pub(super) block_size: u64,
+    pub(super) range: std::ops::Range<u64>,
+    pub(super) next: u64,
+    pub(super) segment_size_blks: u64,
+    /// ranges where the children differ
+    pub(super) mismatches: Vec<ScrubMismatch>,
+    /// current state of the scrub job
+    pub(super) state: ScrubState,
+    /// state requested of the running job, taken on between two segments
+    pub(super) pending: Option<ScrubState>,
+    /// whether the job is in the middle of comparing the segments
+    pub(super) running: bool,
+    /// channel list which allows the await of the end of the job
+    pub(super) complete_chan: Vec<oneshot::Sender<ScrubState>>,
+    /// scrub error, if any
+    pub error: Option<ScrubError>,
+}
+
+impl ScrubJob {
+    /// Creates a new ScrubJob for the nexus which compares the children
+    /// from start to end (of the data partition) and repairs them from the
+    /// child `authority`, if given one. A job of the nexus which is done
+    /// with is replaced.
+    pub fn create<'a>(
+        nexus: &'a str,
+        children: &[String],
+        authority: Option<&str>,
+        range: std::ops::Range<u64>,
+    ) -> Result<&'a mut Self, ScrubError> {
+        Self::new(nexus, children, authority, range)?.store()?;
+
+        Self::lookup(nexus)
+    }
+
+    /// Lookup the scrub job of the nexus
+    pub fn lookup(nexus: &str) -> Result<&mut Self, ScrubError> {
+        match Self::get_instances().get_mut(nexus) {
+            Some(job) => Ok(job),
+            None => Err(ScrubError::JobNotFound {
+                nexus: nexus.to_owned(),
+            }),
+        }
+    }
+
+    /// Lookup the scrub job of the nexus then remove and return it
+    pub fn remove(nexus: &str) -> Result<Self, ScrubError> {
+        match Self::get_instances().remove(nexus) {
+            Some(job) => Ok(*job),
+            None => Err(ScrubError::JobNotFound {
+                nexus: nexus.to_owned(),
+            }),
+        }
+    }
+
+    /// State of the scrub job
+    pub fn state(&self) -> ScrubState {
+        self.state
+    }
+
+    /// Collects statistics from the job
+    pub fn stats(&self) -> ScrubStats {
+        let blocks_total = self.range.end - self.range.start;
+        let blocks_scrubbed = self.next - self.range.start;
+
+        ScrubStats {
+            blocks_total,
+            blocks_scrubbed,
+            progress: if blocks_total == 0 {
+                100
+            } else {
+                (blocks_scrubbed * 100) / blocks_total
+            },
+            mismatches: self.mismatches.clone(),
+        }
+    }
+}
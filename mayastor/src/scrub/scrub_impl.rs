@@ -0,0 +1,409 @@
+#![warn(missing_docs)]
+
+use std::{cell::UnsafeCell, collections::HashMap};
+
+use crc::crc32;
+use futures::channel::oneshot;
+use once_cell::sync::OnceCell;
+use snafu::ResultExt;
+
+use spdk_sys::{spdk_get_thread, SPDK_BDEV_LARGE_BUF_MAX_SIZE};
+
+use crate::{
+    core::{Bdev, BdevHandle, RangeContext, Reactors},
+    nexus_uri::bdev_get_name,
+};
+
+use super::scrub_api::*;
+
+/// Global list of scrub jobs, one per nexus, using a static OnceCell
+pub(super) struct ScrubInstances {
+    inner: UnsafeCell<HashMap<String, Box<ScrubJob>>>,
+}
+
+unsafe impl Sync for ScrubInstances {}
+unsafe impl Send for ScrubInstances {}
+
+/// Size of each segment compared at a time
+const SEGMENT_SIZE: u64 = SPDK_BDEV_LARGE_BUF_MAX_SIZE as u64;
+
+impl ScrubJob {
+    /// Returns a new scrub job based on the parameters
+    pub(super) fn new(
+        nexus: &str,
+        children: &[String],
+        authority: Option<&str>,
+        range: std::ops::Range<u64>,
+    ) -> Result<Self, ScrubError> {
+        if children.len() < 2 {
+            return Err(ScrubError::NotEnoughChildren {
+                nexus: nexus.to_string(),
+                children: children.len(),
+            });
+        }
+        if let Some(authority) = authority {
+            if !children.iter().any(|c| c == authority) {
+                return Err(ScrubError::InvalidAuthority {
+                    child: authority.to_string(),
+                });
+            }
+        }
+
+        // only the children to repair need to be written to
+        let handles = children
+            .iter()
+            .map(|child| {
+                BdevHandle::open(
+                    &bdev_get_name(child).context(BdevInvalidURI {
+                        uri: child.to_string(),
+                    })?,
+                    authority.map_or(false, |a| a != child.as_str()),
+                    false,
+                )
+                .context(NoBdevHandle {
+                    bdev: child,
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // the children are part of the same nexus, so they share the
+        // block size
+        let block_size = handles[0].get_bdev().block_len() as u64;
+        let segment_size_blks = (SEGMENT_SIZE / block_size) as u64;
+
+        let nexus_descriptor =
+            Bdev::open_by_name(nexus, false).context(BdevNotFound {
+                bdev: nexus.to_string(),
+            })?;
+
+        Ok(Self {
+            nexus: nexus.to_string(),
+            nexus_descriptor,
+            children: children.to_vec(),
+            handles,
+            authority: authority.map(String::from),
+            block_size,
+            next: range.start,
+            range,
+            segment_size_blks,
+            mismatches: Vec::new(),
+            state: ScrubState::Init,
+            pending: None,
+            running: false,
+            complete_chan: Vec::new(),
+            error: None,
+        })
+    }
+
+    /// Store the job in the global list, in place of a previous job of the
+    /// nexus which is done with
+    pub(super) fn store(self) -> Result<(), ScrubError> {
+        if let Ok(job) = Self::lookup(&self.nexus) {
+            if !job.state().done() {
+                return Err(ScrubError::JobAlreadyExists {
+                    nexus: self.nexus,
+                });
+            }
+        }
+        let _ =
+            Self::get_instances().insert(self.nexus.clone(), Box::new(self));
+        Ok(())
+    }
+
+    /// Schedules the job to start in a future
+    pub fn start(&mut self) -> Result<(), ScrubError> {
+        match self.state {
+            ScrubState::Init => {
+                info!(
+                    "Scrub job of nexus {}: comparing children {:?}, repairing from {:?}",
+                    self.nexus, self.children, self.authority
+                );
+                self.state = ScrubState::Running;
+                self.schedule();
+                Ok(())
+            }
+            ScrubState::Running => Ok(()),
+            state => self.op_error("start", state),
+        }
+    }
+
+    /// Pauses the job, which can then be later resumed
+    pub fn pause(&mut self) -> Result<(), ScrubError> {
+        match self.state {
+            ScrubState::Running => {
+                self.pending = Some(ScrubState::Paused);
+                Ok(())
+            }
+            ScrubState::Paused => Ok(()),
+            state => self.op_error("pause", state),
+        }
+    }
+
+    /// Resumes a previously paused job
+    pub fn resume(&mut self) -> Result<(), ScrubError> {
+        match self.state {
+            // still comparing the segment in flight
+            ScrubState::Running => {
+                if self.pending == Some(ScrubState::Paused) {
+                    self.pending = None;
+                }
+                Ok(())
+            }
+            ScrubState::Paused => {
+                self.state = ScrubState::Running;
+                self.schedule();
+                Ok(())
+            }
+            state => self.op_error("resume", state),
+        }
+    }
+
+    /// Stops the job, the results found so far are kept
+    pub fn stop(&mut self) -> Result<(), ScrubError> {
+        match self.state {
+            ScrubState::Running => {
+                self.pending = Some(ScrubState::Stopped);
+            }
+            ScrubState::Init | ScrubState::Paused => {
+                self.finish(ScrubState::Stopped);
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Forcefully terminates the job, returns an async channel which can be
+    /// used to await for the segment in flight, if any, to be done with
+    pub fn terminate(&mut self) -> oneshot::Receiver<ScrubState> {
+        let (sender, receiver) = oneshot::channel();
+        if self.state.done() {
+            let _ = sender.send(self.state);
+        } else {
+            self.complete_chan.push(sender);
+            let _ = self.stop();
+        }
+        receiver
+    }
+
+    fn op_error(
+        &self,
+        operation: &str,
+        state: ScrubState,
+    ) -> Result<(), ScrubError> {
+        Err(ScrubError::OpError {
+            operation: operation.to_string(),
+            state: state.to_string(),
+        })
+    }
+
+    /// Runs the job on the master reactor
+    fn schedule(&self) {
+        let nexus = self.nexus.clone();
+        Reactors::master().send_future(async move {
+            match ScrubJob::lookup(&nexus) {
+                Ok(job) => job.run().await,
+                Err(_) => error!(
+                    "Failed to find and start the scrub job of nexus {}",
+                    nexus
+                ),
+            }
+        });
+    }
+
+    /// Compares the segments one after the other until all of them are
+    /// done with, or the job is requested to pause or stop
+    async fn run(&mut self) {
+        if self.running || self.state != ScrubState::Running {
+            return;
+        }
+        self.running = true;
+
+        let state = loop {
+            match self.pending.take() {
+                Some(state) => break state,
+                None if self.next >= self.range.end => {
+                    break ScrubState::Completed
+                }
+                None => {}
+            }
+
+            let blk = self.next;
+            if let Err(e) = self.locked_scrub_one(blk).await {
+                error!(
+                    "Scrub job of nexus {}: failed to compare block {}: {}",
+                    self.nexus, blk, e
+                );
+                self.error = Some(e);
+                break ScrubState::Failed;
+            }
+            self.next =
+                std::cmp::min(blk + self.segment_size_blks, self.range.end);
+        };
+
+        self.running = false;
+        if state == ScrubState::Paused {
+            self.state = state;
+        } else {
+            self.finish(state);
+        }
+    }
+
+    /// Moves the job to its final state, closing the children and waking
+    /// up anyone waiting on it
+    fn finish(&mut self, state: ScrubState) {
+        info!(
+            "Scrub job of nexus {}: {} with {} ranges differing",
+            self.nexus,
+            state,
+            self.mismatches.len()
+        );
+        self.state = state;
+        self.handles.clear();
+        for sender in self.complete_chan.drain(..) {
+            let _ = sender.send(state);
+        }
+    }
+
+    /// Return the size of the segment at `blk`
+    fn get_segment_size_blks(&self, blk: u64) -> u64 {
+        std::cmp::min(self.segment_size_blks, self.range.end - blk)
+    }
+
+    /// Compares one segment worth of data of all the children. During this
+    /// time the LBA range being compared is locked so that there cannot be
+    /// front end I/O to the same LBA range.
+    ///
+    /// # Safety
+    ///
+    /// The RangeContext MUST NOT be dropped until after the lock and unlock
+    /// have completed, it is stored on the stack for the duration of the
+    /// calls.
+    async fn locked_scrub_one(&mut self, blk: u64) -> Result<(), ScrubError> {
+        let len = self.get_segment_size_blks(blk);
+        // the range is locked on the nexus, which has a data partition only
+        let mut ctx = RangeContext::new(blk - self.range.start, len);
+        let ch = self
+            .nexus_descriptor
+            .get_channel()
+            .expect("Failed to get nexus channel");
+
+        self.nexus_descriptor
+            .lock_lba_range(&mut ctx, &ch)
+            .await
+            .context(RangeLockError {
+                blk,
+                len,
+            })?;
+
+        let result = self.scrub_one(blk).await;
+
+        self.nexus_descriptor
+            .unlock_lba_range(&mut ctx, &ch)
+            .await
+            .context(RangeUnLockError {
+                blk,
+                len,
+            })?;
+
+        result
+    }
+
+    /// Compares one segment worth of data of the children by their
+    /// checksums against the authoritative child, or else the first one,
+    /// and repairs the children which differ if there is an authority
+    async fn scrub_one(&mut self, blk: u64) -> Result<(), ScrubError> {
+        let len = self.get_segment_size_blks(blk);
+        let offset = blk * self.block_size;
+        let mut buffer = self.handles[0]
+            .dma_malloc((len * self.block_size) as usize)
+            .context(NoScrubBuffer {})?;
+
+        // the reference is read last, so that its data is at hand for the
+        // repair
+        let reference = self
+            .authority
+            .as_ref()
+            .and_then(|a| self.children.iter().position(|c| c == a))
+            .unwrap_or(0);
+        let order = (0 .. self.children.len())
+            .filter(|i| *i != reference)
+            .chain(std::iter::once(reference));
+
+        let mut checksums = vec![0; self.children.len()];
+        for i in order {
+            self.handles[i].read_at(offset, &mut buffer).await.context(
+                ReadIoError {
+                    bdev: &self.children[i],
+                },
+            )?;
+            checksums[i] = crc32::checksum_ieee(buffer.as_slice());
+        }
+
+        let differing = (0 .. self.children.len())
+            .filter(|i| checksums[*i] != checksums[reference])
+            .collect::<Vec<_>>();
+        if differing.is_empty() {
+            return Ok(());
+        }
+
+        let children = differing
+            .iter()
+            .map(|i| self.children[*i].clone())
+            .collect::<Vec<_>>();
+        warn!(
+            "Scrub job of nexus {}: children {:?} differ from {} at block {}",
+            self.nexus, children, self.children[reference], blk
+        );
+
+        let repaired = self.authority.is_some();
+        if repaired {
+            for i in differing {
+                self.handles[i].write_at(offset, &buffer).await.context(
+                    WriteIoError {
+                        bdev: &self.children[i],
+                    },
+                )?;
+            }
+        }
+
+        self.record_mismatch(ScrubMismatch {
+            offset: blk - self.range.start,
+            num_blocks: len,
+            children,
+            repaired,
+        });
+        Ok(())
+    }
+
+    /// Record the mismatching segment, as part of the previous range if it
+    /// follows on from it with the same children
+    fn record_mismatch(&mut self, mismatch: ScrubMismatch) {
+        if let Some(last) = self.mismatches.last_mut() {
+            if last.offset + last.num_blocks == mismatch.offset
+                && last.children == mismatch.children
+                && last.repaired == mismatch.repaired
+            {
+                last.num_blocks += mismatch.num_blocks;
+                return;
+            }
+        }
+        self.mismatches.push(mismatch);
+    }
+
+    /// Get the scrub job instances container, we ensure that this can only
+    /// ever be called on a properly allocated thread
+    pub(super) fn get_instances() -> &'static mut HashMap<String, Box<Self>> {
+        let thread = unsafe { spdk_get_thread() };
+        if thread.is_null() {
+            panic!("not called from SPDK thread")
+        }
+
+        static SCRUB_INSTANCES: OnceCell<ScrubInstances> = OnceCell::new();
+
+        let global_instances = SCRUB_INSTANCES.get_or_init(|| ScrubInstances {
+            inner: UnsafeCell::new(HashMap::new()),
+        });
+
+        unsafe { &mut *global_instances.inner.get() }
+    }
+}
@@ -0,0 +1,330 @@
+//! The high-level snapshot object methods.
+//!
+//! Snapshot is a read-only point-in-time copy of a replica (in SPDK
+//! terminology a snapshot lvol). The parent replica and the creation time
+//! of the snapshot are encoded in its name as "<replica-uuid>@<time>", time
+//! being the number of seconds since the epoch, because the name is the only
+//! attribute of a snapshot lvol which SPDK persists on our behalf. Further
+//! snapshots of the replica taken within the same second are told apart by
+//! a counter, as in "<replica-uuid>@<time>.<n>".
+
+use std::{
+    ffi::{c_void, CStr, CString},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use futures::channel::oneshot;
+use nix::errno::Errno;
+use rpc::mayastor as rpc;
+use snafu::{ResultExt, Snafu};
+
+use spdk_sys::{
+    spdk_blob_is_snapshot,
+    spdk_lvol,
+    vbdev_lvol_create_snapshot,
+    vbdev_lvol_destroy,
+    vbdev_lvol_get_from_bdev,
+};
+
+use crate::{
+    core::{Bdev, BdevIter},
+    ffihelper::{cb_arg, done_errno_cb, errno_result_from_i32, ErrnoResult},
+    replica::Replica,
+};
+
+/// These are high-level context errors one for each rpc method.
+#[derive(Debug, Snafu)]
+pub enum RpcError {
+    #[snafu(display("Failed to create snapshot of replica {}", uuid))]
+    CreateSnapshot { source: Error, uuid: String },
+    #[snafu(display("Failed to delete snapshot {}", name))]
+    DeleteSnapshot { source: Error, name: String },
+}
+
+impl From<RpcError> for tonic::Status {
+    fn from(e: RpcError) -> Self {
+        match e {
+            RpcError::CreateSnapshot {
+                source, ..
+            } => Self::from(source),
+            RpcError::DeleteSnapshot {
+                source, ..
+            } => Self::from(source),
+        }
+    }
+}
+
+// Snapshot errors.
+#[derive(Debug, Snafu)]
+pub enum Error {
+    #[snafu(display("Replica does not exist"))]
+    ReplicaNotFound {},
+    #[snafu(display("Failed to create snapshot lvol"))]
+    CreateSnapshotLvol { source: Errno },
+    #[snafu(display("Failed to destroy snapshot lvol"))]
+    DestroySnapshotLvol { source: Errno },
+}
+
+impl From<Error> for tonic::Status {
+    fn from(e: Error) -> Self {
+        match e {
+            Error::ReplicaNotFound {
+                ..
+            } => Self::not_found(e.to_string()),
+            Error::CreateSnapshotLvol {
+                ..
+            } => Self::internal(e.to_string()),
+            Error::DestroySnapshotLvol {
+                ..
+            } => Self::internal(e.to_string()),
+        }
+    }
+}
+
+type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Structure representing a snapshot which is basically a read-only SPDK
+/// lvol.
+///
+/// The same safety rules as for the Replica apply: the raw pointer to the
+/// lvol is only valid within a synchronous context on reactor_0.
+pub struct Snapshot {
+    lvol_ptr: *mut spdk_lvol,
+}
+
+/// Return current time as the number of seconds since the epoch.
+pub fn snapshot_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("System time is before the epoch")
+        .as_secs()
+}
+
+/// Return true if the lvol is a snapshot rather than a replica.
+pub(crate) fn lvol_is_snapshot(lvol: *mut spdk_lvol) -> bool {
+    unsafe { spdk_blob_is_snapshot((*lvol).blob) }
+}
+
+impl Snapshot {
+    /// Create a snapshot of the replica. All snapshots of a consistent group
+    /// of replicas (i.e. of a nexus) are created with the same time.
+    pub async fn create(replica: &Replica, created: u64) -> Result<Self> {
+        let mut name = format!("{}@{}", replica.get_uuid(), created);
+        let mut n = 0;
+        while Bdev::lookup_by_name(&name).is_some() {
+            n += 1;
+            name = format!("{}@{}.{}", replica.get_uuid(), created, n);
+        }
+
+        let c_name = CString::new(name.clone()).unwrap();
+        let (sender, receiver) =
+            oneshot::channel::<ErrnoResult<*mut spdk_lvol>>();
+        unsafe {
+            vbdev_lvol_create_snapshot(
+                replica.as_ptr(),
+                c_name.as_ptr(),
+                Some(Self::snapshot_done_cb),
+                cb_arg(sender),
+            );
+        }
+
+        let lvol_ptr = receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(CreateSnapshotLvol {})?;
+
+        info!(
+            "Created snapshot {} on pool {}",
+            name,
+            replica.get_pool_name()
+        );
+        Ok(Self {
+            lvol_ptr,
+        })
+    }
+
+    /// Lookup snapshot by name.
+    pub fn lookup(name: &str) -> Option<Self> {
+        let bdev = Bdev::lookup_by_name(name)?;
+        let lvol = unsafe { vbdev_lvol_get_from_bdev(bdev.as_ptr()) };
+        if lvol.is_null() || !lvol_is_snapshot(lvol) {
+            None
+        } else {
+            Some(Self {
+                lvol_ptr: lvol,
+            })
+        }
+    }
+
+    /// Destroy the snapshot. Consumes the "self" so after calling this method
+    /// self can't be used anymore. The data of the snapshot which is still
+    /// referenced by its clone is handed over to the clone by SPDK.
+    pub async fn destroy(self) -> Result<()> {
+        let name = self.get_name().to_owned();
+        let (sender, receiver) = oneshot::channel::<ErrnoResult<()>>();
+        unsafe {
+            vbdev_lvol_destroy(
+                self.lvol_ptr,
+                Some(done_errno_cb),
+                cb_arg(sender),
+            );
+        }
+
+        receiver
+            .await
+            .expect("Cancellation is not supported")
+            .context(DestroySnapshotLvol {})?;
+
+        info!("Destroyed snapshot {}", name);
+        Ok(())
+    }
+
+    /// Get name of the snapshot.
+    pub fn get_name(&self) -> &str {
+        unsafe {
+            CStr::from_ptr(&(*self.lvol_ptr).name as *const i8)
+                .to_str()
+                .unwrap()
+        }
+    }
+
+    /// Get uuid of the replica the snapshot has been taken of.
+    pub fn get_parent(&self) -> &str {
+        let name = self.get_name();
+        match name.rfind('@') {
+            Some(i) => &name[.. i],
+            None => name,
+        }
+    }
+
+    /// Get creation time of the snapshot in seconds since the epoch.
+    pub fn get_created(&self) -> u64 {
+        let name = self.get_name();
+        match name.rfind('@') {
+            Some(i) => name[i + 1 ..]
+                .split('.')
+                .next()
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(0),
+            None => 0,
+        }
+    }
+
+    /// Get size of the snapshot in bytes.
+    pub fn get_size(&self) -> u64 {
+        let bdev: Bdev = unsafe { (*self.lvol_ptr).bdev.into() };
+        u64::from(bdev.block_len()) * bdev.num_blocks()
+    }
+
+    /// Get name of the pool which snapshot belongs to.
+    pub fn get_pool_name(&self) -> &str {
+        unsafe {
+            let lvs = &*(*self.lvol_ptr).lvol_store;
+            CStr::from_ptr(&lvs.name as *const i8).to_str().unwrap()
+        }
+    }
+
+    /// Return raw pointer to lvol (C struct spdk_lvol).
+    pub fn as_ptr(&self) -> *mut spdk_lvol {
+        self.lvol_ptr
+    }
+
+    /// Callback called from SPDK for snapshot create method.
+    extern "C" fn snapshot_done_cb(
+        sender_ptr: *mut c_void,
+        lvol_ptr: *mut spdk_lvol,
+        errno: i32,
+    ) {
+        let sender = unsafe {
+            Box::from_raw(
+                sender_ptr as *mut oneshot::Sender<ErrnoResult<*mut spdk_lvol>>,
+            )
+        };
+        sender
+            .send(errno_result_from_i32(lvol_ptr, errno))
+            .expect("Receiver is gone");
+    }
+}
+
+/// Iterator over snapshots
+pub struct SnapshotIter {
+    /// Iterator over all bdevs, None if there are no bdevs at all
+    bdevs: Option<BdevIter>,
+}
+
+impl SnapshotIter {
+    pub fn new() -> SnapshotIter {
+        SnapshotIter {
+            bdevs: Bdev::bdev_first().map(|bdev| bdev.into_iter()),
+        }
+    }
+}
+
+impl Default for SnapshotIter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Iterator for SnapshotIter {
+    type Item = Snapshot;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bdevs = self.bdevs.as_mut()?;
+        // skip all other bdevs which are not snapshot lvols
+        bdevs
+            .map(|bdev| unsafe { vbdev_lvol_get_from_bdev(bdev.as_ptr()) })
+            .find(|lvol| !lvol.is_null() && lvol_is_snapshot(*lvol))
+            .map(|lvol| Snapshot {
+                lvol_ptr: lvol,
+            })
+    }
+}
+
+impl From<Snapshot> for rpc::Snapshot {
+    fn from(s: Snapshot) -> Self {
+        rpc::Snapshot {
+            name: s.get_name().to_owned(),
+            pool: s.get_pool_name().to_owned(),
+            replica: s.get_parent().to_owned(),
+            size: s.get_size(),
+            created: s.get_created(),
+        }
+    }
+}
+
+pub(crate) async fn create_snapshot(
+    args: rpc::CreateSnapshotRequest,
+) -> Result<rpc::Snapshot, RpcError> {
+    let replica = match Replica::lookup(&args.uuid) {
+        Some(replica) => replica,
+        None => Err(Error::ReplicaNotFound {}).context(CreateSnapshot {
+            uuid: args.uuid.clone(),
+        })?,
+    };
+    let snapshot = Snapshot::create(&replica, snapshot_time()).await.context(
+        CreateSnapshot {
+            uuid: args.uuid.clone(),
+        },
+    )?;
+    Ok(snapshot.into())
+}
+
+pub(crate) fn list_snapshots() -> rpc::ListSnapshotsReply {
+    rpc::ListSnapshotsReply {
+        snapshots: SnapshotIter::new()
+            .map(|s| s.into())
+            .collect::<Vec<rpc::Snapshot>>(),
+    }
+}
+
+pub(crate) async fn delete_snapshot(
+    args: rpc::DeleteSnapshotRequest,
+) -> Result<(), RpcError> {
+    match Snapshot::lookup(&args.name) {
+        Some(snapshot) => snapshot.destroy().await.context(DeleteSnapshot {
+            name: args.name,
+        }),
+        None => Ok(()),
+    }
+}
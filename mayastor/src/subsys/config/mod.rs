@@ -29,7 +29,14 @@ use spdk_sys::{
 };
 
 use crate::{
-    bdev::{nexus::instances, nexus_create, VerboseError},
+    bdev::{
+        nexus::instances,
+        nexus_create,
+        nexus_lookup,
+        ReadPolicy,
+        VerboseError,
+        WritePolicy,
+    },
     core::{Bdev, Cores, Reactor},
     jsonrpc::{jsonrpc_register, Code, RpcErrorCode},
     nexus_uri::bdev_create,
@@ -44,6 +51,7 @@ use crate::{
             NexusOpts,
             NvmeBdevOpts,
             NvmfTgtConfig,
+            RebuildOpts,
         },
         NvmfSubsystem,
     },
@@ -147,6 +155,8 @@ pub struct Config {
     pub nexus_opts: NexusOpts,
     /// error store opts
     pub err_store_opts: ErrStoreOpts,
+    /// rebuild opts
+    pub rebuild_opts: RebuildOpts,
     ///
     /// The next options are intended for usage during testing
     ///
@@ -224,11 +234,15 @@ impl Config {
             pools: None,
             implicit_share_base: true,
             err_store_opts: self.err_store_opts.get(),
+            rebuild_opts: self.rebuild_opts.get(),
         };
 
         // collect nexus bdevs and insert them into the config
+        // skipping those which mirror the disks of a pool as the pool
+        // creates them
         let nexus_bdevs = instances()
             .iter()
+            .filter(|nexus| nexus.bdev.claimed_by() != Some("lvol".into()))
             .map(|nexus| NexusBdev {
                 name: nexus.name.clone(),
                 uuid: nexus.bdev.uuid_as_string(),
@@ -238,6 +252,8 @@ impl Config {
                     .iter()
                     .map(|child| child.name.clone())
                     .collect::<Vec<_>>(),
+                read_policy: nexus.read_policy(),
+                write_policy: nexus.write_policy(),
             })
             .collect::<Vec<_>>();
 
@@ -261,9 +277,19 @@ impl Config {
         let pools = PoolsIter::new()
             .map(|p| Pool {
                 name: p.get_name().into(),
-                disks: vec![p.get_base_bdev().name()],
+                // the disks of a mirror are known by their URIs
+                disks: p
+                    .get_disks()
+                    .into_iter()
+                    .map(|d| match p.get_layout() {
+                        rpc::mayastor::PoolLayout::PoolMirror => d.uri,
+                        rpc::mayastor::PoolLayout::PoolStripe
+                        | rpc::mayastor::PoolLayout::PoolSingle => d.name,
+                    })
+                    .collect(),
                 blk_size: p.get_base_bdev().block_len(),
                 io_if: 0, // AIO
+                layout: p.get_layout() as i32,
                 replicas: ReplicaIter::new()
                     .map(|p| Replica {
                         name: p.get_uuid().to_string(),
@@ -331,6 +357,9 @@ impl Config {
                                 e.verbose()
                             );
                             failures += 1;
+                        } else if let Some(n) = nexus_lookup(&nexus.name) {
+                            n.set_read_policy(nexus.read_policy);
+                            n.set_write_policy(nexus.write_policy);
                         }
                     }
                     Err(_e) => {
@@ -492,6 +521,12 @@ pub struct NexusBdev {
     pub size: String,
     /// the children the nexus should be created on
     pub children: Vec<String>,
+    /// policy used to select the child to read from
+    #[serde(default)]
+    pub read_policy: ReadPolicy,
+    /// policy used to decide when a write has succeeded
+    #[serde(default)]
+    pub write_policy: WritePolicy,
 }
 
 #[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
@@ -515,6 +550,9 @@ pub struct Pool {
     pub blk_size: u32,
     /// use AIO, uring or auto detect
     pub io_if: i32,
+    /// stripe or mirror the disks
+    #[serde(default)]
+    pub layout: i32,
     /// list of replicas to share on load
     pub replicas: Vec<Replica>,
 }
@@ -527,6 +565,7 @@ impl From<&Pool> for rpc::mayastor::CreatePoolRequest {
             disks: o.disks.clone(),
             block_size: o.blk_size,
             io_if: o.io_if,
+            layout: o.layout,
         }
     }
 }
@@ -45,6 +45,9 @@ pub struct NexusOpts {
     pub iscsi_nexus_port: u16,
     /// Port for replica target portal
     pub iscsi_replica_port: u16,
+    /// timeout in seconds after which an IO outstanding on a child of a
+    /// nexus is aborted and the child faulted, 0 disables the timeout
+    pub child_timeout_sec: u64,
 }
 
 /// Default nvmf port used for replicas.
@@ -67,6 +70,7 @@ impl Default for NexusOpts {
             iscsi_enable: true,
             iscsi_nexus_port: ISCSI_PORT_NEXUS,
             iscsi_replica_port: ISCSI_PORT_REPLICA,
+            child_timeout_sec: 0,
         }
     }
 }
@@ -489,14 +493,29 @@ pub struct ErrStoreOpts {
     /// NexusErrStore enabled
     pub enable_err_store: bool,
 
-    /// whether to fault the child due to the total number of failed IOs
+    /// what to do with the child once any of the limits below is exceeded
     pub action: ActionType,
 
     /// the maximum number of errors in total
     pub max_errors: u32,
 
+    /// the maximum number of read errors, unlimited apart from the total
+    /// if not set
+    pub max_read_errors: Option<u32>,
+
+    /// the maximum number of write errors, unlimited apart from the total
+    /// if not set
+    pub max_write_errors: Option<u32>,
+
     /// errors older than this are ignored
     pub retention_ns: u64,
+
+    /// length of the sliding window the error rate is measured in, zero
+    /// disables the rate detection
+    pub rate_window_ns: u64,
+
+    /// the maximum number of errors within any window
+    pub max_errors_per_window: u32,
 }
 
 impl Default for ErrStoreOpts {
@@ -506,7 +525,11 @@ impl Default for ErrStoreOpts {
             enable_err_store: true,
             action: ActionType::Fault,
             max_errors: 64,
+            max_read_errors: None,
+            max_write_errors: None,
             retention_ns: 10_000_000_000,
+            rate_window_ns: 0,
+            max_errors_per_window: 0,
         }
     }
 }
@@ -516,3 +539,40 @@ impl GetOpts for ErrStoreOpts {
         self.clone()
     }
 }
+
+#[serde(default, deny_unknown_fields)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RebuildOpts {
+    /// number of times the copy of a segment is retried before the rebuild
+    /// fails
+    pub segment_retries: u32,
+
+    /// delay before the first retry of a segment in milliseconds, doubled
+    /// with every further retry
+    pub retry_backoff_ms: u64,
+
+    /// carry on reading from another healthy child when reading from the
+    /// source of the rebuild fails
+    pub switch_source: bool,
+
+    /// compare the rebuilt children against the source once copied and
+    /// copy the segments which differ again
+    pub verify: bool,
+}
+
+impl Default for RebuildOpts {
+    fn default() -> Self {
+        Self {
+            segment_retries: 3,
+            retry_backoff_ms: 100,
+            switch_source: true,
+            verify: false,
+        }
+    }
+}
+
+impl GetOpts for RebuildOpts {
+    fn get(&self) -> Self {
+        self.clone()
+    }
+}
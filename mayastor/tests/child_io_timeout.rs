@@ -0,0 +1,101 @@
+use std::time::{Duration, Instant};
+
+pub mod common;
+
+pub use common::error_bdev::{
+    create_error_bdev,
+    inject_error,
+    SPDK_BDEV_IO_TYPE_WRITE,
+    VBDEV_IO_PENDING,
+};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, ChildStatus, NexusStatus},
+    core::{
+        mayastor_env_stop,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    subsys::Config,
+};
+
+static NXNAME: &str = "child_io_timeout_nexus";
+
+static DISKNAME1: &str = "/tmp/timeout1.img";
+static BDEVNAME1: &str = "aio:///tmp/timeout1.img?blk_size=512";
+
+static DISKNAME2: &str = "/tmp/timeout2.img";
+
+static ERROR_DEVICE: &str = "timeout_error_device";
+static EE_ERROR_DEVICE: &str = "EE_timeout_error_device"; // The prefix is added by the vbdev_error module
+static BDEV_EE_ERROR_DEVICE: &str = "bdev:///EE_timeout_error_device";
+
+static CFGNAME: &str = "/tmp/child_io_timeout.yaml";
+
+#[test]
+// a child on which an IO never completes is faulted once the IO times out
+// and the IO of the nexus completes from the other child
+fn child_io_timeout() {
+    let mut config = Config::default();
+    config.nexus_opts.child_timeout_sec = 1;
+    config.write(CFGNAME).unwrap();
+
+    common::truncate_file(DISKNAME1, 64 * 1024);
+    common::truncate_file(DISKNAME2, 64 * 1024);
+
+    test_init!(CFGNAME);
+
+    Reactor::block_on(async {
+        create_error_bdev(ERROR_DEVICE, DISKNAME2);
+        nexus_create(
+            NXNAME,
+            32 * 1024 * 1024,
+            None,
+            &[BDEVNAME1.to_string(), BDEV_EE_ERROR_DEVICE.to_string()],
+        )
+        .await
+        .unwrap();
+
+        inject_error(
+            EE_ERROR_DEVICE,
+            SPDK_BDEV_IO_TYPE_WRITE,
+            VBDEV_IO_PENDING,
+            1,
+        );
+
+        let bdev = BdevHandle::open(NXNAME, true, false).unwrap();
+        let mut buf = bdev.dma_malloc(512).expect("failed to allocate buffer");
+        buf.fill(0xff);
+        let start = Instant::now();
+        bdev.write_at(0, &buf)
+            .await
+            .expect("write should complete from the other child");
+        assert!(start.elapsed() >= Duration::from_secs(1));
+
+        // the child is faulted on the management reactor
+        let nexus = nexus_lookup(NXNAME).unwrap();
+        while nexus.children[1].status() != ChildStatus::Faulted {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            reactor_poll!(1);
+        }
+        assert_eq!(nexus.status(), NexusStatus::Degraded);
+
+        // the data is read back from the remaining child
+        let mut buf = bdev.dma_malloc(512).expect("failed to allocate buffer");
+        bdev.read_at(0, &mut buf).await.unwrap();
+        assert!(buf.as_slice().iter().all(|b| *b == 0xff));
+
+        drop(bdev);
+        nexus.destroy().await.unwrap();
+    });
+
+    mayastor_env_stop(0);
+
+    common::delete_file(&[
+        DISKNAME1.to_string(),
+        DISKNAME2.to_string(),
+        CFGNAME.to_string(),
+    ]);
+}
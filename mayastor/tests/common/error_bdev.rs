@@ -1,8 +1,10 @@
 use spdk_sys::{create_aio_bdev, vbdev_error_create, vbdev_error_inject_error};
 pub use spdk_sys::{SPDK_BDEV_IO_TYPE_READ, SPDK_BDEV_IO_TYPE_WRITE};
 
-// constant used by the vbdev_error module but not exported
+// constants used by the vbdev_error module but not exported
 pub const VBDEV_IO_FAILURE: u32 = 1;
+/// the IO does not complete until the error bdev is reset
+pub const VBDEV_IO_PENDING: u32 = 2;
 
 pub fn create_error_bdev(error_device: &str, backing_device: &str) {
     let mut retval: i32;
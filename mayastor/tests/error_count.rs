@@ -54,7 +54,7 @@ fn nexus_error_count_test() {
         create_error_bdev(ERROR_DEVICE, DISKNAME2);
         create_nexus().await;
         err_write_nexus(true).await;
-        err_read_nexus_both(true).await;
+        err_read_nexus_both().await;
     });
 
     reactor_run_millis(1); // give time for any errors to be added to the error store
@@ -87,7 +87,7 @@ fn nexus_error_count_test() {
             1,
         );
         err_write_nexus(false).await;
-        err_read_nexus_both(true).await;
+        err_read_nexus_both().await;
     });
 
     reactor_run_millis(1); // give time for any errors to be added to the error store
@@ -119,7 +119,7 @@ fn nexus_error_count_test() {
             VBDEV_IO_FAILURE,
             1,
         );
-        err_read_nexus_both(false).await;
+        err_read_nexus_both().await;
         err_write_nexus(true).await;
     });
 
@@ -161,7 +161,7 @@ fn nexus_error_count_test() {
             100,
         );
         for _ in 0 .. 257 {
-            err_read_nexus_both(false).await;
+            err_read_nexus_both().await;
         }
         for _ in 0 .. 100 {
             err_write_nexus(false).await;
@@ -259,15 +259,13 @@ async fn err_write_nexus(succeed: bool) {
     };
 }
 
-async fn err_read_nexus_both(succeed: bool) {
+async fn err_read_nexus_both() {
     let res1 = err_read_nexus().await;
     let res2 = err_read_nexus().await;
 
-    if succeed {
-        assert!(res1 && res2); // both succeeded
-    } else {
-        assert_ne!(res1, res2); // one succeeded, one failed
-    }
+    // a read that fails on the error device is retried on the healthy child
+    // so both always succeed, the failure is still counted in the error store
+    assert!(res1 && res2);
 }
 
 async fn err_read_nexus() -> bool {
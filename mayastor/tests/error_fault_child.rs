@@ -74,14 +74,14 @@ fn nexus_fault_child_test() {
         );
 
         for _ in 0 .. 3 {
-            err_read_nexus_both(false).await;
+            err_read_nexus_both().await;
             reactor_run_millis(1);
         }
         for _ in 0 .. 2 {
             // the second iteration causes the error count to exceed the max no
             // of retry errors (4) for the read and causes the child to be
             // removed
-            err_read_nexus_both(false).await;
+            err_read_nexus_both().await;
             reactor_run_millis(1);
         }
     });
@@ -91,7 +91,7 @@ fn nexus_fault_child_test() {
     check_nexus_state_is(NexusStatus::Degraded);
 
     Reactor::block_on(async {
-        err_read_nexus_both(true).await; // should succeed because both IOs go to the remaining child
+        err_read_nexus_both().await; // both IOs go to the remaining child
         err_write_nexus(true).await; // should succeed because the IO goes to
                                      // the remaining child
     });
@@ -138,15 +138,12 @@ async fn err_read_nexus() -> bool {
     d.read_at(0, &mut buf).await.is_ok()
 }
 
-async fn err_read_nexus_both(succeed: bool) {
+async fn err_read_nexus_both() {
     let res1 = err_read_nexus().await;
     let res2 = err_read_nexus().await;
 
-    if succeed {
-        assert!(res1 && res2); // both succeeded
-    } else {
-        assert_ne!(res1, res2); // one succeeded, one failed
-    }
+    // failed reads are retried on the healthy child
+    assert!(res1 && res2);
 }
 
 async fn err_write_nexus(succeed: bool) {
@@ -1,9 +1,51 @@
+use crossbeam::channel::unbounded;
+use futures::FutureExt;
+
 pub mod common;
-use mayastor::bdev::{NexusErrStore, QueryType};
+use common::error_bdev::{
+    create_error_bdev,
+    inject_error,
+    SPDK_BDEV_IO_TYPE_READ,
+    SPDK_BDEV_IO_TYPE_WRITE,
+    VBDEV_IO_FAILURE,
+};
+use mayastor::{
+    bdev::{
+        nexus_create,
+        nexus_lookup,
+        ActionType,
+        ChildStatus,
+        NexusErrStore,
+        NexusStatus,
+        QueryType,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    events::{self, EventKind, Subscription},
+    subsys::Config,
+};
 use std::time::{Duration, Instant};
 
 const ALL_FLAGS: u32 = 0xffff_ffff;
 
+static NEXUS_NAME: &str = "error_store_test_nexus";
+
+static DISKNAME1: &str = "/tmp/error_store1.img";
+static BDEVNAME1: &str = "aio:///tmp/error_store1.img?blk_size=512";
+
+static DISKNAME2: &str = "/tmp/error_store2.img";
+
+static ERROR_DEVICE: &str = "error_store_device";
+static EE_ERROR_DEVICE: &str = "EE_error_store_device"; // The prefix is added by the vbdev_error module
+static BDEV_EE_ERROR_DEVICE: &str = "bdev:///EE_error_store_device";
+
+static YAML_CONFIG_FILE: &str = "/tmp/error_store_test_nexus.yaml";
+
 #[test]
 fn nexus_child_error_store_test() {
     let mut es = NexusErrStore::new(15);
@@ -100,6 +142,268 @@ fn nexus_child_error_store_test() {
     assert_eq!(errors, 0);
 }
 
+#[test]
+fn nexus_child_error_store_list_test() {
+    let mut es = NexusErrStore::new(4);
+    let start_inst = Instant::now();
+
+    assert!(es.list(ALL_FLAGS, ALL_FLAGS, None).is_empty());
+
+    add_records(&mut es, 2, NexusErrStore::IO_TYPE_READ, start_inst, 5);
+    add_records(&mut es, 1, NexusErrStore::IO_TYPE_WRITE, start_inst, 10);
+
+    // the most recent record comes first
+    let records = es.list(ALL_FLAGS, ALL_FLAGS, None);
+    assert_eq!(records.len(), 3);
+    assert_eq!(records[0].io_op, NexusErrStore::IO_TYPE_WRITE);
+    assert_eq!(records[1].io_op, NexusErrStore::IO_TYPE_READ);
+    assert_eq!(records[0].io_error, NexusErrStore::IO_FAILED);
+    assert_eq!(records[0].io_num_blocks, 1);
+
+    // the same filters apply as to the query
+    let records = es.list(NexusErrStore::READ_FLAG, ALL_FLAGS, None);
+    assert_eq!(records.len(), 2);
+    let records = es.list(
+        ALL_FLAGS,
+        ALL_FLAGS,
+        Some(start_inst + Duration::from_nanos(6)),
+    );
+    assert_eq!(records.len(), 1);
+    assert!(es.list(ALL_FLAGS, 0, None).is_empty());
+
+    // the oldest records are overwritten
+    add_records(&mut es, 2, NexusErrStore::IO_TYPE_UNMAP, start_inst, 15);
+    let records = es.list(ALL_FLAGS, ALL_FLAGS, None);
+    assert_eq!(records.len(), 4);
+    assert_eq!(records[3].io_op, NexusErrStore::IO_TYPE_READ);
+    assert_eq!(
+        records.len() as u32,
+        es.query(ALL_FLAGS, ALL_FLAGS, None, QueryType::Total)
+    );
+}
+
+#[test]
+fn nexus_child_error_store_rate_test() {
+    let mut es = NexusErrStore::new(16);
+    let start_inst = Instant::now();
+    let rate = |es: &NexusErrStore, op_flags, window| {
+        es.query(
+            op_flags,
+            ALL_FLAGS,
+            None,
+            QueryType::MaxRate(Duration::from_nanos(window)),
+        )
+    };
+
+    assert_eq!(rate(&es, ALL_FLAGS, 10), 0);
+
+    // a burst of errors in between sporadic ones, added out of order
+    add_records(&mut es, 1, NexusErrStore::IO_TYPE_READ, start_inst, 0);
+    add_records(&mut es, 2, NexusErrStore::IO_TYPE_WRITE, start_inst, 52);
+    add_records(&mut es, 2, NexusErrStore::IO_TYPE_READ, start_inst, 50);
+    add_records(&mut es, 1, NexusErrStore::IO_TYPE_WRITE, start_inst, 55);
+    add_records(&mut es, 1, NexusErrStore::IO_TYPE_READ, start_inst, 100);
+
+    assert_eq!(rate(&es, ALL_FLAGS, 1), 2);
+    assert_eq!(rate(&es, ALL_FLAGS, 5), 4);
+    assert_eq!(rate(&es, ALL_FLAGS, 6), 5);
+    assert_eq!(rate(&es, ALL_FLAGS, 1000), 7);
+    assert_eq!(rate(&es, NexusErrStore::READ_FLAG, 10), 2);
+    assert_eq!(rate(&es, NexusErrStore::WRITE_FLAG, 10), 3);
+
+    // errors before the given time do not count
+    assert_eq!(
+        es.query(
+            ALL_FLAGS,
+            ALL_FLAGS,
+            Some(start_inst + Duration::from_nanos(53)),
+            QueryType::MaxRate(Duration::from_nanos(1000)),
+        ),
+        2
+    );
+
+    es.clear();
+    assert_eq!(rate(&es, ALL_FLAGS, 1000), 0);
+    assert_eq!(es.query(ALL_FLAGS, ALL_FLAGS, None, QueryType::Total), 0);
+}
+
+#[test]
+fn nexus_child_error_store_retire_test() {
+    common::truncate_file(DISKNAME1, 64 * 1024);
+    common::truncate_file(DISKNAME2, 64 * 1024);
+
+    // read errors only count towards the total
+    let mut config = Config::default();
+    config.err_store_opts.enable_err_store = true;
+    config.err_store_opts.err_store_size = 256;
+    config.err_store_opts.action = ActionType::Retire;
+    config.err_store_opts.max_errors = 64;
+    config.err_store_opts.max_write_errors = Some(2);
+    config.write(YAML_CONFIG_FILE).unwrap();
+
+    test_init!(YAML_CONFIG_FILE);
+
+    let mut subscription = Reactor::block_on(async {
+        create_error_bdev(ERROR_DEVICE, DISKNAME2);
+        let ch = vec![BDEV_EE_ERROR_DEVICE.to_string(), BDEVNAME1.to_string()];
+        nexus_create(NEXUS_NAME, 64 * 1024 * 1024, None, &ch)
+            .await
+            .unwrap();
+        inject_error(
+            EE_ERROR_DEVICE,
+            SPDK_BDEV_IO_TYPE_READ,
+            VBDEV_IO_FAILURE,
+            4,
+        );
+        inject_error(
+            EE_ERROR_DEVICE,
+            SPDK_BDEV_IO_TYPE_WRITE,
+            VBDEV_IO_FAILURE,
+            3,
+        );
+        events::subscribe(0, 0).unwrap()
+    })
+    .unwrap();
+
+    // the reads alternate between the children and are retried on the
+    // healthy one
+    for _ in 0 .. 16 {
+        if child_errors(NexusErrStore::READ_FLAG) == 4 {
+            break;
+        }
+        assert!(Reactor::block_on(read_nexus()).unwrap());
+        reactor_run_millis(1);
+    }
+    for _ in 0 .. 2 {
+        assert!(!Reactor::block_on(write_nexus()).unwrap());
+        reactor_run_millis(1);
+    }
+    assert_eq!(child_errors(NexusErrStore::READ_FLAG), 4);
+    assert_eq!(child_errors(NexusErrStore::WRITE_FLAG), 2);
+    assert_eq!(child_status(), ChildStatus::Online);
+    assert!(child_errors_events(&mut subscription).is_empty());
+
+    // the third write error exceeds the limit and the child is retired,
+    // which takes it offline and rebuilds it from scratch
+    assert!(!Reactor::block_on(write_nexus()).unwrap());
+    for _ in 0 .. 100 {
+        reactor_run_millis(10);
+        let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+        if nexus.status() == NexusStatus::Online
+            && !nexus
+                .get_rebuild_history(Some(BDEV_EE_ERROR_DEVICE))
+                .rebuilds
+                .is_empty()
+        {
+            break;
+        }
+    }
+    assert_eq!(
+        child_errors_events(&mut subscription),
+        vec![EventKind::ChildErrors {
+            nexus: NEXUS_NAME.into(),
+            child: BDEV_EE_ERROR_DEVICE.into(),
+            action: ActionType::Retire,
+            reason: "3 write errors exceed the limit of 2".into(),
+        }]
+    );
+    let history = nexus_lookup(NEXUS_NAME)
+        .unwrap()
+        .get_rebuild_history(Some(BDEV_EE_ERROR_DEVICE))
+        .rebuilds;
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].state, "completed");
+    assert_eq!(child_status(), ChildStatus::Online);
+    assert_eq!(child_errors(ALL_FLAGS), 0);
+
+    Reactor::block_on(async {
+        assert!(write_nexus().await);
+        nexus_lookup(NEXUS_NAME).unwrap().destroy().await.unwrap();
+    });
+
+    mayastor_env_stop(0);
+
+    common::delete_file(&[
+        DISKNAME1.to_string(),
+        DISKNAME2.to_string(),
+        YAML_CONFIG_FILE.to_string(),
+    ]);
+}
+
+fn child_status() -> ChildStatus {
+    let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+    nexus
+        .children
+        .iter()
+        .find(|c| c.name == BDEV_EE_ERROR_DEVICE)
+        .unwrap()
+        .status()
+}
+
+fn child_errors(op_flags: u32) -> u32 {
+    nexus_lookup(NEXUS_NAME)
+        .unwrap()
+        .error_record_query(
+            BDEV_EE_ERROR_DEVICE,
+            op_flags,
+            NexusErrStore::IO_FAILED_FLAG,
+            None,
+            QueryType::Total,
+        )
+        .unwrap()
+        .unwrap()
+}
+
+// the ChildErrors events published since the last call
+fn child_errors_events(subscription: &mut Subscription) -> Vec<EventKind> {
+    let mut kinds = Vec::new();
+    while let Some(event) = subscription.next().now_or_never() {
+        let kind = event.unwrap().kind;
+        if let EventKind::ChildErrors {
+            ..
+        } = kind
+        {
+            kinds.push(kind);
+        }
+    }
+    kinds
+}
+
+async fn read_nexus() -> bool {
+    let bdev =
+        Bdev::lookup_by_name(NEXUS_NAME).expect("failed to lookup nexus");
+    let d = bdev
+        .open(true)
+        .expect("failed open bdev")
+        .into_handle()
+        .unwrap();
+    let mut buf = d.dma_malloc(512).expect("failed to allocate buffer");
+
+    d.read_at(0, &mut buf).await.is_ok()
+}
+
+async fn write_nexus() -> bool {
+    let bdev =
+        Bdev::lookup_by_name(NEXUS_NAME).expect("failed to lookup nexus");
+    let d = bdev
+        .open(true)
+        .expect("failed open bdev")
+        .into_handle()
+        .unwrap();
+    let buf = d.dma_malloc(512).expect("failed to allocate buffer");
+
+    d.write_at(0, &buf).await.is_ok()
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
+
 fn add_records(
     es: &mut NexusErrStore,
     how_many: usize,
@@ -0,0 +1,182 @@
+use crossbeam::channel::unbounded;
+use futures::FutureExt;
+
+use std::time::Duration;
+pub mod common;
+
+use common::error_bdev::{
+    create_error_bdev,
+    inject_error,
+    SPDK_BDEV_IO_TYPE_READ,
+    VBDEV_IO_FAILURE,
+};
+use mayastor::{
+    bdev::{
+        nexus_create,
+        nexus_lookup,
+        ActionType,
+        ChildStatus,
+        NexusErrStore,
+        NexusStatus,
+        QueryType,
+    },
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+    events::{self, EventKind, Subscription},
+    subsys::Config,
+};
+
+static NEXUS_NAME: &str = "error_store_warn_test_nexus";
+
+static DISKNAME1: &str = "/tmp/error_store_warn1.img";
+static BDEVNAME1: &str = "aio:///tmp/error_store_warn1.img?blk_size=512";
+
+static DISKNAME2: &str = "/tmp/error_store_warn2.img";
+
+static ERROR_DEVICE: &str = "error_store_warn_device";
+static EE_ERROR_DEVICE: &str = "EE_error_store_warn_device"; // The prefix is added by the vbdev_error module
+static BDEV_EE_ERROR_DEVICE: &str = "bdev:///EE_error_store_warn_device";
+
+static YAML_CONFIG_FILE: &str = "/tmp/error_store_warn_test_nexus.yaml";
+
+#[test]
+fn nexus_child_error_store_warn_test() {
+    common::truncate_file(DISKNAME1, 64 * 1024);
+    common::truncate_file(DISKNAME2, 64 * 1024);
+
+    let mut config = Config::default();
+    config.err_store_opts.enable_err_store = true;
+    config.err_store_opts.err_store_size = 256;
+    config.err_store_opts.action = ActionType::Warn;
+    config.err_store_opts.max_errors = 64;
+    config.err_store_opts.max_read_errors = Some(1);
+    config.write(YAML_CONFIG_FILE).unwrap();
+
+    test_init!(YAML_CONFIG_FILE);
+
+    let mut subscription = Reactor::block_on(async {
+        create_error_bdev(ERROR_DEVICE, DISKNAME2);
+        let ch = vec![BDEV_EE_ERROR_DEVICE.to_string(), BDEVNAME1.to_string()];
+        nexus_create(NEXUS_NAME, 64 * 1024 * 1024, None, &ch)
+            .await
+            .unwrap();
+        inject_error(
+            EE_ERROR_DEVICE,
+            SPDK_BDEV_IO_TYPE_READ,
+            VBDEV_IO_FAILURE,
+            4,
+        );
+        events::subscribe(0, 0).unwrap()
+    })
+    .unwrap();
+
+    // the second read error exceeds the limit, only that is warned about
+    for errors in 1 ..= 4 {
+        read_until_errors(errors);
+        let expected = if errors == 2 {
+            vec![EventKind::ChildErrors {
+                nexus: NEXUS_NAME.into(),
+                child: BDEV_EE_ERROR_DEVICE.into(),
+                action: ActionType::Warn,
+                reason: "2 read errors exceed the limit of 1".into(),
+            }]
+        } else {
+            vec![]
+        };
+        assert_eq!(child_errors_events(&mut subscription), expected);
+    }
+
+    // the child is left alone
+    let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+    assert_eq!(nexus.status(), NexusStatus::Online);
+    assert_eq!(
+        nexus
+            .children
+            .iter()
+            .find(|c| c.name == BDEV_EE_ERROR_DEVICE)
+            .unwrap()
+            .status(),
+        ChildStatus::Online
+    );
+
+    Reactor::block_on(async {
+        nexus_lookup(NEXUS_NAME).unwrap().destroy().await.unwrap();
+    });
+
+    mayastor_env_stop(0);
+
+    common::delete_file(&[
+        DISKNAME1.to_string(),
+        DISKNAME2.to_string(),
+        YAML_CONFIG_FILE.to_string(),
+    ]);
+}
+
+// read from the nexus until the child has the given number of read errors,
+// the reads alternate between the children and are retried on the healthy one
+fn read_until_errors(errors: u32) {
+    for _ in 0 .. 8 {
+        if child_errors() == errors {
+            return;
+        }
+        assert!(Reactor::block_on(read_nexus()).unwrap());
+        reactor_run_millis(1);
+    }
+    assert_eq!(child_errors(), errors);
+}
+
+fn child_errors() -> u32 {
+    nexus_lookup(NEXUS_NAME)
+        .unwrap()
+        .error_record_query(
+            BDEV_EE_ERROR_DEVICE,
+            NexusErrStore::READ_FLAG,
+            NexusErrStore::IO_FAILED_FLAG,
+            None,
+            QueryType::Total,
+        )
+        .unwrap()
+        .unwrap()
+}
+
+// the ChildErrors events published since the last call
+fn child_errors_events(subscription: &mut Subscription) -> Vec<EventKind> {
+    let mut kinds = Vec::new();
+    while let Some(event) = subscription.next().now_or_never() {
+        let kind = event.unwrap().kind;
+        if let EventKind::ChildErrors {
+            ..
+        } = kind
+        {
+            kinds.push(kind);
+        }
+    }
+    kinds
+}
+
+async fn read_nexus() -> bool {
+    let bdev =
+        Bdev::lookup_by_name(NEXUS_NAME).expect("failed to lookup nexus");
+    let d = bdev
+        .open(true)
+        .expect("failed open bdev")
+        .into_handle()
+        .unwrap();
+    let mut buf = d.dma_malloc(512).expect("failed to allocate buffer");
+
+    d.read_at(0, &mut buf).await.is_ok()
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
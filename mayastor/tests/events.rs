@@ -0,0 +1,150 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, ChildStatus, NexusStatus},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    events::{self, EventKind, Lifecycle},
+    pool::{create_pool, Pool},
+    replica::Replica,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static NEXUS_NAME: &str = "events_nexus";
+static UUID: &str = "00000000-76b6-4fcf-864d-1027d4038756";
+
+#[test]
+fn events() {
+    common::mayastor_test_init();
+    let rc = MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                let mut subscription = events::subscribe(0, 0).unwrap();
+
+                create_pool(CreatePoolRequest {
+                    name: "events_pool".into(),
+                    disks: vec!["malloc:///malloc0?size_mb=64".into()],
+                    block_size: 0,
+                    io_if: 0,
+                    layout: 0,
+                })
+                .await
+                .unwrap();
+                let first = subscription.next().await.unwrap();
+                assert_eq!(
+                    first.kind,
+                    EventKind::Pool {
+                        name: "events_pool".into(),
+                        change: Lifecycle::Created,
+                    }
+                );
+
+                Replica::create(UUID, "events_pool", 16 * 1024 * 1024, false)
+                    .await
+                    .unwrap();
+                let event = subscription.next().await.unwrap();
+                assert_eq!(event.sequence, first.sequence + 1);
+                assert_eq!(
+                    event.kind,
+                    EventKind::Replica {
+                        uuid: UUID.into(),
+                        pool: "events_pool".into(),
+                        change: Lifecycle::Created,
+                    }
+                );
+
+                // the status of the children precedes that of the nexus
+                let children = vec![
+                    "malloc:///malloc1?size_mb=64".to_string(),
+                    "malloc:///malloc2?size_mb=64".to_string(),
+                ];
+                nexus_create(NEXUS_NAME, 32 * 1024 * 1024, None, &children)
+                    .await
+                    .unwrap();
+                for child in &children {
+                    assert_eq!(
+                        subscription.next().await.unwrap().kind,
+                        EventKind::ChildStatus {
+                            nexus: NEXUS_NAME.into(),
+                            child: child.clone(),
+                            status: ChildStatus::Online,
+                            reason: None,
+                        }
+                    );
+                }
+                assert_eq!(
+                    subscription.next().await.unwrap().kind,
+                    EventKind::NexusStatus {
+                        nexus: NEXUS_NAME.into(),
+                        status: NexusStatus::Online,
+                    }
+                );
+
+                // offlining a child degrades the nexus
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                nexus.offline_child(&children[0]).await.unwrap();
+                assert_eq!(
+                    subscription.next().await.unwrap().kind,
+                    EventKind::ChildStatus {
+                        nexus: NEXUS_NAME.into(),
+                        child: children[0].clone(),
+                        status: ChildStatus::Degraded,
+                        reason: Some("ChildOffline".into()),
+                    }
+                );
+                assert_eq!(
+                    subscription.next().await.unwrap().kind,
+                    EventKind::NexusStatus {
+                        nexus: NEXUS_NAME.into(),
+                        status: NexusStatus::Degraded,
+                    }
+                );
+                nexus.destroy().await.unwrap();
+
+                // a subscriber can resume after the last event it has seen
+                assert_eq!(first.instance, events::instance());
+                let mut resumed =
+                    events::subscribe(first.instance, first.sequence).unwrap();
+                let event = resumed.next().await.unwrap();
+                assert_eq!(event.sequence, first.sequence + 1);
+
+                // but not from the future
+                assert!(events::subscribe(first.instance, u64::MAX).is_err());
+
+                // nor after an event of another instance of mayastor
+                assert!(events::subscribe(
+                    first.instance.wrapping_add(1),
+                    first.sequence
+                )
+                .is_err());
+
+                Replica::lookup(UUID).unwrap().destroy().await.unwrap();
+                Pool::lookup("events_pool")
+                    .unwrap()
+                    .destroy()
+                    .await
+                    .unwrap();
+                let kinds = vec![
+                    subscription.next().await.unwrap().kind,
+                    subscription.next().await.unwrap().kind,
+                ];
+                assert_eq!(
+                    kinds,
+                    vec![
+                        EventKind::Replica {
+                            uuid: UUID.into(),
+                            pool: "events_pool".into(),
+                            change: Lifecycle::Destroyed,
+                        },
+                        EventKind::Pool {
+                            name: "events_pool".into(),
+                            change: Lifecycle::Destroyed,
+                        },
+                    ]
+                );
+            });
+
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+    assert_eq!(rc, 0);
+}
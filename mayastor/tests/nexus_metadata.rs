@@ -4,10 +4,12 @@ use mayastor::{
     bdev::{
         nexus_create,
         nexus_lookup,
+        ChildStatus,
         NexusConfig,
-        NexusConfigVersion1,
-        NexusConfigVersion2,
-        NexusConfigVersion3,
+        NexusDirtyLog,
+        NexusShareProtocol,
+        NexusTopology,
+        NexusTopologyChild,
     },
     core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
 };
@@ -58,37 +60,33 @@ async fn read_write_metadata() {
     let now = SystemTime::now();
     let mut data: Vec<NexusConfig> = Vec::new();
 
-    data.push(NexusConfig::Version1(NexusConfigVersion1 {
-        name: "Hello".to_string(),
-        tags: String::from("How now brown cow")
-            .split_whitespace()
-            .map(String::from)
-            .collect(),
-        revision: 38,
-        checksum: 0x3c2d_38ab,
-        data: String::from("Hello from v1"),
+    data.push(NexusConfig::DirtyLog(NexusDirtyLog {
+        child: BDEVNAME.to_string(),
+        clean: false,
+        num_blocks: 131_072,
+        region_blks: 2048,
+        bitmap: vec![0x3c2d_38ab],
     }));
 
-    data.push(NexusConfig::Version2(NexusConfigVersion2 {
-        name: "Hello".to_string(),
-        tags: String::from("How now brown cow")
-            .split_whitespace()
-            .map(String::from)
-            .collect(),
-        revision: 40,
-        checksum: 0x3c2e_40ab,
-        data: String::from("Hello from v2"),
-        count: 100,
+    data.push(NexusConfig::DirtyLog(NexusDirtyLog {
+        child: BDEVNAME.to_string(),
+        clean: true,
+        num_blocks: 131_072,
+        region_blks: 2048,
+        bitmap: vec![0x3c2e_40ab],
     }));
 
-    data.push(NexusConfig::Version3(NexusConfigVersion3 {
-        name: "Hello".to_string(),
-        revision: 42,
-        checksum: 0x3c2f_42ab,
-        data: String::from("Hello from v3")
-            .split_whitespace()
-            .map(String::from)
-            .collect(),
+    data.push(NexusConfig::Topology(NexusTopology {
+        uuid: "metadata_nexus".to_string(),
+        size: 512 * 131_072,
+        generation: 42,
+        children: vec![NexusTopologyChild {
+            uri: BDEVNAME.to_string(),
+            status: ChildStatus::Online,
+            generation: 42,
+        }],
+        share: NexusShareProtocol::None,
+        created: now,
     }));
 
     // create an index and append two objects
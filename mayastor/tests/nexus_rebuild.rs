@@ -1,13 +1,19 @@
-use std::sync::Mutex;
+use std::{sync::Mutex, time::Duration};
 
 use crossbeam::channel::unbounded;
 use once_cell::sync::Lazy;
 
 use common::error_bdev;
 use mayastor::{
-    bdev::{nexus_lookup, ChildStatus, VerboseError},
+    bdev::{nexus_lookup, ChildStatus, NexusStatus, VerboseError},
     core::{MayastorCliArgs, MayastorEnvironment, Mthread, Reactor},
-    rebuild::{RebuildJob, RebuildState, SEGMENT_SIZE},
+    rebuild::{
+        ClientOperations,
+        RebuildJob,
+        RebuildLimits,
+        RebuildState,
+        SEGMENT_SIZE,
+    },
 };
 use rpc::mayastor::ShareProtocolNexus;
 
@@ -267,6 +273,91 @@ fn rebuild_with_load() {
     test_fini();
 }
 
+#[test]
+// only the regions written to while the child was offline are rebuilt
+fn rebuild_offline_online() {
+    test_ini("rebuild_offline_online");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 2, false).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        let nexus_device =
+            common::device_path_from_uri(nexus.get_share_uri().unwrap());
+
+        nexus.offline_child(&get_dev(1)).await.unwrap();
+
+        let (s, r) = unbounded::<String>();
+        std::thread::spawn(move || {
+            s.send(common::dd_urandom_file_size(&nexus_device, 1024 * 1024))
+        });
+        reactor_poll!(r);
+
+        nexus.online_child(&get_dev(1)).await.unwrap();
+        nexus_test_child(1).await;
+        // allow the nexus futures to run
+        reactor_poll!(10);
+        assert_eq!(nexus.children[1].status(), ChildStatus::Online);
+
+        // only the regions written while offline have been copied
+        let history = nexus.get_rebuild_history(Some(&get_dev(1))).rebuilds;
+        let stats = history[0].stats.as_ref().unwrap();
+        assert_eq!(stats.progress, 100);
+        assert_eq!(stats.blocks_recovered, stats.blocks_total);
+        assert_eq!(stats.blocks_transferred, stats.blocks_recovered);
+        assert!(stats.blocks_transferred >= 1024 * 1024 / 512);
+        assert!(stats.blocks_transferred < nexus.size() / 512);
+
+        nexus.destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+// a child which is offline when the nexus is destroyed is rebuilt when the
+// nexus is created again, from the dirty log sealed at the destroy
+fn rebuild_offline_destroy() {
+    test_ini("rebuild_offline_destroy");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 2, false).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        let nexus_device =
+            common::device_path_from_uri(nexus.get_share_uri().unwrap());
+
+        nexus.offline_child(&get_dev(1)).await.unwrap();
+
+        let (s, r) = unbounded::<String>();
+        std::thread::spawn(move || {
+            s.send(common::dd_urandom_file_size(&nexus_device, 1024 * 1024))
+        });
+        reactor_poll!(r);
+        nexus.destroy().await.unwrap();
+
+        mayastor::bdev::nexus_create(
+            nexus_name(),
+            NEXUS_SIZE,
+            None,
+            &[get_dev(0), get_dev(1)],
+        )
+        .await
+        .unwrap();
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        assert_eq!(nexus.children[1].status(), ChildStatus::Degraded);
+
+        // only the regions written to are rebuilt
+        let stats =
+            RebuildJob::lookup(&get_dev(1)).unwrap().as_client().stats();
+        assert!(stats.blocks_total > 0);
+        assert!(stats.blocks_total < nexus.size() / 512);
+        nexus_test_child(1).await;
+
+        nexus.destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
 async fn nexus_create(size: u64, children: u64, fill_random: bool) {
     let mut ch = Vec::new();
     for i in 0 .. children {
@@ -346,6 +437,34 @@ async fn nexus_test_child(child: u64) {
     reactor_poll!(r);
 }
 
+// the rebuild is throttled to 1MiB/s with a single copy task, at which the
+// copy of the nexus takes about 5 seconds
+const THROTTLED: RebuildLimits = RebuildLimits {
+    bandwidth_mbps: 1,
+    concurrency: 1,
+};
+
+// starts a throttled rebuild of the child, and of the `more` children with
+// the same job, and lets it run for the given time
+async fn start_throttled_rebuild(
+    child: u64,
+    more: &[u64],
+    verify: bool,
+    run: Duration,
+) {
+    let nexus = nexus_lookup(nexus_name()).unwrap();
+    let more = more.iter().map(|c| get_dev(*c)).collect::<Vec<_>>();
+    let _ = nexus
+        .start_rebuild_many(&get_dev(child), &more, THROTTLED, verify)
+        .await
+        .unwrap();
+
+    let start = std::time::Instant::now();
+    while start.elapsed() < run {
+        reactor_poll!(1);
+    }
+}
+
 #[test]
 // test rebuild with different combinations of sizes for src and dst children
 fn rebuild_sizes() {
@@ -559,6 +678,284 @@ fn rebuild_operations() {
     test_fini();
 }
 
+#[test]
+// tests the bandwidth and concurrency limits of the rebuild
+fn rebuild_limits() {
+    test_ini("rebuild_limits");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, false).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), true).await.unwrap();
+
+        let limits = RebuildLimits {
+            bandwidth_mbps: 1,
+            concurrency: 0,
+        };
+        nexus
+            .start_rebuild_with_limits(&get_dev(1), limits)
+            .await
+            .expect_err("at least one segment has to be copied at a time");
+
+        start_throttled_rebuild(1, &[], false, Duration::from_millis(500))
+            .await;
+        assert_eq!(
+            RebuildJob::lookup(&get_dev(1)).unwrap().limits(),
+            THROTTLED
+        );
+        let progress = nexus.get_rebuild_progress(&get_dev(1)).unwrap();
+        assert!(progress.progress < 50);
+
+        let limits = RebuildLimits {
+            bandwidth_mbps: 1,
+            concurrency: 2,
+        };
+        nexus.set_rebuild_limits(&get_dev(1), limits).await.unwrap();
+        assert_eq!(RebuildJob::lookup(&get_dev(1)).unwrap().limits(), limits);
+
+        let limits = RebuildLimits {
+            bandwidth_mbps: 0,
+            concurrency: 1000,
+        };
+        nexus
+            .set_rebuild_limits(&get_dev(1), limits)
+            .await
+            .expect_err("too many segments copied at a time");
+
+        // without the limits the rebuild completes in no time
+        nexus
+            .set_rebuild_limits(&get_dev(1), RebuildLimits::default())
+            .await
+            .unwrap();
+        nexus_test_child(1).await;
+
+        nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+// the statistics of a running rebuild and the history of the ended ones
+fn rebuild_stats() {
+    test_ini("rebuild_stats");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, false).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), true).await.unwrap();
+        nexus
+            .get_rebuild_stats(&get_dev(1))
+            .expect_err("no rebuild running");
+
+        start_throttled_rebuild(1, &[], false, Duration::from_secs(1)).await;
+        let reply = nexus.get_rebuild_stats(&get_dev(1)).unwrap();
+        assert_eq!(reply.state, "running");
+        assert_eq!(reply.source, get_dev(0));
+        let stats = reply.stats.unwrap();
+        assert!(stats.start_time > 0);
+        assert!(stats.elapsed_ms >= 1000);
+        assert!(stats.blocks_transferred > 0);
+        assert!(stats.blocks_recovered < stats.blocks_total);
+        assert_eq!(stats.tasks_total, 1);
+        assert!(stats.throughput > 0 && stats.throughput <= 2 * 1024 * 1024);
+        assert!(stats.remaining_ms > 0);
+
+        nexus.stop_rebuild(&get_dev(1)).await.unwrap();
+        common::wait_for_rebuild(
+            get_dev(1),
+            RebuildState::Stopped,
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap();
+        reactor_poll!(10);
+
+        let _ = nexus.start_rebuild(&get_dev(1)).await.unwrap();
+        nexus_test_child(1).await;
+        reactor_poll!(10);
+
+        let history = nexus.get_rebuild_history(None).rebuilds;
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0].uri, get_dev(1));
+        assert_eq!(history[0].state, "completed");
+        assert!(history[0].error.is_empty());
+        let stats = history[0].stats.as_ref().unwrap();
+        assert_eq!(stats.blocks_recovered, stats.blocks_total);
+        assert_eq!(history[1].state, "stopped");
+        assert!(history[1].end_time <= history[0].end_time);
+        assert!(nexus
+            .get_rebuild_history(Some(&get_dev(0)))
+            .rebuilds
+            .is_empty());
+
+        nexus.destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+fn rebuild_resume() {
+    test_ini("rebuild_resume");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, true).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), true).await.unwrap();
+
+        start_throttled_rebuild(1, &[], false, Duration::from_secs(2)).await;
+        let progress = nexus.get_rebuild_progress(&get_dev(1)).unwrap();
+        assert!(progress.progress > 0 && progress.progress < 100);
+
+        // the checkpoint outlives the nexus
+        nexus.destroy().await.unwrap();
+        assert_eq!(RebuildJob::count(), 0);
+
+        mayastor::bdev::nexus_create(
+            nexus_name(),
+            NEXUS_SIZE,
+            None,
+            &[get_dev(0), get_dev(1)],
+        )
+        .await
+        .unwrap();
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        assert_eq!(nexus.children[1].status(), ChildStatus::Degraded);
+
+        // the rebuild picks up where it was left off
+        let resumed = nexus.get_rebuild_progress(&get_dev(1)).unwrap();
+        assert!(resumed.progress >= progress.progress);
+        nexus_test_child(1).await;
+
+        // and once done there is nothing left to resume
+        nexus.destroy().await.unwrap();
+        mayastor::bdev::nexus_create(
+            nexus_name(),
+            NEXUS_SIZE,
+            None,
+            &[get_dev(0), get_dev(1)],
+        )
+        .await
+        .unwrap();
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        assert_eq!(RebuildJob::count(), 0);
+        assert_eq!(nexus.status(), NexusStatus::Online);
+
+        nexus.destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+// a rebuild of which the source is gone when the nexus is created again is
+// started all over from one of the remaining healthy children
+fn rebuild_resume_without_source() {
+    test_ini("rebuild_resume_without_source");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 2, true).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(2), true).await.unwrap();
+
+        start_throttled_rebuild(2, &[], false, Duration::from_secs(2)).await;
+        let job = RebuildJob::lookup(&get_dev(2)).unwrap();
+        assert_eq!(job.source, get_dev(0));
+        nexus.destroy().await.unwrap();
+
+        mayastor::bdev::nexus_create(
+            nexus_name(),
+            NEXUS_SIZE,
+            None,
+            &[get_dev(1), get_dev(2)],
+        )
+        .await
+        .unwrap();
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        assert_eq!(nexus.children[1].status(), ChildStatus::Degraded);
+
+        let job = RebuildJob::lookup(&get_dev(2)).unwrap();
+        assert_eq!(job.source, get_dev(1));
+        nexus_test_child(2).await;
+        assert_eq!(nexus.status(), NexusStatus::Online);
+
+        nexus.destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+// the verification finds and fixes a segment of the destination which
+// differs from the source
+fn rebuild_verify() {
+    test_ini("rebuild_verify");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, true).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), true).await.unwrap();
+
+        start_throttled_rebuild(1, &[], true, Duration::default()).await;
+
+        // at 1MiB/s both the copy and the verification take about 5 seconds
+        let start = std::time::Instant::now();
+        while !nexus.get_rebuild_progress(&get_dev(1)).unwrap().verifying {
+            assert!(start.elapsed() < std::time::Duration::from_secs(20));
+            reactor_poll!(1);
+        }
+        nexus.pause_rebuild(&get_dev(1)).await.unwrap();
+        common::wait_for_rebuild(
+            get_dev(1),
+            RebuildState::Paused,
+            std::time::Duration::from_secs(1),
+        )
+        .unwrap();
+
+        // corrupt a segment of the destination still to be verified
+        let verified = RebuildJob::lookup(&get_dev(1))
+            .unwrap()
+            .as_client()
+            .stats()
+            .segments_verified;
+        let offset =
+            nexus.data_ent_offset * 512 + (verified + 4) * SEGMENT_SIZE;
+        assert!(offset < nexus.data_ent_offset * 512 + nexus.size());
+        {
+            use std::os::unix::fs::FileExt;
+            let file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(get_disk(1))
+                .unwrap();
+            file.write_all_at(&[0xa5; 512], offset).unwrap();
+            file.sync_all().unwrap();
+        }
+
+        nexus.resume_rebuild(&get_dev(1)).await.unwrap();
+        let start = std::time::Instant::now();
+        loop {
+            let progress = nexus.get_rebuild_progress(&get_dev(1)).unwrap();
+            if progress.mismatches_fixed > 0 {
+                assert_eq!(progress.mismatches_found, 1);
+                assert_eq!(progress.mismatches_fixed, 1);
+                break;
+            }
+            assert!(start.elapsed() < std::time::Duration::from_secs(10));
+            reactor_poll!(1);
+        }
+
+        nexus
+            .set_rebuild_limits(&get_dev(1), RebuildLimits::default())
+            .await
+            .unwrap();
+        nexus_test_child(1).await;
+
+        nexus.destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
 #[test]
 // rebuilds N children at the same time
 // creates the nexus with 1 healthy and then adds N children which
@@ -612,6 +1009,94 @@ fn rebuild_multiple() {
     test_fini();
 }
 
+#[test]
+// rebuilds 2 children with a single job, reading the source once
+fn rebuild_many() {
+    test_ini("rebuild_many");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, true).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), true).await.unwrap();
+        nexus.add_child(&get_dev(2), true).await.unwrap();
+
+        nexus
+            .start_rebuild_many(
+                &get_dev(1),
+                &[get_dev(0)],
+                RebuildLimits::default(),
+                false,
+            )
+            .await
+            .expect_err("the source cannot be rebuilt as well");
+        assert_eq!(RebuildJob::count(), 0);
+
+        let _ = nexus
+            .start_rebuild_many(
+                &get_dev(1),
+                &[get_dev(2)],
+                RebuildLimits::default(),
+                false,
+            )
+            .await
+            .unwrap();
+        assert_eq!(RebuildJob::count(), 1);
+        assert_eq!(
+            RebuildJob::lookup(&get_dev(2)).unwrap().destinations(),
+            vec![get_dev(1), get_dev(2)]
+        );
+        nexus
+            .start_rebuild(&get_dev(2))
+            .await
+            .expect_err("the child is already being rebuilt");
+
+        nexus_test_child(1).await;
+        nexus_test_child(2).await;
+        reactor_poll!(10);
+        assert_eq!(nexus.children[1].status(), ChildStatus::Online);
+        assert_eq!(nexus.children[2].status(), ChildStatus::Online);
+
+        nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+// removing one of the children rebuilt by a single job leaves the other one
+// with a job of its own
+fn rebuild_many_remove() {
+    test_ini("rebuild_many_remove");
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, false).await;
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), true).await.unwrap();
+        nexus.add_child(&get_dev(2), true).await.unwrap();
+
+        start_throttled_rebuild(1, &[2], false, Duration::default()).await;
+        reactor_poll!(10);
+
+        nexus.remove_child(&get_dev(1)).await.unwrap();
+        reactor_poll!(10);
+        assert_eq!(RebuildJob::count(), 1);
+        assert_eq!(
+            RebuildJob::lookup(&get_dev(2)).unwrap().destinations(),
+            vec![get_dev(2)]
+        );
+
+        nexus
+            .set_rebuild_limits(&get_dev(2), RebuildLimits::default())
+            .await
+            .unwrap();
+        nexus_test_child(2).await;
+
+        nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
 #[test]
 fn rebuild_fault_src() {
     test_ini("rebuild_fault_src");
@@ -646,6 +1131,92 @@ fn rebuild_fault_src() {
     test_fini();
 }
 
+#[test]
+fn rebuild_retry_segment() {
+    test_ini("rebuild_retry_segment");
+    set_err_dev(0);
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, false).await;
+
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), false).await.unwrap();
+
+        // a few errors are overcome by retrying the same source
+        error_bdev::inject_error(
+            &get_err_dev(0),
+            error_bdev::SPDK_BDEV_IO_TYPE_READ,
+            error_bdev::VBDEV_IO_FAILURE,
+            2,
+        );
+
+        common::wait_for_rebuild(
+            get_dev(1),
+            RebuildState::Completed,
+            std::time::Duration::from_secs(20),
+        )
+        .unwrap();
+        reactor_poll!(10);
+        assert_eq!(nexus.children[1].status(), ChildStatus::Online);
+
+        // the failed reads have been retried rather than failing the rebuild
+        let history = nexus.get_rebuild_history(Some(&get_dev(1))).rebuilds;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].state, "completed");
+        assert!(history[0].stats.as_ref().unwrap().segments_retried > 0);
+
+        nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+fn rebuild_switch_source() {
+    test_ini("rebuild_switch_source");
+    set_err_dev(0);
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 2, false).await;
+
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(2), false).await.unwrap();
+        assert_eq!(RebuildJob::lookup(&get_dev(2)).unwrap().source, get_dev(0));
+
+        // the first source keeps on failing so the other one takes over
+        error_bdev::inject_error(
+            &get_err_dev(0),
+            error_bdev::SPDK_BDEV_IO_TYPE_READ,
+            error_bdev::VBDEV_IO_FAILURE,
+            88,
+        );
+
+        common::wait_for_rebuild(
+            get_dev(2),
+            RebuildState::Completed,
+            std::time::Duration::from_secs(20),
+        )
+        .unwrap();
+        reactor_poll!(10);
+        assert_eq!(nexus.children[2].status(), ChildStatus::Online);
+
+        let (s, r) = unbounded::<String>();
+        std::thread::spawn(move || {
+            s.send(common::compare_devices(
+                &get_disk(1),
+                &get_disk(2),
+                NEXUS_SIZE,
+                true,
+            ))
+        });
+        reactor_poll!(r);
+
+        nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
 #[test]
 fn rebuild_fault_dst() {
     test_ini("rebuild_fault_dst");
@@ -674,6 +1245,78 @@ fn rebuild_fault_dst() {
         reactor_poll!(10);
         assert_eq!(nexus.children[1].status(), ChildStatus::Faulted);
 
+        // the failure is still known once the job is gone
+        let history = nexus.get_rebuild_history(Some(&get_dev(1))).rebuilds;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].state, "failed");
+        assert!(!history[0].error.is_empty());
+
+        nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
+    });
+
+    test_fini();
+}
+
+#[test]
+// a child which fails to be written to is given up on and faulted, while
+// the others rebuilt by the same job carry on
+fn rebuild_many_fault_dst() {
+    test_ini("rebuild_many_fault_dst");
+    set_err_dev(2);
+
+    Reactor::block_on(async {
+        nexus_create(NEXUS_SIZE, 1, false).await;
+
+        let nexus = nexus_lookup(nexus_name()).unwrap();
+        nexus.add_child(&get_dev(1), true).await.unwrap();
+        nexus.add_child(&get_dev(2), true).await.unwrap();
+
+        error_bdev::inject_error(
+            &get_err_dev(2),
+            error_bdev::SPDK_BDEV_IO_TYPE_WRITE,
+            error_bdev::VBDEV_IO_FAILURE,
+            88,
+        );
+
+        let _ = nexus
+            .start_rebuild_many(
+                &get_dev(1),
+                &[get_dev(2)],
+                RebuildLimits::default(),
+                false,
+            )
+            .await
+            .unwrap();
+        common::wait_for_rebuild(
+            get_dev(1),
+            RebuildState::Completed,
+            std::time::Duration::from_secs(20),
+        )
+        .unwrap();
+        // allow the nexus futures to run
+        reactor_poll!(10);
+        assert_eq!(nexus.children[1].status(), ChildStatus::Online);
+        assert_eq!(nexus.children[2].status(), ChildStatus::Faulted);
+
+        let history = nexus.get_rebuild_history(Some(&get_dev(1))).rebuilds;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].state, "completed");
+        let history = nexus.get_rebuild_history(Some(&get_dev(2))).rebuilds;
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].state, "failed");
+        assert!(!history[0].error.is_empty());
+
+        let (s, r) = unbounded::<String>();
+        std::thread::spawn(move || {
+            s.send(common::compare_devices(
+                &get_disk(0),
+                &get_disk(1),
+                NEXUS_SIZE,
+                true,
+            ))
+        });
+        reactor_poll!(r);
+
         nexus_lookup(nexus_name()).unwrap().destroy().await.unwrap();
     });
 
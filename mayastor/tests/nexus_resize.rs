@@ -0,0 +1,152 @@
+use std::os::unix::fs::FileExt;
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        BdevHandle,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+};
+
+static NEXUS_NAME: &str = "resize_nexus";
+
+static FILE_SIZE: u64 = 64 * 1024 * 1024; // 64MiB
+
+static DISKNAME1: &str = "/tmp/resize1.img";
+static BDEVNAME1: &str = "aio:///tmp/resize1.img?blk_size=512";
+
+static DISKNAME2: &str = "/tmp/resize2.img";
+static BDEVNAME2: &str = "aio:///tmp/resize2.img?blk_size=512";
+
+pub mod common;
+
+fn test_start() {
+    common::mayastor_test_init();
+    common::delete_file(&[DISKNAME1.into(), DISKNAME2.into()]);
+    common::truncate_file_bytes(DISKNAME1, FILE_SIZE);
+    common::truncate_file_bytes(DISKNAME2, FILE_SIZE);
+}
+
+fn test_finish() {
+    let disks = [DISKNAME1.into(), DISKNAME2.into()];
+    common::delete_file(&disks);
+}
+
+// the location of the backup GPT header according to the primary one, which
+// is at the end of the disk the label has been written for
+fn backup_gpt_lba(disk: &str) -> u64 {
+    let file = std::fs::File::open(disk).unwrap();
+    let mut header = [0u8; 512];
+    file.read_exact_at(&mut header, 512).unwrap();
+    assert_eq!(&header[.. 8], b"EFI PART");
+
+    let mut alternate = [0u8; 8];
+    alternate.copy_from_slice(&header[32 .. 40]);
+    u64::from_le_bytes(alternate)
+}
+
+#[test]
+fn nexus_resize() {
+    test_start();
+    let rc = MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                let children =
+                    vec![BDEVNAME1.to_string(), BDEVNAME2.to_string()];
+                nexus_create(NEXUS_NAME, 32 * 1024 * 1024, None, &children)
+                    .await
+                    .expect("Failed to create nexus");
+            });
+
+            // growing within the data partition of the children
+            Reactor::block_on(async {
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                nexus
+                    .resize(48 * 1024 * 1024)
+                    .await
+                    .expect("Failed to grow nexus");
+                assert_eq!(nexus.size(), 48 * 1024 * 1024);
+
+                // the size is rounded down to whole blocks
+                nexus
+                    .resize(52 * 1024 * 1024 + 100)
+                    .await
+                    .expect("Failed to grow nexus");
+                assert_eq!(nexus.size(), 52 * 1024 * 1024);
+
+                // the new size is part of the persisted topology
+                for child in &nexus.children {
+                    let topology =
+                        child.read_topology().await.unwrap().unwrap();
+                    assert_eq!(topology.size, 52 * 1024 * 1024);
+                }
+            });
+
+            // a nexus cannot shrink
+            Reactor::block_on(async {
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                nexus
+                    .resize(32 * 1024 * 1024)
+                    .await
+                    .expect_err("Nexus should not shrink");
+                assert_eq!(nexus.size(), 52 * 1024 * 1024);
+            });
+
+            // nor grow beyond its children
+            Reactor::block_on(async {
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                nexus
+                    .resize(FILE_SIZE)
+                    .await
+                    .expect_err("Nexus should not outgrow its children");
+                assert_eq!(nexus.size(), 52 * 1024 * 1024);
+            });
+
+            // once the children have been grown, the label is grown along
+            // with the nexus
+            Reactor::block_on(async {
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                nexus.destroy().await.unwrap();
+
+                common::truncate_file_bytes(DISKNAME1, 2 * FILE_SIZE);
+                common::truncate_file_bytes(DISKNAME2, 2 * FILE_SIZE);
+                let children =
+                    vec![BDEVNAME1.to_string(), BDEVNAME2.to_string()];
+                nexus_create(NEXUS_NAME, 48 * 1024 * 1024, None, &children)
+                    .await
+                    .expect("Failed to create nexus");
+                for disk in &[DISKNAME1, DISKNAME2] {
+                    assert_eq!(backup_gpt_lba(disk), FILE_SIZE / 512 - 1);
+                }
+
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                nexus
+                    .resize(FILE_SIZE)
+                    .await
+                    .expect("Failed to grow nexus past the old label");
+                assert_eq!(nexus.size(), FILE_SIZE);
+                for disk in &[DISKNAME1, DISKNAME2] {
+                    assert_eq!(backup_gpt_lba(disk), 2 * FILE_SIZE / 512 - 1);
+                }
+
+                // the frontend has been told about the new size
+                let bdev = Bdev::lookup_by_name(NEXUS_NAME).unwrap();
+                assert_eq!(bdev.size_in_bytes(), FILE_SIZE);
+                let handle = BdevHandle::open(NEXUS_NAME, true, false).unwrap();
+                let buf = handle.dma_malloc(512).unwrap();
+                handle.write_at(FILE_SIZE - 512, &buf).await.unwrap();
+                drop(handle);
+
+                nexus.destroy().await.unwrap();
+            });
+
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+    assert_eq!(rc, 0);
+    test_finish();
+}
@@ -0,0 +1,174 @@
+use std::{os::unix::fs::FileExt, time::Duration};
+
+use crossbeam::channel::unbounded;
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, Nexus},
+    core::{MayastorCliArgs, MayastorEnvironment, Reactor},
+    scrub::{ScrubJob, ScrubState},
+};
+
+pub mod common;
+
+static NEXUS_SIZE: u64 = 5 * 1024 * 1024; // 5MiB
+
+// approximate on-disk metadata that will be written to the child by the nexus
+const META_SIZE: u64 = 5 * 1024 * 1024; // 5MiB
+
+fn get_disk(nexus: &str, number: u64) -> String {
+    format!("/tmp/{}-disk{}.img", nexus, number)
+}
+fn get_dev(nexus: &str, number: u64) -> String {
+    format!("aio://{}?blk_size=512", get_disk(nexus, number))
+}
+
+fn test_ini(nexus: &str) {
+    test_init!();
+    for i in 0 .. 3 {
+        common::delete_file(&[get_disk(nexus, i)]);
+        common::truncate_file_bytes(
+            &get_disk(nexus, i),
+            NEXUS_SIZE + META_SIZE,
+        );
+    }
+}
+fn test_fini(nexus: &str) {
+    for i in 0 .. 3 {
+        common::delete_file(&[get_disk(nexus, i)]);
+    }
+}
+
+async fn create(nexus: &str, children: u64) -> &'static mut Nexus {
+    let ch = (0 .. children)
+        .map(|i| get_dev(nexus, i))
+        .collect::<Vec<_>>();
+    nexus_create(nexus, NEXUS_SIZE, None, &ch).await.unwrap();
+    nexus_lookup(nexus).unwrap()
+}
+
+// write a block of garbage to the data partition of the child behind the
+// back of the nexus
+fn corrupt(nexus: &Nexus, name: &str, number: u64, offset: u64) {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(get_disk(name, number))
+        .unwrap();
+    file.write_all_at(&[0xa5; 512], (nexus.data_ent_offset + offset) * 512)
+        .unwrap();
+    file.sync_all().unwrap();
+}
+
+fn wait_for_scrub(nexus: &str) -> ScrubState {
+    let start = std::time::Instant::now();
+    loop {
+        let state = ScrubJob::lookup(nexus).unwrap().state();
+        if state.done() {
+            return state;
+        }
+        assert!(start.elapsed() < Duration::from_secs(10));
+        reactor_poll!(1);
+    }
+}
+
+#[test]
+// scrub reports the children which differ and repairs them from the
+// authoritative child when given one
+fn scrub_repair() {
+    let name = "scrub_repair";
+    test_ini(name);
+
+    Reactor::block_on(async move {
+        let nexus = create(name, 2).await;
+        corrupt(nexus, name, 1, 1024);
+
+        // without an authority the differences are only reported
+        nexus.start_scrub(None).await.unwrap();
+        assert_eq!(wait_for_scrub(name), ScrubState::Completed);
+        let progress = nexus.get_scrub_progress().unwrap();
+        assert_eq!(progress.progress, 100);
+        assert_eq!(progress.blocks_scrubbed, progress.blocks_total);
+        assert_eq!(progress.mismatches.len(), 1);
+        assert_eq!(progress.mismatches[0].uris, vec![get_dev(name, 1)]);
+        assert!(progress.mismatches[0].offset <= 1024);
+        assert!(!progress.mismatches[0].repaired);
+
+        nexus.start_scrub(Some(&get_dev(name, 0))).await.unwrap();
+        assert_eq!(wait_for_scrub(name), ScrubState::Completed);
+        let progress = nexus.get_scrub_progress().unwrap();
+        assert_eq!(progress.authority, get_dev(name, 0));
+        assert_eq!(progress.mismatches.len(), 1);
+        assert!(progress.mismatches[0].repaired);
+
+        // the children are the same again
+        nexus.start_scrub(None).await.unwrap();
+        assert_eq!(wait_for_scrub(name), ScrubState::Completed);
+        assert!(nexus.get_scrub_progress().unwrap().mismatches.is_empty());
+
+        let (s, r) = unbounded::<String>();
+        let size = nexus.size();
+        std::thread::spawn(move || {
+            s.send(common::compare_devices(
+                &get_disk(name, 0),
+                &get_disk(name, 1),
+                size,
+                true,
+            ))
+        });
+        reactor_poll!(r);
+
+        nexus.destroy().await.unwrap();
+        assert!(ScrubJob::lookup(name).is_err());
+    });
+
+    test_fini(name);
+}
+
+#[test]
+fn scrub_operations() {
+    let name = "scrub_operations";
+    test_ini(name);
+
+    Reactor::block_on(async move {
+        let nexus = create(name, 3).await;
+
+        nexus
+            .start_scrub(Some(&get_dev("unknown", 0)))
+            .await
+            .expect_err("authority is not a child of the nexus");
+        nexus
+            .pause_scrub()
+            .await
+            .expect_err("no scrub has been started");
+
+        // the job only gets going on the next poll of the reactor
+        nexus.start_scrub(None).await.unwrap();
+        nexus
+            .start_scrub(None)
+            .await
+            .expect_err("scrub already in progress");
+        nexus.pause_scrub().await.unwrap();
+        reactor_poll!(10);
+        assert_eq!(ScrubJob::lookup(name).unwrap().state(), ScrubState::Paused);
+        assert_eq!(nexus.get_scrub_progress().unwrap().blocks_scrubbed, 0);
+
+        nexus.resume_scrub().await.unwrap();
+        nexus.stop_scrub().await.unwrap();
+        assert_eq!(wait_for_scrub(name), ScrubState::Stopped);
+        nexus
+            .resume_scrub()
+            .await
+            .expect_err("scrub has been stopped");
+
+        // removing one of the scrubbed children stops the scrub
+        nexus.start_scrub(None).await.unwrap();
+        nexus.remove_child(&get_dev(name, 2)).await.unwrap();
+        assert_eq!(
+            ScrubJob::lookup(name).unwrap().state(),
+            ScrubState::Stopped
+        );
+
+        nexus.destroy().await.unwrap();
+    });
+
+    test_fini(name);
+}
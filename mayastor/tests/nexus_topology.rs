@@ -0,0 +1,124 @@
+use std::time::Duration;
+
+use mayastor::{
+    bdev::{
+        nexus_create,
+        nexus_lookup,
+        ChildStatus,
+        NexusShareProtocol,
+        NexusTopology,
+    },
+    core::{MayastorCliArgs, MayastorEnvironment, Reactor},
+};
+use rpc::mayastor::ShareProtocolNexus;
+
+pub mod common;
+
+static NEXUS_NAME: &str = "topology_nexus";
+static NEXUS_UUID: &str = "d8a4ad8a-2a40-4a4b-9c4e-0b8d6d3b3d5f";
+static NEXUS_SIZE: u64 = 5 * 1024 * 1024; // 5MiB
+
+// approximate on-disk metadata that will be written to the child by the nexus
+const META_SIZE: u64 = 5 * 1024 * 1024; // 5MiB
+
+fn get_disk(number: u64) -> String {
+    format!("/tmp/{}-disk{}.img", NEXUS_NAME, number)
+}
+fn get_dev(number: u64) -> String {
+    format!("aio://{}?blk_size=512", get_disk(number))
+}
+
+async fn create() {
+    let ch = vec![get_dev(0), get_dev(1)];
+    nexus_create(NEXUS_NAME, NEXUS_SIZE, Some(NEXUS_UUID), &ch)
+        .await
+        .unwrap();
+}
+
+// the topology as found on the MayaMeta partition of child `number`
+async fn read_topology(number: u64) -> NexusTopology {
+    let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+    nexus.children[number as usize]
+        .read_topology()
+        .await
+        .unwrap()
+        .unwrap()
+}
+
+#[test]
+fn nexus_topology() {
+    test_init!();
+    for i in 0 .. 2 {
+        common::delete_file(&[get_disk(i)]);
+        common::truncate_file_bytes(&get_disk(i), NEXUS_SIZE + META_SIZE);
+    }
+
+    Reactor::block_on(async {
+        create().await;
+        let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+
+        let topology = read_topology(0).await;
+        assert_eq!(Some(&topology), nexus.topology());
+        assert_eq!(topology, read_topology(1).await);
+        assert_eq!(topology.uuid, NEXUS_UUID);
+        assert_eq!(topology.size, NEXUS_SIZE);
+        assert_eq!(topology.generation, 0);
+        assert_eq!(topology.share, NexusShareProtocol::None);
+        assert_eq!(topology.children.len(), 2);
+        assert!(topology
+            .children
+            .iter()
+            .all(|c| c.status == ChildStatus::Online && c.generation == 0));
+        let created = topology.created;
+
+        // only the healthy child learns about the offline one
+        nexus.offline_child(&get_dev(1)).await.unwrap();
+        let topology = read_topology(0).await;
+        assert_eq!(topology.generation, 1);
+        assert_eq!(topology.children[0].generation, 0);
+        assert_eq!(topology.children[1].status, ChildStatus::Degraded);
+        assert_eq!(topology.children[1].generation, 1);
+
+        // the child is brought up to date once it has been rebuilt
+        nexus.online_child(&get_dev(1)).await.unwrap();
+        let start = std::time::Instant::now();
+        while nexus.topology().unwrap().children[1].status
+            != ChildStatus::Online
+        {
+            assert!(start.elapsed() < Duration::from_secs(10));
+            reactor_poll!(1);
+        }
+        let topology = read_topology(1).await;
+        assert_eq!(topology, read_topology(0).await);
+        assert!(topology.generation >= 2);
+
+        nexus
+            .share(ShareProtocolNexus::NexusNbd, None)
+            .await
+            .unwrap();
+        let shared = read_topology(0).await;
+        assert_eq!(shared.share, NexusShareProtocol::Nbd);
+        assert_eq!(shared.generation, topology.generation + 1);
+        nexus.destroy().await.unwrap();
+
+        // the topology, including the protocol the nexus was shared with,
+        // carries on when the nexus is created again
+        create().await;
+        let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+        let topology = nexus.topology().unwrap();
+        assert_eq!(topology.created, created);
+        assert_eq!(topology.share, NexusShareProtocol::Nbd);
+        assert_eq!(topology.generation, shared.generation);
+
+        // only unsharing the nexus explicitly records it is no longer shared
+        nexus.unshare().await.unwrap();
+        let unshared = read_topology(0).await;
+        assert_eq!(unshared.share, NexusShareProtocol::None);
+        assert_eq!(unshared.generation, shared.generation + 1);
+        nexus.destroy().await.unwrap();
+    });
+
+    for i in 0 .. 2 {
+        common::delete_file(&[get_disk(i)]);
+    }
+}
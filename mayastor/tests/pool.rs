@@ -3,6 +3,7 @@ use std::panic::catch_unwind;
 use mayastor::{
     core::{
         mayastor_env_stop,
+        Bdev,
         MayastorCliArgs,
         MayastorEnvironment,
         Reactor,
@@ -10,7 +11,7 @@ use mayastor::{
     },
     pool::{create_pool, Pool, PoolsIter},
 };
-use rpc::mayastor::CreatePoolRequest;
+use rpc::mayastor::{CreatePoolRequest, PoolLayout, PoolState};
 
 pub mod common;
 
@@ -34,6 +35,7 @@ fn create_pool_legacy() {
                         disks: vec![DISKNAME1.to_string()],
                         block_size: 0,
                         io_if: 0,
+                        layout: 0,
                     })
                     .await
                     .unwrap();
@@ -41,14 +43,16 @@ fn create_pool_legacy() {
 
                 // create a pool using uri's
                 Reactor::block_on(async {
-                    create_pool(CreatePoolRequest {
+                    let pool = create_pool(CreatePoolRequest {
                         name: "uri".into(),
                         disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        layout: 0,
                     })
                     .await
                     .unwrap();
+                    assert_eq!(pool.layout, PoolLayout::PoolSingle as i32);
                 });
 
                 // should succeed to create the same pool with the same name and
@@ -60,6 +64,7 @@ fn create_pool_legacy() {
                         disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        layout: 0,
                     })
                     .await;
 
@@ -74,6 +79,7 @@ fn create_pool_legacy() {
                         disks: vec!["malloc:///malloc1?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        layout: 0,
                     })
                     .await;
                     assert_eq!(pool.is_err(), true)
@@ -113,6 +119,7 @@ fn create_pool_legacy() {
                         disks: vec!["malloc:///malloc0?size_mb=64".to_string()],
                         block_size: 0,
                         io_if: 0,
+                        layout: 0,
                     })
                     .await
                     .unwrap();
@@ -122,6 +129,7 @@ fn create_pool_legacy() {
                         disks: vec![DISKNAME1.to_string()],
                         block_size: 0,
                         io_if: 0,
+                        layout: 0,
                     })
                     .await
                     .unwrap();
@@ -143,3 +151,136 @@ fn create_pool_legacy() {
     common::delete_file(&[DISKNAME1.into()]);
     result.unwrap();
 }
+
+#[test]
+fn create_pool_multi_disk() {
+    common::mayastor_test_init();
+    let rc = MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            // stripe the disks
+            Reactor::block_on(async {
+                let pool = create_pool(CreatePoolRequest {
+                    name: "stripe".into(),
+                    disks: vec![
+                        "malloc:///malloc0?size_mb=64".to_string(),
+                        "malloc:///malloc1?size_mb=64".to_string(),
+                    ],
+                    block_size: 0,
+                    io_if: 0,
+                    layout: PoolLayout::PoolStripe as i32,
+                })
+                .await
+                .unwrap();
+                assert_eq!(pool.layout, PoolLayout::PoolStripe as i32);
+                assert_eq!(pool.state, PoolState::PoolOnline as i32);
+                assert_eq!(pool.disks.len(), 2);
+                // more than a single disk could hold
+                assert!(pool.capacity > 64 * 1024 * 1024);
+            });
+
+            // disks of different sizes cannot be striped, and the disks
+            // created for the pool are destroyed again
+            Reactor::block_on(async {
+                create_pool(CreatePoolRequest {
+                    name: "unequal".into(),
+                    disks: vec![
+                        "malloc:///malloc6?size_mb=64".to_string(),
+                        "malloc:///malloc7?size_mb=32".to_string(),
+                    ],
+                    block_size: 0,
+                    io_if: 0,
+                    layout: PoolLayout::PoolStripe as i32,
+                })
+                .await
+                .expect_err("Disks of a stripe should be of the same size");
+                assert!(Pool::lookup("unequal").is_none());
+                assert!(Bdev::lookup_by_name("malloc6").is_none());
+                assert!(Bdev::lookup_by_name("malloc7").is_none());
+            });
+
+            // mirror the disks
+            Reactor::block_on(async {
+                let pool = create_pool(CreatePoolRequest {
+                    name: "mirror".into(),
+                    disks: vec![
+                        "malloc:///malloc2?size_mb=64".to_string(),
+                        "malloc:///malloc3?size_mb=64".to_string(),
+                    ],
+                    block_size: 0,
+                    io_if: 0,
+                    layout: PoolLayout::PoolMirror as i32,
+                })
+                .await
+                .unwrap();
+                assert_eq!(pool.layout, PoolLayout::PoolMirror as i32);
+                assert_eq!(pool.state, PoolState::PoolOnline as i32);
+                assert_eq!(pool.disk_states.len(), 2);
+                assert!(pool
+                    .disk_states
+                    .iter()
+                    .all(|d| d.state == PoolState::PoolOnline as i32));
+                // no more than a single disk can hold
+                assert!(pool.capacity < 64 * 1024 * 1024);
+            });
+
+            // idempotent as long as the disks are the same
+            Reactor::block_on(async {
+                let pool = create_pool(CreatePoolRequest {
+                    name: "mirror".into(),
+                    disks: vec![
+                        "malloc:///malloc2?size_mb=64".to_string(),
+                        "malloc:///malloc3?size_mb=64".to_string(),
+                    ],
+                    block_size: 0,
+                    io_if: 0,
+                    layout: PoolLayout::PoolMirror as i32,
+                })
+                .await;
+                assert_eq!(pool.is_ok(), true);
+
+                let pool = create_pool(CreatePoolRequest {
+                    name: "mirror".into(),
+                    disks: vec!["malloc:///malloc2?size_mb=64".to_string()],
+                    block_size: 0,
+                    io_if: 0,
+                    layout: PoolLayout::PoolMirror as i32,
+                })
+                .await;
+                assert_eq!(pool.is_err(), true);
+            });
+
+            // a disk added to a mirror is another mirror of the pool
+            Reactor::block_on(async {
+                let pool = Pool::lookup("mirror").unwrap();
+                let capacity = pool.get_capacity();
+                pool.add_mirror("malloc:///malloc4?size_mb=64")
+                    .await
+                    .unwrap();
+                assert_eq!(pool.get_disks().len(), 3);
+                assert_eq!(pool.get_capacity(), capacity);
+
+                // while a stripe cannot take any
+                Pool::lookup("stripe")
+                    .unwrap()
+                    .add_mirror("malloc:///malloc5?size_mb=64")
+                    .await
+                    .expect_err("Striped pool has no mirrors");
+            });
+
+            // destroying the pools destroys all of their disks
+            Reactor::block_on(async {
+                for p in PoolsIter::new() {
+                    p.destroy().await.unwrap();
+                }
+                for disk in
+                    &["malloc0", "malloc1", "malloc2", "malloc3", "malloc4"]
+                {
+                    assert!(Bdev::lookup_by_name(disk).is_none());
+                }
+            });
+
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+    assert_eq!(rc, 0);
+}
@@ -0,0 +1,78 @@
+use mayastor::{
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    pool::{create_pool, Pool},
+    replica::Replica,
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static POOL_NAME: &str = "resize_pool";
+
+static UUID1: &str = "00000000-76b6-4fcf-864d-1027d4038756";
+static UUID2: &str = "11111111-76b6-4fcf-864d-1027d4038756";
+
+const MB: u64 = 1024 * 1024;
+
+#[test]
+fn replica_resize() {
+    common::mayastor_test_init();
+    let rc = MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                create_pool(CreatePoolRequest {
+                    name: POOL_NAME.into(),
+                    disks: vec!["malloc:///malloc0?size_mb=64".into()],
+                    block_size: 0,
+                    io_if: 0,
+                    layout: 0,
+                })
+                .await
+                .unwrap();
+
+                Replica::create(UUID1, POOL_NAME, 16 * MB, false)
+                    .await
+                    .unwrap();
+                Replica::create(UUID2, POOL_NAME, 16 * MB, true)
+                    .await
+                    .unwrap();
+            });
+
+            // a thick replica is limited by the free space of the pool
+            Reactor::block_on(async {
+                let replica = Replica::lookup(UUID1).unwrap();
+                replica.resize(32 * MB).await.unwrap();
+                assert_eq!(replica.get_size(), 32 * MB);
+
+                replica
+                    .resize(16 * MB)
+                    .await
+                    .expect_err("Replica should not shrink");
+
+                let free = Pool::lookup(POOL_NAME).unwrap().get_free();
+                replica
+                    .resize(replica.get_size() + free + 4 * MB)
+                    .await
+                    .expect_err("Replica should not outgrow the pool");
+                assert_eq!(replica.get_size(), 32 * MB);
+            });
+
+            // whereas a thin one is not
+            Reactor::block_on(async {
+                let replica = Replica::lookup(UUID2).unwrap();
+                replica.resize(128 * MB).await.unwrap();
+                assert_eq!(replica.get_size(), 128 * MB);
+            });
+
+            Reactor::block_on(async {
+                for uuid in &[UUID1, UUID2] {
+                    Replica::lookup(uuid).unwrap().destroy().await.unwrap();
+                }
+                Pool::lookup(POOL_NAME).unwrap().destroy().await.unwrap();
+            });
+
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+    assert_eq!(rc, 0);
+}
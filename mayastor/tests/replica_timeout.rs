@@ -4,7 +4,7 @@ use std::{thread, time};
 
 use common::ms_exec::MayastorProcess;
 use mayastor::{
-    bdev::{nexus_create, nexus_lookup},
+    bdev::{nexus_create, nexus_lookup, NexusStatus},
     core::{
         mayastor_env_stop,
         BdevHandle,
@@ -32,6 +32,8 @@ static UUID1: &str = "00000000-76b6-4fcf-864d-1027d4038756";
 static CFGNAME2: &str = "/tmp/child2.yaml";
 static UUID2: &str = "11111111-76b6-4fcf-864d-1027d4038756";
 
+static CFGNAME_NEXUS: &str = "/tmp/nexus_child_timeout.yaml";
+
 static NXNAME: &str = "replica_timeout_test";
 
 fn generate_config() {
@@ -145,6 +147,54 @@ fn replica_term() {
     common::delete_file(&[DISKNAME1.to_string(), DISKNAME2.to_string()]);
 }
 
+#[test]
+#[ignore]
+fn replica_stop_child_timeout() {
+    generate_config();
+
+    let mut config = Config::default();
+    config.nexus_opts.child_timeout_sec = 1;
+    config.write(CFGNAME_NEXUS).unwrap();
+
+    common::truncate_file(DISKNAME1, DISKSIZE_KB);
+    common::truncate_file(DISKNAME2, DISKSIZE_KB);
+
+    let mut ms1 = start_mayastor(CFGNAME1);
+    let mut ms2 = start_mayastor(CFGNAME2);
+    // Allow Mayastor processes to start listening on NVMf port
+    thread::sleep(time::Duration::from_millis(250));
+
+    test_init!(CFGNAME_NEXUS);
+
+    Reactor::block_on(async {
+        create_nexus(false).await;
+        write_some().await;
+        read_some().await.unwrap();
+        ms1.sig_stop();
+        // the IOs to the stopped child time out, the child is faulted and
+        // the IOs complete from the remaining child
+        write_some().await;
+        read_some()
+            .await
+            .expect("should read with 1 Nexus child stopped");
+        assert_eq!(
+            nexus_lookup(NXNAME).unwrap().status(),
+            NexusStatus::Degraded
+        );
+        ms1.sig_cont();
+        nexus_lookup(NXNAME).unwrap().destroy().await.unwrap();
+    });
+    ms1.sig_term();
+    ms2.sig_term();
+    mayastor_env_stop(0);
+
+    common::delete_file(&[
+        DISKNAME1.to_string(),
+        DISKNAME2.to_string(),
+        CFGNAME_NEXUS.to_string(),
+    ]);
+}
+
 async fn create_nexus(single: bool) {
     let mut ch = vec![
         "nvmf://127.0.0.1:8430/nqn.2019-05.io.openebs:".to_string()
@@ -0,0 +1,156 @@
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup},
+    core::{mayastor_env_stop, MayastorCliArgs, MayastorEnvironment, Reactor},
+    pool::{create_pool, PoolsIter},
+    replica::{Replica, ReplicaIter},
+    snapshot::{Snapshot, SnapshotIter},
+};
+use rpc::mayastor::CreatePoolRequest;
+
+pub mod common;
+
+static NEXUS_NAME: &str = "snapshot_nexus";
+
+static UUID1: &str = "00000000-76b6-4fcf-864d-1027d4038756";
+static UUID2: &str = "11111111-76b6-4fcf-864d-1027d4038756";
+static CLONE1: &str = "22222222-76b6-4fcf-864d-1027d4038756";
+static CLONE2: &str = "33333333-76b6-4fcf-864d-1027d4038756";
+
+#[test]
+fn snapshot() {
+    common::mayastor_test_init();
+    let rc = MayastorEnvironment::new(MayastorCliArgs::default())
+        .start(|| {
+            Reactor::block_on(async {
+                for (pool, disk) in &[
+                    ("pool1", "malloc:///malloc0?size_mb=128"),
+                    ("pool2", "malloc:///malloc1?size_mb=128"),
+                ] {
+                    create_pool(CreatePoolRequest {
+                        name: pool.to_string(),
+                        disks: vec![disk.to_string()],
+                        block_size: 0,
+                        io_if: 0,
+                        layout: 0,
+                    })
+                    .await
+                    .unwrap();
+                }
+
+                Replica::create(UUID1, "pool1", 64 * 1024 * 1024, false)
+                    .await
+                    .unwrap();
+                Replica::create(UUID2, "pool2", 64 * 1024 * 1024, false)
+                    .await
+                    .unwrap();
+            });
+
+            // snapshot of a single replica
+            Reactor::block_on(async {
+                let replica = Replica::lookup(UUID1).unwrap();
+                let snapshot = Snapshot::create(&replica, 1).await.unwrap();
+                assert_eq!(snapshot.get_name(), format!("{}@1", UUID1));
+                assert_eq!(snapshot.get_parent(), UUID1);
+                assert_eq!(snapshot.get_created(), 1);
+                assert_eq!(snapshot.get_pool_name(), "pool1");
+                assert_eq!(snapshot.get_size(), replica.get_size());
+
+                // snapshots taken within the same second get distinct names
+                let snapshot = Snapshot::create(&replica, 1).await.unwrap();
+                assert_eq!(snapshot.get_name(), format!("{}@1.1", UUID1));
+                assert_eq!(snapshot.get_parent(), UUID1);
+                assert_eq!(snapshot.get_created(), 1);
+
+                // snapshots are not listed as replicas
+                assert_eq!(SnapshotIter::new().count(), 2);
+                assert_eq!(ReplicaIter::new().count(), 2);
+            });
+
+            // snapshot of all children of a nexus
+            Reactor::block_on(async {
+                let children = vec![
+                    format!("bdev:///{}", UUID1),
+                    format!("bdev:///{}", UUID2),
+                ];
+                nexus_create(NEXUS_NAME, 32 * 1024 * 1024, None, &children)
+                    .await
+                    .expect("Failed to create nexus");
+
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                let snapshot = nexus.create_snapshot().await.unwrap();
+                assert!(snapshot.remote.is_empty());
+                let snapshots = snapshot.snapshots;
+                assert_eq!(snapshots.len(), 2);
+                assert_eq!(snapshots[0].get_parent(), UUID1);
+                assert_eq!(snapshots[1].get_parent(), UUID2);
+                assert_eq!(
+                    snapshots[0].get_created(),
+                    snapshots[1].get_created()
+                );
+                assert_eq!(SnapshotIter::new().count(), 4);
+
+                nexus.destroy().await.unwrap();
+            });
+
+            // children which are not local replicas are reported back
+            Reactor::block_on(async {
+                let children = vec![
+                    format!("bdev:///{}", UUID2),
+                    "malloc:///malloc2?size_mb=64".to_string(),
+                ];
+                nexus_create(NEXUS_NAME, 32 * 1024 * 1024, None, &children)
+                    .await
+                    .expect("Failed to create nexus");
+
+                let nexus = nexus_lookup(NEXUS_NAME).unwrap();
+                let snapshot = nexus.create_snapshot().await.unwrap();
+                assert_eq!(snapshot.snapshots.len(), 1);
+                assert_eq!(snapshot.snapshots[0].get_parent(), UUID2);
+                assert_eq!(snapshot.remote, vec![children[1].clone()]);
+                assert_eq!(SnapshotIter::new().count(), 5);
+
+                nexus.destroy().await.unwrap();
+            });
+
+            // writable clones of a snapshot
+            Reactor::block_on(async {
+                let name = format!("{}@1", UUID1);
+                let snapshot = Snapshot::lookup(&name).unwrap();
+                for uuid in &[CLONE1, CLONE2] {
+                    let clone =
+                        Replica::create_clone(uuid, &snapshot).await.unwrap();
+                    assert_eq!(clone.get_pool_name(), "pool1");
+                    assert_eq!(clone.get_size(), snapshot.get_size());
+                    assert_eq!(clone.get_origin(), Some(name.clone()));
+                }
+
+                // the uuid of a clone must be unique as well
+                assert!(Replica::create_clone(CLONE1, &snapshot)
+                    .await
+                    .is_err());
+                assert_eq!(ReplicaIter::new().count(), 4);
+            });
+
+            Reactor::block_on(async {
+                for replica in ReplicaIter::new().collect::<Vec<_>>() {
+                    replica.destroy().await.unwrap();
+                }
+
+                // newest first so that no snapshot has a clone left
+                let mut snapshots = SnapshotIter::new().collect::<Vec<_>>();
+                snapshots.sort_by_key(|s| std::cmp::Reverse(s.get_created()));
+                for snapshot in snapshots {
+                    snapshot.destroy().await.unwrap();
+                }
+                assert_eq!(SnapshotIter::new().count(), 0);
+
+                for pool in PoolsIter::new() {
+                    pool.destroy().await.unwrap();
+                }
+            });
+
+            mayastor_env_stop(0);
+        })
+        .unwrap();
+    assert_eq!(rc, 0);
+}
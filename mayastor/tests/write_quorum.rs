@@ -0,0 +1,156 @@
+extern crate log;
+
+use crossbeam::channel::unbounded;
+
+use std::time::Duration;
+pub mod common;
+
+pub use common::error_bdev::{
+    create_error_bdev,
+    inject_error,
+    SPDK_BDEV_IO_TYPE_WRITE,
+    VBDEV_IO_FAILURE,
+};
+
+use mayastor::{
+    bdev::{nexus_create, nexus_lookup, ChildStatus, NexusStatus, WritePolicy},
+    core::{
+        mayastor_env_stop,
+        Bdev,
+        MayastorCliArgs,
+        MayastorEnvironment,
+        Reactor,
+    },
+};
+use rpc::mayastor::RebuildRecord;
+
+static WRITE_QUORUM_TEST_NEXUS: &str = "write_quorum_test_nexus";
+
+static DISKNAME1: &str = "/tmp/quorum1.img";
+static BDEVNAME1: &str = "aio:///tmp/quorum1.img?blk_size=512";
+
+static DISKNAME2: &str = "/tmp/quorum2.img";
+static BDEVNAME2: &str = "aio:///tmp/quorum2.img?blk_size=512";
+
+static DISKNAME3: &str = "/tmp/quorum3.img";
+
+static ERROR_DEVICE: &str = "quorum_error_device";
+static EE_ERROR_DEVICE: &str = "EE_quorum_error_device"; // The prefix is added by the vbdev_error module
+static BDEV_EE_ERROR_DEVICE: &str = "bdev:///EE_quorum_error_device";
+
+#[test]
+fn nexus_write_quorum_test() {
+    common::truncate_file(DISKNAME1, 64 * 1024);
+    common::truncate_file(DISKNAME2, 64 * 1024);
+    common::truncate_file(DISKNAME3, 64 * 1024);
+
+    test_init!();
+
+    Reactor::block_on(async {
+        create_error_bdev(ERROR_DEVICE, DISKNAME3);
+        create_nexus().await;
+
+        // with the default policy a single failing child fails the write
+        inject_error(
+            EE_ERROR_DEVICE,
+            SPDK_BDEV_IO_TYPE_WRITE,
+            VBDEV_IO_FAILURE,
+            1,
+        );
+        write_nexus(false).await;
+        check_nexus_state_is(NexusStatus::Online);
+
+        // with the quorum policy the write succeeds on the two other
+        // children and the failing child is faulted and rebuilt
+        nexus_lookup(WRITE_QUORUM_TEST_NEXUS)
+            .unwrap()
+            .set_write_policy(WritePolicy::Quorum);
+        inject_error(
+            EE_ERROR_DEVICE,
+            SPDK_BDEV_IO_TYPE_WRITE,
+            VBDEV_IO_FAILURE,
+            1,
+        );
+        write_nexus(true).await;
+    });
+
+    // wait for the failing child to be faulted and rebuilt
+    for _ in 0 .. 100 {
+        let nexus = nexus_lookup(WRITE_QUORUM_TEST_NEXUS).unwrap();
+        if nexus.status() == NexusStatus::Online
+            && !rebuild_history().is_empty()
+        {
+            break;
+        }
+        reactor_run_millis(10);
+    }
+    check_nexus_state_is(NexusStatus::Online);
+    let history = rebuild_history();
+    assert_eq!(history.len(), 1);
+    assert_eq!(history[0].state, "completed");
+    assert_eq!(
+        nexus_lookup(WRITE_QUORUM_TEST_NEXUS).unwrap().children[2].status(),
+        ChildStatus::Online
+    );
+
+    Reactor::block_on(async {
+        write_nexus(true).await;
+        let n = nexus_lookup(WRITE_QUORUM_TEST_NEXUS).unwrap();
+        n.destroy().await.unwrap();
+    });
+
+    mayastor_env_stop(0);
+
+    common::delete_file(&[
+        DISKNAME1.to_string(),
+        DISKNAME2.to_string(),
+        DISKNAME3.to_string(),
+    ]);
+}
+
+// rebuilds of the failing child which have come to an end
+fn rebuild_history() -> Vec<RebuildRecord> {
+    nexus_lookup(WRITE_QUORUM_TEST_NEXUS)
+        .unwrap()
+        .get_rebuild_history(Some(BDEV_EE_ERROR_DEVICE))
+        .rebuilds
+}
+
+fn check_nexus_state_is(expected_status: NexusStatus) {
+    let nexus = nexus_lookup(WRITE_QUORUM_TEST_NEXUS).unwrap();
+    assert_eq!(nexus.status(), expected_status);
+}
+
+async fn create_nexus() {
+    let ch = vec![
+        BDEVNAME1.to_string(),
+        BDEVNAME2.to_string(),
+        BDEV_EE_ERROR_DEVICE.to_string(),
+    ];
+
+    nexus_create(WRITE_QUORUM_TEST_NEXUS, 32 * 1024 * 1024, None, &ch)
+        .await
+        .unwrap();
+}
+
+async fn write_nexus(succeed: bool) {
+    let bdev = Bdev::lookup_by_name(WRITE_QUORUM_TEST_NEXUS)
+        .expect("failed to lookup nexus");
+    let d = bdev
+        .open(true)
+        .expect("failed open bdev")
+        .into_handle()
+        .unwrap();
+    let buf = d.dma_malloc(512).expect("failed to allocate buffer");
+
+    assert_eq!(d.write_at(0, &buf).await.is_ok(), succeed);
+}
+
+fn reactor_run_millis(milliseconds: u64) {
+    let (s, r) = unbounded::<()>();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(milliseconds));
+        s.send(())
+    });
+    reactor_poll!(r);
+}
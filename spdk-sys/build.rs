@@ -76,6 +76,7 @@ fn main() {
         .whitelist_function("delete_malloc_disk")
         .whitelist_function("^bdev.*")
         .whitelist_function("^nbd_.*")
+        .whitelist_function("^raid_bdev.*")
         .whitelist_function("^vbdev_.*")
         .blacklist_type("^longfunc")
         .whitelist_var("^NVMF.*")
@@ -5,6 +5,7 @@
 #include <bdev/lvol/vbdev_lvol.h>
 #include <bdev/nvme/bdev_nvme.h>
 #include <bdev/malloc/bdev_malloc.h>
+#include <bdev/raid/bdev_raid.h>
 #include <bdev/uring/bdev_uring.h>
 #include <iscsi/init_grp.h>
 #include <iscsi/iscsi.h>
@@ -13,10 +14,12 @@
 #include <nbd/nbd_internal.h>
 #include <spdk/bdev.h>
 #include <spdk/bdev_module.h>
+#include <spdk/blob.h>
 #include <spdk/conf.h>
 #include <spdk/cpuset.h>
 #include <spdk/env.h>
 #include <spdk/env_dpdk.h>
+#include <spdk/histogram_data.h>
 #include <spdk/event.h>
 #include <spdk/jsonrpc.h>
 #include <spdk/log.h>